@@ -0,0 +1,47 @@
+use std::fs::{self, File};
+use std::hint::black_box;
+use std::time::{Duration, UNIX_EPOCH};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rush_tunnel::{index, list_profiles, load_profile, migrate};
+
+const PROFILES: usize = 500;
+
+// Parsing every profile one by one, as listings did before the index, against the same listing
+// answered from an up-to-date index.
+fn bench_list_profiles(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..PROFILES {
+        let path = dir.path().join(format!("profile-{:03}.toml", i));
+        let profile = format!(
+            "schema_version = {}\njump_host_user = \"jumper\"\njump_host = \"bastion-{i}.example.com\"\n\
+             jump_port = 22\ntarget_host_user = \"deploy\"\ntarget_host = \"10.0.{}.{}\"\ntarget_port = 22\n\
+             port_forward = {}\ntags = [\"bench\", \"group-{}\"]\ndescription = \"Benchmark profile {i}\"\n",
+            migrate::CURRENT_SCHEMA_VERSION,
+            i / 250,
+            i % 250,
+            8000 + i,
+            i % 10,
+        );
+        fs::write(&path, profile).unwrap();
+        // Old enough to be indexed.
+        let stamp = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        File::options().write(true).open(&path).unwrap().set_modified(stamp).unwrap();
+    }
+    rush_tunnel::set_profiles_dir(dir.path().to_path_buf());
+    let names = list_profiles().unwrap().unwrap();
+    assert_eq!(names.len(), PROFILES);
+
+    c.bench_function("parse every profile", |b| {
+        b.iter(|| {
+            for name in &names {
+                black_box(load_profile(name).unwrap());
+            }
+        })
+    });
+    index::load_profiles(&names).unwrap();
+    c.bench_function("load from the index", |b| b.iter(|| black_box(index::load_profiles(&names).unwrap())));
+}
+
+criterion_group!(benches, bench_list_profiles);
+criterion_main!(benches);
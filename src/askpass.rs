@@ -0,0 +1,200 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, ExitCode};
+
+use anyhow::{Context, Result};
+use tracing::debug;
+use zeroize::Zeroizing;
+
+use crate::paths::expand_path;
+use crate::{style, SshConfig};
+
+// ssh runs `SSH_ASKPASS` for every password prompt, including the one spawned for the jump host.
+// Pointing it back at this binary lets a single invocation answer both hosts with the right
+// password instead of typing them in.
+pub const HELPER_ENV: &str = "RUSH_TUNNEL_ASKPASS";
+const JUMP_LOGIN_ENV: &str = "RUSH_TUNNEL_ASKPASS_JUMP_LOGIN";
+const JUMP_PASSWORD_ENV: &str = "RUSH_TUNNEL_ASKPASS_JUMP_PASSWORD";
+const TARGET_LOGIN_ENV: &str = "RUSH_TUNNEL_ASKPASS_TARGET_LOGIN";
+const TARGET_PASSWORD_ENV: &str = "RUSH_TUNNEL_ASKPASS_TARGET_PASSWORD";
+const KEY_FILE_ENV: &str = "RUSH_TUNNEL_ASKPASS_KEY_FILE";
+const KEY_PASSPHRASE_ENV: &str = "RUSH_TUNNEL_ASKPASS_KEY_PASSPHRASE";
+
+pub struct Credentials {
+    pub jump_password: String,
+    pub target_password: String,
+    // Overwritten in memory when dropped; ssh's copy lives in its environment like the passwords.
+    pub key_passphrase: Option<Zeroizing<String>>,
+}
+
+impl Credentials {
+    // With only a key passphrase there are no password prompts to answer.
+    pub fn has_passwords(&self) -> bool {
+        !self.jump_password.is_empty() || !self.target_password.is_empty()
+    }
+}
+
+pub fn configure(command: &mut Command, config: &SshConfig, credentials: &Credentials) -> Result<()> {
+    let helper = env::current_exe().context("Failed to locate the rush-tunnel executable")?;
+    command
+        .env("SSH_ASKPASS", &helper)
+        .env("SSH_ASKPASS_REQUIRE", "force")
+        .env(HELPER_ENV, "1")
+        .env(JUMP_LOGIN_ENV, login(&config.jump_host_user, &config.jump_host))
+        .env(JUMP_PASSWORD_ENV, &credentials.jump_password)
+        .env(TARGET_LOGIN_ENV, login(&config.target_host_user, &config.target_host))
+        .env(TARGET_PASSWORD_ENV, &credentials.target_password);
+    if let (Some(passphrase), Some(key_file)) = (&credentials.key_passphrase, &config.options.identity_file) {
+        command.env(KEY_FILE_ENV, key_file).env(KEY_PASSPHRASE_ENV, passphrase.as_str());
+    }
+    debug!(helper = %helper.display(), "answering ssh password prompts through askpass");
+    Ok(())
+}
+
+// Files rendered by secret managers usually end with a newline, which is not part of the password.
+pub fn read_password_file(path: &str) -> Result<String> {
+    let path = expand_path(path)?;
+    let contents =
+        fs::read_to_string(&path).context(format!("Failed to read password file {}", path.display()))?;
+    if let Some(mode) = loose_permissions(&path) {
+        eprintln!(
+            "{}",
+            style::warning(&format!(
+                "Warning: password file {} is readable by other users (mode {:o}); run chmod 600 on it",
+                path.display(),
+                mode
+            ))
+        );
+    }
+    debug!(path = %path.display(), "read password file");
+    Ok(contents.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(unix)]
+fn loose_permissions(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path).ok()?.permissions().mode() & 0o777;
+    (mode & 0o077 != 0).then_some(mode)
+}
+
+#[cfg(not(unix))]
+fn loose_permissions(_path: &Path) -> Option<u32> {
+    None
+}
+
+pub fn is_helper_invocation() -> bool {
+    env::var_os(HELPER_ENV).is_some()
+}
+
+pub fn run_helper() -> ExitCode {
+    let prompt = env::args().nth(1).unwrap_or_default();
+    let var = |name| env::var(name).unwrap_or_default();
+    let logins = [
+        (var(JUMP_LOGIN_ENV), var(JUMP_PASSWORD_ENV)),
+        (var(TARGET_LOGIN_ENV), var(TARGET_PASSWORD_ENV)),
+    ];
+
+    let key = (var(KEY_FILE_ENV), var(KEY_PASSPHRASE_ENV));
+
+    // Anything other than a password or key passphrase prompt (such as an unknown host key) is declined.
+    match select_password(&prompt, &logins).or_else(|| select_passphrase(&prompt, &key)) {
+        Some(password) => {
+            println!("{}", password);
+            ExitCode::SUCCESS
+        }
+        None => ExitCode::FAILURE,
+    }
+}
+
+fn login(user: &str, host: &str) -> String {
+    format!("{}@{}", user, host)
+}
+
+// An empty password is one that wasn't saved, so ssh is told no rather than sent an empty answer.
+fn select_password<'a>(prompt: &str, logins: &'a [(String, String)]) -> Option<&'a str> {
+    logins
+        .iter()
+        .find(|(login, _)| prompt.contains(&format!("{}'s password", login)))
+        .map(|(_, password)| password.as_str())
+        .filter(|password| !password.is_empty())
+}
+
+// ssh asks "Enter passphrase for key '<path>': ", so only the profile's own key gets the passphrase.
+fn select_passphrase<'a>(prompt: &str, (key_file, passphrase): &'a (String, String)) -> Option<&'a str> {
+    let asks_for_key = !key_file.is_empty() && prompt.starts_with(&format!("Enter passphrase for key '{}'", key_file));
+    (asks_for_key && !passphrase.is_empty()).then_some(passphrase.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logins() -> Vec<(String, String)> {
+        vec![
+            ("jumper@bastion".to_string(), "jump-secret".to_string()),
+            ("deploy@10.0.0.5".to_string(), "target-secret".to_string()),
+        ]
+    }
+
+    #[test]
+    fn test_select_password_by_prompt() {
+        let logins = logins();
+
+        assert_eq!(select_password("jumper@bastion's password: ", &logins), Some("jump-secret"));
+        assert_eq!(select_password("deploy@10.0.0.5's password: ", &logins), Some("target-secret"));
+    }
+
+    #[test]
+    fn test_read_password_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("jump.pass");
+        fs::write(&path, "s3cret with spaces \n").unwrap();
+
+        assert_eq!(read_password_file(&path.to_string_lossy()).unwrap(), "s3cret with spaces ");
+        assert!(read_password_file(&dir.path().join("missing").to_string_lossy()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_loose_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("jump.pass");
+        fs::write(&path, "secret").unwrap();
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(loose_permissions(&path), None);
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(loose_permissions(&path), Some(0o644));
+    }
+
+    #[test]
+    fn test_select_password_declines_other_prompts() {
+        let logins = logins();
+
+        assert_eq!(
+            select_password("Are you sure you want to continue connecting (yes/no/[fingerprint])?", &logins),
+            None
+        );
+        assert_eq!(select_password("other@bastion's password: ", &logins), None);
+        let unsaved = vec![("deploy@10.0.0.5".to_string(), String::new())];
+        assert_eq!(select_password("deploy@10.0.0.5's password: ", &unsaved), None);
+    }
+
+    #[test]
+    fn test_select_passphrase_for_the_profile_key() {
+        let key = ("/home/me/.ssh/deploy_ed25519".to_string(), "key-secret".to_string());
+
+        assert_eq!(
+            select_passphrase("Enter passphrase for key '/home/me/.ssh/deploy_ed25519': ", &key),
+            Some("key-secret")
+        );
+        assert_eq!(select_passphrase("Enter passphrase for key '/home/me/.ssh/id_rsa': ", &key), None);
+        assert_eq!(select_passphrase("deploy@10.0.0.5's password: ", &key), None);
+        let no_key = (String::new(), String::new());
+        assert_eq!(select_passphrase("Enter passphrase for key '': ", &no_key), None);
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path};
+
+use anyhow::{bail, Context, Result};
+
+use crate::crypto::{decrypt_bytes, encrypt_bytes_with, Cipher};
+use crate::fsutil::{create_private_dir, write_private};
+
+pub fn create_archive(profiles_dir: &Path) -> Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(Vec::new());
+
+    for entry in fs::read_dir(profiles_dir).context("Failed to read profiles directory")? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+        let file_name = path.file_name().context("Failed to get file name")?;
+        builder
+            .append_path_with_name(&path, file_name)
+            .context(format!("Failed to add '{}' to backup", path.display()))?;
+    }
+
+    builder.into_inner().context("Failed to finish backup archive")
+}
+
+pub fn restore_archive<F>(data: &[u8], profiles_dir: &Path, mut confirm_overwrite: F) -> Result<Vec<String>>
+where
+    F: FnMut(&str) -> Result<bool>,
+{
+    create_private_dir(profiles_dir).context("Failed to create profiles directory")?;
+
+    let mut archive = tar::Archive::new(data);
+    let mut restored = Vec::new();
+
+    for entry in archive.entries().context("Failed to read backup archive")? {
+        let mut entry = entry.context("Failed to read backup entry")?;
+        let entry_path = entry.path()?.into_owned();
+
+        let mut components = entry_path.components();
+        let file_name = match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) => name.to_string_lossy().to_string(),
+            _ => bail!("Backup contains an unexpected path: {}", entry_path.display()),
+        };
+
+        let target_path = profiles_dir.join(&file_name);
+        if target_path.exists() && !confirm_overwrite(&file_name)? {
+            continue;
+        }
+
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .context(format!("Failed to read '{}' from backup", file_name))?;
+        write_private(&target_path, &contents).context(format!("Failed to restore '{}'", file_name))?;
+        restored.push(file_name);
+    }
+
+    Ok(restored)
+}
+
+pub fn encrypt_archive(passphrase: &str, archive: &[u8], cipher: Cipher) -> Result<Vec<u8>> {
+    let encrypted = encrypt_bytes_with(passphrase, archive, cipher).context("Failed to encrypt backup")?;
+    Ok(encrypted.into_bytes())
+}
+
+pub fn decrypt_archive(passphrase: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let encrypted = std::str::from_utf8(data).context("Backup is not a valid encrypted archive")?;
+    decrypt_bytes(passphrase, encrypted).context("Failed to decrypt backup (wrong passphrase?)")
+}
+
+// Encrypted backups are hex text, while a plain tar archive always contains NUL padding.
+pub fn is_encrypted_archive(data: &[u8]) -> bool {
+    !data.is_empty() && data.iter().all(|b| b.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_round_trip() {
+        let source = tempfile::tempdir().unwrap();
+        fs::write(source.path().join("prod.toml"), "jump_host = \"a\"").unwrap();
+        fs::write(source.path().join("staging.toml"), "jump_host = \"b\"").unwrap();
+
+        let archive = create_archive(source.path()).unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let mut restored = restore_archive(&archive, target.path(), |_| Ok(true)).unwrap();
+        restored.sort();
+
+        assert_eq!(restored, vec!["prod.toml", "staging.toml"]);
+        assert_eq!(
+            fs::read_to_string(target.path().join("prod.toml")).unwrap(),
+            "jump_host = \"a\""
+        );
+    }
+
+    #[test]
+    fn test_restore_keeps_existing_file_when_declined() {
+        let source = tempfile::tempdir().unwrap();
+        fs::write(source.path().join("prod.toml"), "new").unwrap();
+        let archive = create_archive(source.path()).unwrap();
+
+        let target = tempfile::tempdir().unwrap();
+        fs::write(target.path().join("prod.toml"), "old").unwrap();
+        let restored = restore_archive(&archive, target.path(), |_| Ok(false)).unwrap();
+
+        assert!(restored.is_empty());
+        assert_eq!(fs::read_to_string(target.path().join("prod.toml")).unwrap(), "old");
+    }
+
+    #[test]
+    fn test_encrypted_archive_round_trip() {
+        let archive = b"plain archive bytes\0\0".to_vec();
+        assert!(!is_encrypted_archive(&archive));
+
+        let encrypted = encrypt_archive("Backup-Passphrase-1", &archive, Cipher::Aes256Gcm).unwrap();
+        assert!(is_encrypted_archive(&encrypted));
+        assert_eq!(decrypt_archive("Backup-Passphrase-1", &encrypted).unwrap(), archive);
+        assert!(decrypt_archive("wrong", &encrypted).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::Result;
+
+use crate::config::SshOptions;
+use crate::forward::LocalForward;
+use crate::{join_problems, normalize_required, ConfigError, SshConfig, DEFAULT_SSH_PORT};
+
+/// Builds an [`SshConfig`] without prompting, applying the same defaults as the CLI.
+///
+/// ```
+/// use rush_tunnel::SshConfig;
+///
+/// let config = SshConfig::builder()
+///     .jump_host_user("jumper")
+///     .jump_host("bastion.example.com")
+///     .target_host_user("deploy")
+///     .target_host("10.0.0.5")
+///     .port_forward(5432)
+///     .build()?;
+///
+/// assert_eq!(config.jump_port, 22);
+/// assert_eq!(rush_tunnel::build_ssh_args(&config, &Default::default())[1], "jumper@bastion.example.com:22");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Default)]
+pub struct SshConfigBuilder {
+    jump_host_user: Option<String>,
+    jump_host: Option<String>,
+    target_host_user: Option<String>,
+    target_host: Option<String>,
+    jump_port: Option<u16>,
+    target_port: Option<u16>,
+    proxy_command: Option<String>,
+    pre_proxy: Option<String>,
+    port_forward: Option<u16>,
+    bind_address: Option<String>,
+    forward_remote_host: Option<String>,
+    forward_remote_port: Option<u16>,
+    forwards: Vec<LocalForward>,
+    tags: Vec<String>,
+    description: Option<String>,
+    agent_forwarding: bool,
+    multiplex: bool,
+    control_persist: Option<u32>,
+    local_command: Option<String>,
+    on_connect: Option<String>,
+    on_disconnect: Option<String>,
+    options: SshOptions,
+}
+
+impl SshConfig {
+    pub fn builder() -> SshConfigBuilder {
+        SshConfigBuilder::default()
+    }
+}
+
+impl SshConfigBuilder {
+    pub fn jump_host_user(mut self, user: impl Into<String>) -> Self {
+        self.jump_host_user = Some(user.into());
+        self
+    }
+
+    pub fn jump_host(mut self, host: impl Into<String>) -> Self {
+        self.jump_host = Some(host.into());
+        self
+    }
+
+    pub fn target_host_user(mut self, user: impl Into<String>) -> Self {
+        self.target_host_user = Some(user.into());
+        self
+    }
+
+    pub fn target_host(mut self, host: impl Into<String>) -> Self {
+        self.target_host = Some(host.into());
+        self
+    }
+
+    pub fn jump_port(mut self, port: u16) -> Self {
+        self.jump_port = Some(port);
+        self
+    }
+
+    pub fn target_port(mut self, port: u16) -> Self {
+        self.target_port = Some(port);
+        self
+    }
+
+    // Replaces the jump host, so the jump host setters are then not needed.
+    pub fn proxy_command(mut self, command: impl Into<String>) -> Self {
+        self.proxy_command = Some(command.into());
+        self
+    }
+
+    pub fn pre_proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.pre_proxy = Some(proxy.into());
+        self
+    }
+
+    pub fn port_forward(mut self, port: u16) -> Self {
+        self.port_forward = Some(port);
+        self
+    }
+
+    pub fn bind_address(mut self, address: impl Into<String>) -> Self {
+        self.bind_address = Some(address.into());
+        self
+    }
+
+    pub fn forward_remote_host(mut self, host: impl Into<String>) -> Self {
+        self.forward_remote_host = Some(host.into());
+        self
+    }
+
+    pub fn forward_remote_port(mut self, port: u16) -> Self {
+        self.forward_remote_port = Some(port);
+        self
+    }
+
+    pub fn forward(mut self, forward: LocalForward) -> Self {
+        self.forwards.push(forward);
+        self
+    }
+
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn agent_forwarding(mut self, enabled: bool) -> Self {
+        self.agent_forwarding = enabled;
+        self
+    }
+
+    pub fn multiplex(mut self, enabled: bool) -> Self {
+        self.multiplex = enabled;
+        self
+    }
+
+    pub fn control_persist(mut self, seconds: u32) -> Self {
+        self.control_persist = Some(seconds);
+        self
+    }
+
+    pub fn local_command(mut self, command: impl Into<String>) -> Self {
+        self.local_command = Some(command.into());
+        self
+    }
+
+    pub fn on_connect(mut self, command: impl Into<String>) -> Self {
+        self.on_connect = Some(command.into());
+        self
+    }
+
+    pub fn on_disconnect(mut self, command: impl Into<String>) -> Self {
+        self.on_disconnect = Some(command.into());
+        self
+    }
+
+    pub fn options(mut self, options: SshOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn build(self) -> Result<SshConfig> {
+        let proxy_command = self.proxy_command.as_deref().and_then(normalize_required);
+        let (jump_host_user, jump_host) = match proxy_command {
+            Some(_) => (
+                self.jump_host_user.unwrap_or_default(),
+                self.jump_host.as_deref().and_then(normalize_required).unwrap_or_default(),
+            ),
+            None => (
+                required(self.jump_host_user, "jump_host_user")?,
+                required(self.jump_host, "jump_host")?,
+            ),
+        };
+        let config = SshConfig {
+            jump_host_user,
+            jump_host,
+            target_host_user: required(self.target_host_user, "target_host_user")?,
+            target_host: required(self.target_host, "target_host")?,
+            jump_port: self.jump_port.unwrap_or(DEFAULT_SSH_PORT),
+            target_port: self.target_port.unwrap_or(DEFAULT_SSH_PORT),
+            proxy_command,
+            pre_proxy: self.pre_proxy.as_deref().and_then(normalize_required),
+            port_forward: self.port_forward,
+            bind_address: self.bind_address.as_deref().and_then(normalize_required),
+            forward_remote_host: self.forward_remote_host.as_deref().and_then(normalize_required),
+            forward_remote_port: self.forward_remote_port,
+            forwards: self.forwards,
+            tags: self.tags.iter().filter_map(|tag| normalize_required(tag)).collect(),
+            description: self.description.as_deref().and_then(normalize_required),
+            agent_forwarding: self.agent_forwarding,
+            multiplex: self.multiplex,
+            control_persist: self.control_persist,
+            local_command: self.local_command.as_deref().and_then(normalize_required),
+            on_connect: self.on_connect.as_deref().and_then(normalize_required),
+            on_disconnect: self.on_disconnect.as_deref().and_then(normalize_required),
+            options: self.options,
+            ..Default::default()
+        };
+
+        // Every problem is listed, while the first stays available to match on with `downcast_ref`.
+        let problems = config.validate();
+        if let Some(first) = problems.first() {
+            return Err(anyhow::Error::new(first.clone())
+                .context(format!("Invalid profile: {}", join_problems(&problems, "; "))));
+        }
+        Ok(config)
+    }
+}
+
+fn required(value: Option<String>, field: &'static str) -> Result<String, ConfigError> {
+    value.as_deref().and_then(normalize_required).ok_or(ConfigError::MissingField(field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete_builder() -> SshConfigBuilder {
+        SshConfig::builder()
+            .jump_host_user("jumper")
+            .jump_host(" bastion ")
+            .target_host_user("deploy")
+            .target_host("10.0.0.5")
+    }
+
+    #[test]
+    fn test_build_applies_defaults() {
+        let config = complete_builder().target_port(2222).build().unwrap();
+
+        assert_eq!(config.jump_host, "bastion");
+        assert_eq!(config.jump_port, 22);
+        assert_eq!(config.target_port, 2222);
+        assert!(config.enc1.is_none() && config.enc2.is_none());
+    }
+
+    #[test]
+    fn test_build_requires_hosts() {
+        let err = SshConfig::builder().jump_host_user("jumper").build().unwrap_err();
+
+        assert_eq!(err.to_string(), "Missing jump host address");
+        assert_eq!(err.downcast_ref::<ConfigError>(), Some(&ConfigError::MissingField("jump_host")));
+    }
+
+    #[test]
+    fn test_build_with_proxy_command() {
+        let proxied = || {
+            SshConfig::builder()
+                .proxy_command("cloudflared access ssh --hostname %h")
+                .target_host_user("deploy")
+                .target_host("10.0.0.5")
+        };
+
+        let config = proxied().build().unwrap();
+        assert!(config.jump_host.is_empty());
+        assert_eq!(config.proxy_command.as_deref(), Some("cloudflared access ssh --hostname %h"));
+
+        let err = proxied().jump_host_user("jumper").jump_host("bastion").build().unwrap_err();
+        assert!(err.to_string().contains("jump_host and proxy_command are both set"));
+    }
+
+    #[test]
+    fn test_build_validates_ports_and_forwards() {
+        let err = complete_builder().jump_port(0).build().unwrap_err();
+        assert_eq!(err.to_string(), "Invalid profile: jump_port 0 is out of range");
+        assert!(matches!(err.downcast_ref::<ConfigError>(), Some(ConfigError::InvalidPort { port: 0, .. })));
+        assert!(complete_builder()
+            .port_forward(8080)
+            .forward("8080:web:80".parse().unwrap())
+            .build()
+            .is_err());
+    }
+}
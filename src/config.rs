@@ -0,0 +1,490 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+use tracing::debug;
+
+use crate::crypto::Cipher;
+use crate::get_profiles_dir;
+use crate::paths::expand_path;
+
+pub const GLOBAL_CONFIG_FILE: &str = "config.toml";
+pub const SSH_BINARY_ENV: &str = "RUSH_TUNNEL_SSH";
+// Every method OpenSSH accepts in PreferredAuthentications.
+pub const AUTH_METHODS: &[&str] = &["gssapi-with-mic", "hostbased", "publickey", "keyboard-interactive", "password"];
+
+#[derive(StructOpt, Serialize, Deserialize, JsonSchema, Default, Clone, Debug, PartialEq)]
+pub struct SshOptions {
+    #[structopt(long, help = "Seconds between keepalive messages (ServerAliveInterval)")]
+    pub server_alive_interval: Option<u32>,
+
+    #[structopt(long, parse(try_from_str = parse_yes_no), help = "Enable compression (yes/no)")]
+    pub compression: Option<bool>,
+
+    #[structopt(long, help = "StrictHostKeyChecking policy (yes, no, accept-new, ask)")]
+    pub strict_host_key_checking: Option<String>,
+
+    // Unset means "on whenever there are forwards"; on the command line it can only be turned off,
+    // with `--no-exit-on-forward-failure`.
+    #[structopt(skip)]
+    pub exit_on_forward_failure: Option<bool>,
+
+    // Unset means "password only" when rush-tunnel answers the password prompts, else ssh's default.
+    #[structopt(
+        long,
+        use_delimiter = true,
+        parse(try_from_str = parse_auth_method),
+        help = "Comma-separated authentication methods to try, in order (PreferredAuthentications)"
+    )]
+    pub auth_methods: Option<Vec<String>>,
+
+    // Saved as written, so `~` and `$VAR` are expanded on the machine that connects.
+    #[structopt(
+        long,
+        value_name = "socket",
+        help = "Agent socket to take keys from, e.g. ~/.1password/agent.sock (IdentityAgent)"
+    )]
+    pub identity_agent: Option<String>,
+
+    // Saved as written too. A passphrase for it can be saved with `tunnel --key-passphrase`.
+    #[structopt(long, value_name = "path", help = "Private key to authenticate to the target with (IdentityFile)")]
+    pub identity_file: Option<String>,
+
+    // Unset means one attempt when rush-tunnel answers the password prompts, else ssh's default of three.
+    #[structopt(
+        long,
+        value_name = "n",
+        help = "Password attempts before ssh gives up (NumberOfPasswordPrompts)"
+    )]
+    pub password_prompts: Option<u32>,
+
+    // Only the names are sent; the target's sshd must list them in AcceptEnv to take the values.
+    #[structopt(
+        long,
+        value_name = "VAR",
+        number_of_values = 1,
+        parse(try_from_str = parse_env_name),
+        help = "Local environment variable to send to the target, repeatable (SendEnv)"
+    )]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub send_env: Vec<String>,
+
+    // Set on the command line with `-4`/`--ipv4` or `-6`/`--ipv6`, which can't be combined.
+    #[structopt(skip)]
+    pub address_family: Option<AddressFamily>,
+
+    // ssh has no way to cap what goes through a forward, so this is never passed to it. It is kept
+    // for the transfers that can be capped, with `scp -l` and `sftp -l`.
+    #[structopt(
+        long,
+        value_name = "Kbit/s",
+        help = "Bandwidth cap for scp/sftp transfers to the target (-l); ssh can't apply it to forwards"
+    )]
+    pub limit_rate: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    // The command line rejects `--ipv4` with `--ipv6`, so at most one of them is set here.
+    pub fn from_flags(ipv4: bool, ipv6: bool) -> Option<AddressFamily> {
+        match (ipv4, ipv6) {
+            (true, _) => Some(AddressFamily::Ipv4),
+            (false, true) => Some(AddressFamily::Ipv6),
+            (false, false) => None,
+        }
+    }
+
+    pub fn ssh_flag(&self) -> &'static str {
+        match self {
+            AddressFamily::Ipv4 => "-4",
+            AddressFamily::Ipv6 => "-6",
+        }
+    }
+
+    // The AddressFamily option, for tools like ssh-copy-id that only pass `-o` through to ssh.
+    pub fn ssh_option(&self) -> &'static str {
+        match self {
+            AddressFamily::Ipv4 => "AddressFamily=inet",
+            AddressFamily::Ipv6 => "AddressFamily=inet6",
+        }
+    }
+}
+
+impl SshOptions {
+    // Fields already set win over `fallback`, so calls chain in precedence order:
+    // `cli.or(profile).or(global)`.
+    pub fn or(self, fallback: SshOptions) -> SshOptions {
+        SshOptions {
+            server_alive_interval: self.server_alive_interval.or(fallback.server_alive_interval),
+            compression: self.compression.or(fallback.compression),
+            strict_host_key_checking: self.strict_host_key_checking.or(fallback.strict_host_key_checking),
+            exit_on_forward_failure: self.exit_on_forward_failure.or(fallback.exit_on_forward_failure),
+            auth_methods: self.auth_methods.or(fallback.auth_methods),
+            identity_agent: self.identity_agent.or(fallback.identity_agent),
+            identity_file: self.identity_file.or(fallback.identity_file),
+            password_prompts: self.password_prompts.or(fallback.password_prompts),
+            send_env: if self.send_env.is_empty() { fallback.send_env } else { self.send_env },
+            address_family: self.address_family.or(fallback.address_family),
+            limit_rate: self.limit_rate.or(fallback.limit_rate),
+        }
+    }
+
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = match &self.auth_methods {
+            Some(methods) if methods.is_empty() => vec!["auth_methods is empty".to_string()],
+            Some(methods) => methods
+                .iter()
+                .filter_map(|method| parse_auth_method(method).err())
+                .map(|e| e.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+        if self.identity_agent.as_deref().is_some_and(|agent| agent.trim().is_empty()) {
+            problems.push("identity_agent is empty".to_string());
+        }
+        if self.identity_file.as_deref().is_some_and(|file| file.trim().is_empty()) {
+            problems.push("identity_file is empty".to_string());
+        }
+        problems.extend(self.send_env.iter().filter_map(|name| parse_env_name(name).err()).map(|e| e.to_string()));
+        if self.limit_rate == Some(0) {
+            problems.push("limit_rate must be at least 1 Kbit/s".to_string());
+        }
+        problems
+    }
+
+    // `none` and `SSH_AUTH_SOCK` are keywords to ssh and come through expansion unchanged.
+    pub fn expand_identity_agent(&mut self) -> Result<()> {
+        if let Some(agent) = &self.identity_agent {
+            let expanded = expand_path(agent).context(format!("Invalid identity_agent '{}'", agent))?;
+            self.identity_agent = Some(expanded.to_string_lossy().to_string());
+        }
+        Ok(())
+    }
+
+    // Expanded here rather than by ssh, so the path matches the one in ssh's passphrase prompt.
+    pub fn expand_identity_file(&mut self) -> Result<()> {
+        if let Some(file) = &self.identity_file {
+            let expanded = expand_path(file).context(format!("Invalid identity_file '{}'", file))?;
+            self.identity_file = Some(expanded.to_string_lossy().to_string());
+        }
+        Ok(())
+    }
+
+    pub fn to_ssh_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(family) = self.address_family {
+            args.push(family.ssh_flag().to_string());
+        }
+        if let Some(interval) = self.server_alive_interval {
+            args.push("-o".to_string());
+            args.push(format!("ServerAliveInterval={}", interval));
+        }
+        if let Some(compression) = self.compression {
+            args.push("-o".to_string());
+            args.push(format!("Compression={}", if compression { "yes" } else { "no" }));
+        }
+        if let Some(policy) = &self.strict_host_key_checking {
+            args.push("-o".to_string());
+            args.push(format!("StrictHostKeyChecking={}", policy));
+        }
+        if let Some(exit) = self.exit_on_forward_failure {
+            args.push("-o".to_string());
+            args.push(format!("ExitOnForwardFailure={}", if exit { "yes" } else { "no" }));
+        }
+        if let Some(methods) = &self.auth_methods {
+            args.push("-o".to_string());
+            args.push(format!("PreferredAuthentications={}", methods.join(",")));
+        }
+        if let Some(agent) = &self.identity_agent {
+            args.push("-o".to_string());
+            args.push(format!("IdentityAgent={}", agent));
+        }
+        if let Some(file) = &self.identity_file {
+            args.push("-o".to_string());
+            args.push(format!("IdentityFile={}", file));
+        }
+        if let Some(prompts) = self.password_prompts {
+            args.push("-o".to_string());
+            args.push(format!("NumberOfPasswordPrompts={}", prompts));
+        }
+        for name in &self.send_env {
+            args.push("-o".to_string());
+            args.push(format!("SendEnv={}", name));
+        }
+        args
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct GlobalConfig {
+    pub ssh_binary: Option<String>,
+    pub cipher: Option<String>,
+    // The answer "Save password?" defaults to when creating a profile; unset means no.
+    pub save_password_default: Option<bool>,
+    #[serde(default)]
+    pub defaults: SshOptions,
+}
+
+impl GlobalConfig {
+    pub fn load() -> Result<GlobalConfig> {
+        let profiles_dir = get_profiles_dir()?;
+        let config_path = Path::new(&profiles_dir).join(GLOBAL_CONFIG_FILE);
+        debug!(path = %config_path.display(), exists = config_path.exists(), "loading global config");
+        if !config_path.exists() {
+            return Ok(GlobalConfig::default());
+        }
+
+        let toml_str = fs::read_to_string(&config_path).context("Failed to read global config file")?;
+        toml::from_str(&toml_str).context(format!("Failed to parse {}", config_path.display()))
+    }
+
+    pub fn cipher(&self) -> Result<Cipher> {
+        match &self.cipher {
+            Some(id) => id.parse().context("Invalid cipher in global config"),
+            None => Ok(Cipher::default()),
+        }
+    }
+
+    pub fn ssh_binary(&self) -> Result<String> {
+        let binary = std::env::var(SSH_BINARY_ENV)
+            .ok()
+            .filter(|binary| !binary.is_empty())
+            .or_else(|| self.ssh_binary.clone())
+            .unwrap_or_else(|| "ssh".to_string());
+        let binary = expand_path(&binary).context(format!("Invalid ssh binary path '{}'", binary))?;
+        Ok(binary.to_string_lossy().to_string())
+    }
+}
+
+fn parse_auth_method(value: &str) -> Result<String> {
+    let method = value.trim();
+    if !AUTH_METHODS.contains(&method) {
+        bail!("unknown auth method '{}' (expected one of {})", method, AUTH_METHODS.join(", "));
+    }
+    Ok(method.to_string())
+}
+
+fn parse_env_name(value: &str) -> Result<String> {
+    let mut chars = value.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        bail!("invalid send_env name '{}' (expected letters, digits and underscores, not starting with a digit)", value);
+    }
+    Ok(value.to_string())
+}
+
+fn parse_yes_no(value: &str) -> Result<bool> {
+    match value {
+        "yes" | "true" => Ok(true),
+        "no" | "false" => Ok(false),
+        _ => bail!("expected 'yes' or 'no'"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_precedence() {
+        let cli = SshOptions {
+            compression: Some(false),
+            ..Default::default()
+        };
+        let profile = SshOptions {
+            compression: Some(true),
+            server_alive_interval: Some(15),
+            ..Default::default()
+        };
+        let global = SshOptions {
+            compression: Some(true),
+            server_alive_interval: Some(60),
+            strict_host_key_checking: Some("accept-new".to_string()),
+            exit_on_forward_failure: Some(false),
+            auth_methods: Some(vec!["password".to_string()]),
+            identity_agent: Some("~/.1password/agent.sock".to_string()),
+            identity_file: Some("~/.ssh/deploy_ed25519".to_string()),
+            password_prompts: Some(2),
+            send_env: vec!["LANG".to_string()],
+            address_family: Some(AddressFamily::Ipv4),
+            limit_rate: Some(8000),
+        };
+
+        let merged = cli.or(profile).or(global);
+
+        assert_eq!(merged.compression, Some(false));
+        assert_eq!(merged.server_alive_interval, Some(15));
+        assert_eq!(merged.strict_host_key_checking.as_deref(), Some("accept-new"));
+        assert_eq!(merged.exit_on_forward_failure, Some(false));
+        assert_eq!(merged.auth_methods, Some(vec!["password".to_string()]));
+        assert_eq!(merged.identity_agent.as_deref(), Some("~/.1password/agent.sock"));
+        assert_eq!(merged.identity_file.as_deref(), Some("~/.ssh/deploy_ed25519"));
+        assert_eq!(merged.password_prompts, Some(2));
+        assert_eq!(merged.send_env, vec!["LANG"]);
+        assert_eq!(merged.address_family, Some(AddressFamily::Ipv4));
+        assert_eq!(merged.limit_rate, Some(8000));
+    }
+
+    #[test]
+    fn test_address_family_from_flags() {
+        assert_eq!(AddressFamily::from_flags(false, false), None);
+        assert_eq!(AddressFamily::from_flags(true, false), Some(AddressFamily::Ipv4));
+        assert_eq!(AddressFamily::from_flags(false, true), Some(AddressFamily::Ipv6));
+    }
+
+    #[test]
+    fn test_unset_options_emit_no_args() {
+        assert!(SshOptions::default().or(SshOptions::default()).to_ssh_args().is_empty());
+    }
+
+    #[test]
+    fn test_to_ssh_args() {
+        let options = SshOptions {
+            server_alive_interval: Some(30),
+            compression: Some(true),
+            strict_host_key_checking: Some("no".to_string()),
+            exit_on_forward_failure: None,
+            auth_methods: Some(vec!["publickey".to_string(), "password".to_string()]),
+            identity_agent: Some("/run/user/1000/gnupg/S.gpg-agent.ssh".to_string()),
+            identity_file: Some("/home/me/.ssh/deploy_ed25519".to_string()),
+            password_prompts: Some(0),
+            send_env: vec!["LANG".to_string(), "DEPLOY_ENV".to_string()],
+            address_family: Some(AddressFamily::Ipv6),
+            // Not an ssh option, so it adds nothing here.
+            limit_rate: Some(8000),
+        };
+
+        assert_eq!(
+            options.to_ssh_args(),
+            vec![
+                "-6",
+                "-o",
+                "ServerAliveInterval=30",
+                "-o",
+                "Compression=yes",
+                "-o",
+                "StrictHostKeyChecking=no",
+                "-o",
+                "PreferredAuthentications=publickey,password",
+                "-o",
+                "IdentityAgent=/run/user/1000/gnupg/S.gpg-agent.ssh",
+                "-o",
+                "IdentityFile=/home/me/.ssh/deploy_ed25519",
+                "-o",
+                "NumberOfPasswordPrompts=0",
+                "-o",
+                "SendEnv=LANG",
+                "-o",
+                "SendEnv=DEPLOY_ENV"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_auth_method_problems() {
+        let options = SshOptions {
+            auth_methods: Some(vec!["password".to_string(), "kerberos".to_string()]),
+            ..Default::default()
+        };
+        assert_eq!(options.problems().len(), 1);
+        assert!(options.problems()[0].starts_with("unknown auth method 'kerberos'"));
+
+        let options = SshOptions {
+            auth_methods: Some(Vec::new()),
+            ..Default::default()
+        };
+        assert_eq!(options.problems(), vec!["auth_methods is empty"]);
+    }
+
+    #[test]
+    fn test_send_env_names() {
+        assert_eq!(parse_env_name("_DEPLOY_ENV2").unwrap(), "_DEPLOY_ENV2");
+        for name in ["", "2FA", "LC_*", "MY-VAR", "A B"] {
+            assert!(parse_env_name(name).is_err(), "{}", name);
+        }
+
+        let options = SshOptions {
+            send_env: vec!["LANG".to_string(), "LC_*".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(options.problems().len(), 1);
+        assert!(options.problems()[0].starts_with("invalid send_env name 'LC_*'"));
+    }
+
+    #[test]
+    fn test_expand_identity_agent() {
+        let mut options = SshOptions {
+            identity_agent: Some("SSH_AUTH_SOCK".to_string()),
+            ..Default::default()
+        };
+        options.expand_identity_agent().unwrap();
+        assert_eq!(options.identity_agent.as_deref(), Some("SSH_AUTH_SOCK"));
+
+        options.identity_agent = Some("~/agent.sock".to_string());
+        options.expand_identity_agent().unwrap();
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(options.identity_agent, Some(home.join("agent.sock").to_string_lossy().to_string()));
+
+        options.identity_agent = Some(" ".to_string());
+        assert_eq!(options.problems(), vec!["identity_agent is empty"]);
+    }
+
+    #[test]
+    fn test_expand_identity_file() {
+        let mut options = SshOptions {
+            identity_file: Some("~/.ssh/deploy_ed25519".to_string()),
+            ..Default::default()
+        };
+        options.expand_identity_file().unwrap();
+        let home = dirs::home_dir().unwrap();
+        assert_eq!(options.identity_file, Some(home.join(".ssh/deploy_ed25519").to_string_lossy().to_string()));
+
+        options.identity_file = Some("".to_string());
+        assert_eq!(options.problems(), vec!["identity_file is empty"]);
+    }
+
+    #[test]
+    fn test_limit_rate_problems() {
+        let options = SshOptions {
+            limit_rate: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(options.problems(), vec!["limit_rate must be at least 1 Kbit/s"]);
+
+        let config: GlobalConfig = toml::from_str("[defaults]\nlimit_rate = 8000\n").unwrap();
+        assert_eq!(config.defaults.limit_rate, Some(8000));
+        assert!(config.defaults.problems().is_empty());
+    }
+
+    #[test]
+    fn test_parse_global_config() {
+        let config: GlobalConfig = toml::from_str(
+            "[defaults]\nserver_alive_interval = 30\ncompression = true\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.defaults.server_alive_interval, Some(30));
+        assert_eq!(config.defaults.compression, Some(true));
+        assert_eq!(config.defaults.strict_host_key_checking, None);
+        assert_eq!(config.cipher().unwrap(), Cipher::ChaCha20Poly1305);
+    }
+
+    #[test]
+    fn test_cipher_selection() {
+        let config: GlobalConfig = toml::from_str("cipher = \"aes-256-gcm\"\n").unwrap();
+        assert_eq!(config.cipher().unwrap(), Cipher::Aes256Gcm);
+
+        let config: GlobalConfig = toml::from_str("cipher = \"des\"\n").unwrap();
+        assert!(config.cipher().is_err());
+    }
+}
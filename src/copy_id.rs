@@ -0,0 +1,149 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+use anyhow::{Context, Result};
+use tracing::{debug, info};
+
+use crate::askpass::{self, Credentials};
+use crate::config::SshOptions;
+use crate::{bracket_host, find_executable, proxy, SshConfig};
+
+pub const COPY_ID_BINARY: &str = "ssh-copy-id";
+// Newest key types first, like ssh's own default identities.
+const PREFERRED_PUBLIC_KEYS: &[&str] = &["id_ed25519.pub", "id_ecdsa.pub", "id_rsa.pub"];
+
+pub fn default_public_key() -> Result<PathBuf> {
+    let ssh_dir = dirs::home_dir().context("Failed to get home directory")?.join(".ssh");
+    find_public_key(&ssh_dir).context(format!("No id_*.pub key found in {}; pass one with --key", ssh_dir.display()))
+}
+
+fn find_public_key(ssh_dir: &Path) -> Option<PathBuf> {
+    if let Some(key) = PREFERRED_PUBLIC_KEYS.iter().map(|name| ssh_dir.join(name)).find(|key| key.is_file()) {
+        return Some(key);
+    }
+    let mut keys: Vec<PathBuf> = fs::read_dir(ssh_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.starts_with("id_") && name.ends_with(".pub") && path.is_file()
+        })
+        .collect();
+    keys.sort();
+    keys.into_iter().next()
+}
+
+// ssh-copy-id hands `-o` options to every ssh it runs, which is how the jump host reaches it.
+pub fn build_copy_id_args(config: &SshConfig, key: &Path) -> Vec<String> {
+    let route = match (&config.proxy_command, proxy::resolve(config)) {
+        (Some(command), _) => format!("ProxyCommand={}", command),
+        (None, Some((pre_proxy, tool))) => {
+            format!("ProxyCommand={}", proxy::jump_proxy_command(config, Some((&pre_proxy, tool)), &[]))
+        }
+        (None, None) => format!(
+            "ProxyJump={}@{}:{}",
+            config.jump_host_user,
+            bracket_host(&config.jump_host),
+            config.jump_port
+        ),
+    };
+    let mut args = vec![
+        "-i".to_string(),
+        key.to_string_lossy().to_string(),
+        "-p".to_string(),
+        config.target_port.to_string(),
+        "-o".to_string(),
+        route,
+    ];
+    // ssh-copy-id first logs in with only the key to see whether it is already installed, which an
+    // auth_methods list would override. There are no forwards for ExitOnForwardFailure to guard.
+    let options = SshOptions {
+        auth_methods: None,
+        exit_on_forward_failure: None,
+        address_family: None,
+        ..config.options.clone()
+    };
+    args.extend(options.to_ssh_args());
+    // ssh-copy-id doesn't take `-4`/`-6` itself.
+    if let Some(family) = config.options.address_family {
+        args.push("-o".to_string());
+        args.push(family.ssh_option().to_string());
+    }
+    args.push(format!("{}@{}", config.target_host_user, bracket_host(&config.target_host)));
+    debug!(?args, "built ssh-copy-id arguments");
+    args
+}
+
+pub fn copy_id(config: &SshConfig, key: &Path, credentials: Option<&Credentials>) -> Result<ExitStatus> {
+    let Some(copy_id_path) = find_executable(COPY_ID_BINARY) else {
+        anyhow::bail!(
+            "{} not found on PATH; it ships with the OpenSSH client (openssh-client on Debian and Ubuntu)",
+            COPY_ID_BINARY
+        );
+    };
+    let mut command = Command::new(copy_id_path);
+    command.args(build_copy_id_args(config, key));
+    if let Some(credentials) = credentials {
+        askpass::configure(&mut command, config, credentials)?;
+    }
+    info!(key = %key.display(), "running ssh-copy-id");
+    let status = command.status().context(format!("Failed to run {}", COPY_ID_BINARY))?;
+    info!(%status, "ssh-copy-id exited");
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_copy_id_args() {
+        let mut config = SshConfig::builder()
+            .jump_host_user("jumper")
+            .jump_host("bastion")
+            .jump_port(2200)
+            .target_host_user("deploy")
+            .target_host("fd00::5")
+            .target_port(2222)
+            .build()
+            .unwrap();
+        config.options.auth_methods = Some(vec!["password".to_string()]);
+        config.options.server_alive_interval = Some(30);
+
+        assert_eq!(
+            build_copy_id_args(&config, Path::new("/home/me/.ssh/id_ed25519.pub")),
+            vec![
+                "-i",
+                "/home/me/.ssh/id_ed25519.pub",
+                "-p",
+                "2222",
+                "-o",
+                "ProxyJump=jumper@bastion:2200",
+                "-o",
+                "ServerAliveInterval=30",
+                "deploy@[fd00::5]"
+            ]
+        );
+
+        config.proxy_command = Some("cloudflared access ssh --hostname %h".to_string());
+        let args = build_copy_id_args(&config, Path::new("key.pub"));
+        assert_eq!(args[5], "ProxyCommand=cloudflared access ssh --hostname %h");
+    }
+
+    #[test]
+    fn test_find_public_key() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(find_public_key(dir.path()), None);
+
+        fs::write(dir.path().join("id_work.pub"), "ssh-ed25519 AAAA").unwrap();
+        fs::write(dir.path().join("id_work"), "private").unwrap();
+        assert_eq!(find_public_key(dir.path()), Some(dir.path().join("id_work.pub")));
+
+        fs::write(dir.path().join("id_rsa.pub"), "ssh-rsa AAAA").unwrap();
+        assert_eq!(find_public_key(dir.path()), Some(dir.path().join("id_rsa.pub")));
+
+        fs::write(dir.path().join("id_ed25519.pub"), "ssh-ed25519 AAAA").unwrap();
+        assert_eq!(find_public_key(dir.path()), Some(dir.path().join("id_ed25519.pub")));
+    }
+}
@@ -1,17 +1,66 @@
 use std::fmt;
+use std::str::FromStr;
 
+use aes_gcm::Aes256Gcm;
 use argon2::{
     Argon2,
-    password_hash::{PasswordHasher, SaltString},
+    password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
 };
 use chacha20poly1305::{
-    aead::{Aead, NewAead},
-    ChaCha20Poly1305, Key, Nonce,
+    aead::{generic_array::GenericArray, Aead, NewAead},
+    ChaCha20Poly1305,
 };
 use hex::{decode, encode};
 use rand_core::{OsRng, RngCore};
 
 const NONCE_SIZE: usize = 12;
+const FORMAT_VERSION: &str = "v1";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Cipher {
+    #[default]
+    ChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+impl Cipher {
+    pub fn id(&self) -> &'static str {
+        match self {
+            Cipher::ChaCha20Poly1305 => "chacha20-poly1305",
+            Cipher::Aes256Gcm => "aes-256-gcm",
+        }
+    }
+
+    fn encrypt(&self, key: &[u8], nonce: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let key = GenericArray::from_slice(key);
+        let nonce = GenericArray::from_slice(nonce);
+        Ok(match self {
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(key).encrypt(nonce, data)?,
+            Cipher::Aes256Gcm => Aes256Gcm::new(key).encrypt(nonce, data)?,
+        })
+    }
+
+    fn decrypt(&self, key: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let key = GenericArray::from_slice(key);
+        let nonce = GenericArray::from_slice(nonce);
+        Ok(match self {
+            Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new(key).decrypt(nonce, ciphertext)?,
+            Cipher::Aes256Gcm => Aes256Gcm::new(key).decrypt(nonce, ciphertext)?,
+        })
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = CryptoError;
+
+    fn from_str(id: &str) -> Result<Cipher, CryptoError> {
+        match id {
+            "chacha20-poly1305" => Ok(Cipher::ChaCha20Poly1305),
+            "aes-256-gcm" => Ok(Cipher::Aes256Gcm),
+            _ => Err(CryptoError::UnknownCipher(id.to_string())),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum CryptoError {
@@ -19,6 +68,7 @@ pub enum CryptoError {
     HexError(hex::FromHexError),
     Utf8Error(std::string::FromUtf8Error),
     AeadError(chacha20poly1305::aead::Error),
+    UnknownCipher(String),
     InvalidDataFormat,
 }
 
@@ -29,11 +79,24 @@ impl fmt::Display for CryptoError {
             CryptoError::HexError(e) => write!(f, "Hex decoding error: {}", e),
             CryptoError::Utf8Error(e) => write!(f, "UTF-8 conversion error: {}", e),
             CryptoError::AeadError(e) => write!(f, "AEAD encryption/decryption error: {}", e),
+            CryptoError::UnknownCipher(id) => write!(f, "Unknown cipher: {}", id),
             CryptoError::InvalidDataFormat => write!(f, "Invalid data format"),
         }
     }
 }
 
+impl std::error::Error for CryptoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CryptoError::Argon2Error(e) => Some(e),
+            CryptoError::HexError(e) => Some(e),
+            CryptoError::Utf8Error(e) => Some(e),
+            CryptoError::AeadError(e) => Some(e),
+            CryptoError::UnknownCipher(_) | CryptoError::InvalidDataFormat => None,
+        }
+    }
+}
+
 impl From<argon2::password_hash::Error> for CryptoError {
     fn from(err: argon2::password_hash::Error) -> CryptoError {
         CryptoError::Argon2Error(err)
@@ -59,57 +122,98 @@ impl From<chacha20poly1305::aead::Error> for CryptoError {
 }
 
 pub fn encrypt_password(master_password: &str, password: &str) -> Result<String, CryptoError> {
-    let salt = SaltString::generate(&mut OsRng);
+    encrypt_bytes(master_password, password.as_bytes())
+}
 
-    let argon2 = Argon2::default();
-    let hashed_master_password = argon2.hash_password(master_password.as_bytes(), &salt)?;
+pub fn encrypt_password_with(
+    master_password: &str,
+    password: &str,
+    cipher: Cipher,
+) -> Result<String, CryptoError> {
+    encrypt_bytes_with(master_password, password.as_bytes(), cipher)
+}
 
-    let binding = hashed_master_password.hash.ok_or(CryptoError::InvalidDataFormat)?;
-    let key = Key::from_slice(binding.as_bytes());
+pub fn decrypt_password(master_password: &str, encrypted_data: &str) -> Result<String, CryptoError> {
+    Ok(String::from_utf8(decrypt_bytes(master_password, encrypted_data)?)?)
+}
+
+pub fn encrypt_bytes(master_password: &str, data: &[u8]) -> Result<String, CryptoError> {
+    encrypt_bytes_with(master_password, data, Cipher::default())
+}
+
+// The result is the hex encoding of `v1;<cipher id>;<salt>;<nonce hex>;<ciphertext hex>`. The salt
+// (Argon2's base64 form) and the 12-byte nonce are drawn fresh from the OS on every call, so the
+// same password never encrypts the same way twice and no key/nonce pair is ever reused. The
+// ciphertext ends in the AEAD tag, so any change to it fails decryption.
+pub fn encrypt_bytes_with(master_password: &str, data: &[u8], cipher: Cipher) -> Result<String, CryptoError> {
+    let salt = SaltString::generate(&mut OsRng);
+    let key = derive_key(master_password, &salt)?;
 
     let mut nonce_bytes = [0u8; NONCE_SIZE];
     OsRng.fill_bytes(&mut nonce_bytes);
-    let nonce = Nonce::from_slice(&nonce_bytes);
 
-    let cipher = ChaCha20Poly1305::new(key);
-    let ciphertext = cipher.encrypt(nonce, password.as_bytes())?;
+    let ciphertext = cipher.encrypt(&key, &nonce_bytes, data)?;
 
-    let mut encrypted_data = salt.as_str().to_string();
-    encrypted_data.push(';');
-    encrypted_data.push_str(&encode(nonce_bytes));
-    encrypted_data.push(';');
-    encrypted_data.push_str(&encode(ciphertext));
+    let encrypted_data = [
+        FORMAT_VERSION,
+        cipher.id(),
+        salt.as_str(),
+        &encode(nonce_bytes),
+        &encode(ciphertext),
+    ]
+    .join(";");
 
     let encoded_data = encode(encrypted_data);
 
     Ok(encoded_data)
 }
 
-pub fn decrypt_password(master_password: &str, encrypted_data: &str) -> Result<String, CryptoError> {
+pub fn decrypt_bytes(master_password: &str, encrypted_data: &str) -> Result<Vec<u8>, CryptoError> {
     let decoded_data = decode(encrypted_data)?;
     let decoded_data_str = String::from_utf8(decoded_data)?;
 
+    // Data written before the versioned header was introduced is `salt;nonce;ciphertext`
+    // encrypted with ChaCha20-Poly1305.
     let parts: Vec<&str> = decoded_data_str.split(';').collect();
-    if parts.len() != 3 {
-        return Err(CryptoError::InvalidDataFormat);
-    }
+    let (cipher, parts) = match parts.as_slice() {
+        [salt, nonce, ciphertext] => (Cipher::ChaCha20Poly1305, [*salt, *nonce, *ciphertext]),
+        [FORMAT_VERSION, cipher, salt, nonce, ciphertext] => (cipher.parse()?, [*salt, *nonce, *ciphertext]),
+        _ => return Err(CryptoError::InvalidDataFormat),
+    };
 
     let salt = SaltString::from_b64(parts[0])?;
     let nonce_bytes = decode(parts[1])?;
     let ciphertext = decode(parts[2])?;
+    if nonce_bytes.len() != NONCE_SIZE {
+        return Err(CryptoError::InvalidDataFormat);
+    }
+
+    let key = derive_key(master_password, &salt)?;
+    cipher.decrypt(&key, &nonce_bytes, &ciphertext)
+}
 
+fn derive_key(master_password: &str, salt: &SaltString) -> Result<Vec<u8>, CryptoError> {
     let argon2 = Argon2::default();
-    let hashed_master_password = argon2.hash_password(master_password.as_bytes(), &salt)?;
+    let hashed_master_password = argon2.hash_password(master_password.as_bytes(), salt)?;
 
     let binding = hashed_master_password.hash.ok_or(CryptoError::InvalidDataFormat)?;
-    let key = Key::from_slice(binding.as_bytes());
-
-    let nonce = Nonce::from_slice(&nonce_bytes);
+    Ok(binding.as_bytes().to_vec())
+}
 
-    let cipher = ChaCha20Poly1305::new(key);
-    let plaintext = cipher.decrypt(nonce, ciphertext.as_ref())?;
+// The verifier is a salted Argon2 PHC hash of the master password itself, so a wrong password can
+// be rejected up front instead of surfacing as an AEAD failure on the first saved password.
+pub fn create_verifier(master_password: &str) -> Result<String, CryptoError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(master_password.as_bytes(), &salt)?.to_string())
+}
 
-    Ok(String::from_utf8(plaintext)?)
+pub fn verify_master_password(master_password: &str, verifier: &str) -> Result<bool, CryptoError> {
+    let hash = PasswordHash::new(verifier)?;
+    match Argon2::default().verify_password(master_password.as_bytes(), &hash) {
+        Ok(()) => Ok(true),
+        Err(password_hash::Error::Password) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
 }
 
 pub fn is_password_strong(password: &str) -> bool {
@@ -152,6 +256,153 @@ mod tests {
         assert!(decryption_result.is_err());
     }
 
+    #[test]
+    fn test_bytes_encryption_decryption() {
+        let master_password = "test_master_password";
+        let data = [0u8, 159, 146, 150, 255];
+
+        let encrypted_data = encrypt_bytes(master_password, &data)
+            .expect("Data encryption failed");
+        let decrypted_data = decrypt_bytes(master_password, &encrypted_data)
+            .expect("Data decryption failed");
+
+        assert_eq!(data.to_vec(), decrypted_data);
+    }
+
+    #[test]
+    fn test_aes_gcm_encryption_decryption() {
+        let master_password = "test_master_password";
+        let password = "test_password";
+
+        let encrypted_password = encrypt_password_with(master_password, password, Cipher::Aes256Gcm)
+            .expect("Password encryption failed");
+        let decrypted_password = decrypt_password(master_password, &encrypted_password)
+            .expect("Password decryption failed");
+
+        assert_eq!(password, decrypted_password);
+    }
+
+    #[test]
+    fn test_cipher_id_is_recorded_in_header() {
+        let encrypted = encrypt_password_with("test_master_password", "pw", Cipher::Aes256Gcm).unwrap();
+        let header = String::from_utf8(decode(encrypted).unwrap()).unwrap();
+
+        assert!(header.starts_with("v1;aes-256-gcm;"));
+    }
+
+    #[test]
+    fn test_cross_cipher_data_fails_cleanly() {
+        let master_password = "test_master_password";
+        let encrypted = encrypt_password_with(master_password, "pw", Cipher::ChaCha20Poly1305).unwrap();
+        let header = String::from_utf8(decode(encrypted).unwrap()).unwrap();
+        let relabelled = encode(header.replacen("chacha20-poly1305", "aes-256-gcm", 1));
+
+        let result = decrypt_password(master_password, &relabelled);
+
+        assert!(matches!(result, Err(CryptoError::AeadError(_))));
+    }
+
+    #[test]
+    fn test_error_source_is_the_underlying_error() {
+        use std::error::Error;
+
+        let result = decrypt_password("test_master_password", "not hex");
+        let err = result.unwrap_err();
+
+        assert!(matches!(err, CryptoError::HexError(_)));
+        assert!(err.source().unwrap().downcast_ref::<hex::FromHexError>().is_some());
+        assert!(CryptoError::InvalidDataFormat.source().is_none());
+    }
+
+    #[test]
+    fn test_unknown_cipher_is_rejected() {
+        let encrypted = encrypt_password("test_master_password", "pw").unwrap();
+        let header = String::from_utf8(decode(encrypted).unwrap()).unwrap();
+        let relabelled = encode(header.replacen("chacha20-poly1305", "rot13", 1));
+
+        let result = decrypt_password("test_master_password", &relabelled);
+
+        assert!(matches!(result, Err(CryptoError::UnknownCipher(id)) if id == "rot13"));
+    }
+
+    #[test]
+    fn test_legacy_format_still_decrypts() {
+        let master_password = "test_master_password";
+        let salt = SaltString::generate(&mut OsRng);
+        let key = derive_key(master_password, &salt).unwrap();
+        let nonce_bytes = [7u8; NONCE_SIZE];
+        let ciphertext = Cipher::ChaCha20Poly1305.encrypt(&key, &nonce_bytes, b"pw").unwrap();
+        let legacy = encode(format!("{};{};{}", salt.as_str(), encode(nonce_bytes), encode(ciphertext)));
+
+        assert_eq!(decrypt_password(master_password, &legacy).unwrap(), "pw");
+    }
+
+    fn header_parts(encrypted: &str) -> Vec<String> {
+        let header = String::from_utf8(decode(encrypted).unwrap()).unwrap();
+        header.split(';').map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_salt_and_nonce_are_fresh_every_call() {
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let first = encrypt_password_with("test_master_password", "same password", cipher).unwrap();
+            let second = encrypt_password_with("test_master_password", "same password", cipher).unwrap();
+            let (first_parts, second_parts) = (header_parts(&first), header_parts(&second));
+
+            assert_ne!(first, second);
+            assert_ne!(first_parts[2], second_parts[2], "salt reused");
+            assert_ne!(first_parts[3], second_parts[3], "nonce reused");
+            assert_ne!(first_parts[4], second_parts[4], "ciphertext repeated");
+        }
+    }
+
+    #[test]
+    fn test_truncated_data_is_rejected() {
+        let master_password = "test_master_password";
+        let parts = header_parts(&encrypt_password(master_password, "pw").unwrap());
+        let ciphertext = &parts[4];
+
+        for malformed in [
+            parts[..4].join(";"),
+            parts[..2].join(";"),
+            format!("{};{};{};{};{}", parts[0], parts[1], parts[2], &parts[3][..8], ciphertext),
+            String::new(),
+        ] {
+            let result = decrypt_password(master_password, &encode(malformed));
+            assert!(matches!(result, Err(CryptoError::InvalidDataFormat)), "{:?}", result);
+        }
+        // Cut into the ciphertext and its tag, including to nothing at all.
+        for keep in [ciphertext.len() - 2, 8, 0] {
+            let truncated = format!("{};{}", parts[..4].join(";"), &ciphertext[..keep]);
+            let result = decrypt_password(master_password, &encode(truncated));
+            assert!(matches!(result, Err(CryptoError::AeadError(_))), "{:?}", result);
+        }
+    }
+
+    #[test]
+    fn test_tampered_ciphertext_is_rejected() {
+        let master_password = "test_master_password";
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let mut parts = header_parts(&encrypt_password_with(master_password, "pw", cipher).unwrap());
+            let mut ciphertext = decode(&parts[4]).unwrap();
+            ciphertext[0] ^= 0x01;
+            parts[4] = encode(ciphertext);
+
+            let result = decrypt_password(master_password, &encode(parts.join(";")));
+
+            assert!(matches!(result, Err(CryptoError::AeadError(_))), "{:?}", result);
+        }
+    }
+
+    #[test]
+    fn test_verifier_accepts_only_the_master_password() {
+        let verifier = create_verifier("test_master_password").unwrap();
+
+        assert!(verify_master_password("test_master_password", &verifier).unwrap());
+        assert!(!verify_master_password("wrong_password", &verifier).unwrap());
+        assert!(verify_master_password("test_master_password", "not-a-hash").is_err());
+    }
+
     #[test]
     fn test_password_policy() {
         assert!(is_password_strong("StrongP@ssword123"));
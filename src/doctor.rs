@@ -0,0 +1,193 @@
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::GlobalConfig;
+use crate::{find_executable, fsutil, get_profiles_dir, list_profiles, load_profile};
+
+// `SSH_ASKPASS_REQUIRE`, which saved passwords rely on, was added in OpenSSH 8.4.
+const ASKPASS_REQUIRE_VERSION: (u32, u32) = (8, 4);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheckStatus {
+    Ok,
+    Info,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Info => "info",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "FAIL",
+        }
+    }
+}
+
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Check {
+        Check {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+pub fn run_checks() -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let global = match GlobalConfig::load() {
+        Ok(global) => {
+            checks.push(Check::new("global config", CheckStatus::Ok, "parsed (or not present)"));
+            global
+        }
+        Err(e) => {
+            checks.push(Check::new("global config", CheckStatus::Fail, format!("{:#}", e)));
+            GlobalConfig::default()
+        }
+    };
+
+    checks.extend(ssh_checks(&global));
+    checks.extend(profiles_checks());
+    checks.push(match find_executable("sshpass") {
+        Some(path) => Check::new(
+            "sshpass",
+            CheckStatus::Info,
+            format!("found at {} (not needed, saved passwords use ssh's askpass)", path.display()),
+        ),
+        None => Check::new("sshpass", CheckStatus::Info, "not installed (not needed, saved passwords use ssh's askpass)"),
+    });
+    checks.push(Check::new(
+        "keyring",
+        CheckStatus::Info,
+        "not used, saved passwords are stored encrypted in the profile",
+    ));
+    checks
+}
+
+fn ssh_checks(global: &GlobalConfig) -> Vec<Check> {
+    let ssh_binary = match global.ssh_binary() {
+        Ok(ssh_binary) => ssh_binary,
+        Err(e) => return vec![Check::new("ssh binary", CheckStatus::Fail, format!("{:#}", e))],
+    };
+    let Some(path) = find_executable(&ssh_binary) else {
+        return vec![Check::new(
+            "ssh binary",
+            CheckStatus::Fail,
+            format!("{} not found on PATH; install OpenSSH client", ssh_binary),
+        )];
+    };
+
+    // `ssh -V` prints its version to stderr.
+    let banner = Command::new(&path)
+        .arg("-V")
+        .output()
+        .map(|output| {
+            let text = if output.stderr.is_empty() { output.stdout } else { output.stderr };
+            String::from_utf8_lossy(&text).trim().to_string()
+        })
+        .unwrap_or_default();
+    let version = parse_openssh_version(&banner);
+
+    let mut checks = vec![Check::new(
+        "ssh binary",
+        CheckStatus::Ok,
+        match version {
+            Some(_) => format!("{} ({})", path.display(), banner),
+            None => format!("{} (version unknown)", path.display()),
+        },
+    )];
+    checks.push(match version {
+        Some(version) if version >= ASKPASS_REQUIRE_VERSION => {
+            Check::new("saved passwords", CheckStatus::Ok, "supported by this ssh")
+        }
+        Some((major, minor)) => Check::new(
+            "saved passwords",
+            CheckStatus::Warn,
+            format!("OpenSSH {}.{} is too old, 8.4 or newer is needed", major, minor),
+        ),
+        None => Check::new("saved passwords", CheckStatus::Warn, "could not determine the OpenSSH version"),
+    });
+    checks
+}
+
+fn profiles_checks() -> Vec<Check> {
+    let profiles_dir = match get_profiles_dir() {
+        Ok(profiles_dir) => profiles_dir,
+        Err(e) => return vec![Check::new("profiles directory", CheckStatus::Fail, format!("{:#}", e))],
+    };
+    let path = Path::new(&profiles_dir);
+
+    let directory = if path.is_dir() {
+        if fsutil::is_dir_writable(path) {
+            Check::new("profiles directory", CheckStatus::Ok, format!("{} (writable)", profiles_dir))
+        } else {
+            Check::new("profiles directory", CheckStatus::Fail, format!("{} is not writable", profiles_dir))
+        }
+    } else if path.exists() {
+        Check::new("profiles directory", CheckStatus::Fail, format!("{} is not a directory", profiles_dir))
+    } else {
+        Check::new(
+            "profiles directory",
+            CheckStatus::Ok,
+            format!("{} (does not exist yet, created on first save)", profiles_dir),
+        )
+    };
+
+    let profiles = match list_profiles() {
+        Ok(profiles) => {
+            let profiles = profiles.unwrap_or_default();
+            let invalid = profiles
+                .iter()
+                .filter(|profile| load_profile(profile).map(|config| !config.validate().is_empty()).unwrap_or(true))
+                .count();
+            if invalid == 0 {
+                Check::new("profiles", CheckStatus::Ok, format!("{} found", profiles.len()))
+            } else {
+                Check::new(
+                    "profiles",
+                    CheckStatus::Warn,
+                    format!("{} found, {} invalid (run `rush-tunnel validate`)", profiles.len(), invalid),
+                )
+            }
+        }
+        Err(e) => Check::new("profiles", CheckStatus::Fail, format!("{:#}", e)),
+    };
+
+    vec![directory, profiles]
+}
+
+fn parse_openssh_version(banner: &str) -> Option<(u32, u32)> {
+    let version = banner.strip_prefix("OpenSSH_")?;
+    let version = version.strip_prefix("for_Windows_").unwrap_or(version);
+    let mut numbers = version.split(|c: char| !c.is_ascii_digit());
+    let major = numbers.next()?.parse().ok()?;
+    let minor = numbers.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_openssh_version() {
+        assert_eq!(
+            parse_openssh_version("OpenSSH_9.6p1 Ubuntu-3ubuntu13, OpenSSL 3.0.13 30 Jan 2024"),
+            Some((9, 6))
+        );
+        assert_eq!(parse_openssh_version("OpenSSH_for_Windows_8.1p1, LibreSSL 3.0.2"), Some((8, 1)));
+        assert_eq!(parse_openssh_version("ARGS: -V"), None);
+        assert_eq!(parse_openssh_version("OpenSSH_8.4p1"), Some((8, 4)));
+        assert_eq!(parse_openssh_version(""), None);
+    }
+}
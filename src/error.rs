@@ -0,0 +1,107 @@
+use std::fmt;
+
+// What is wrong with a profile, as found by `SshConfig::validate`, the builder and profile name checks.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigError {
+    // One of the fields a profile can't do without, named as in the profile file (`jump_host_user`).
+    MissingField(&'static str),
+    InvalidPort { field: String, port: u16 },
+    EmptyHost(String),
+    BadProfileName { name: String, reason: &'static str },
+    // Anything else, such as two settings that can't be used together.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::MissingField(field) => write!(f, "Missing {}", field_label(field)),
+            ConfigError::InvalidPort { field, port } => write!(f, "{} {} is out of range", field, port),
+            ConfigError::EmptyHost(field) => write!(f, "{} is empty", field),
+            ConfigError::BadProfileName { name, reason } => write!(f, "invalid profile name '{}': {}", name, reason),
+            ConfigError::Invalid(problem) => write!(f, "{}", problem),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Why a profile's saved passwords couldn't be decrypted, as found by `decrypt_saved_passwords`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnlockError {
+    WrongMasterPassword,
+    // The verifier accepted the master password, yet the passwords didn't decrypt with it.
+    CorruptPasswords(String),
+}
+
+impl fmt::Display for UnlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnlockError::WrongMasterPassword => write!(f, "the master password doesn't match"),
+            UnlockError::CorruptPasswords(e) => write!(
+                f,
+                "the master password is right, but the saved passwords can't be decrypted ({}); \
+                 clear them with clear-passwords and save them again",
+                e
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UnlockError {}
+
+pub fn join_problems(problems: &[ConfigError], separator: &str) -> String {
+    problems.iter().map(ToString::to_string).collect::<Vec<_>>().join(separator)
+}
+
+fn field_label(field: &str) -> &str {
+    match field {
+        "jump_host_user" => "jump host username",
+        "jump_host" => "jump host address",
+        "target_host_user" => "target host username",
+        "target_host" => "target host address",
+        field => field,
+    }
+}
+
+// Profile names become file names in the profiles directory, so they can't point anywhere else.
+pub fn validate_profile_name(name: &str) -> Result<(), ConfigError> {
+    let bad = |reason| {
+        Err(ConfigError::BadProfileName {
+            name: name.to_string(),
+            reason,
+        })
+    };
+    if name.trim().is_empty() {
+        return bad("it is empty");
+    }
+    if name.contains(['/', '\\']) {
+        return bad("it contains a path separator");
+    }
+    if name.starts_with('.') {
+        return bad("it starts with a dot");
+    }
+    if name.chars().any(char::is_control) {
+        return bad("it contains a control character");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_profile_name() {
+        assert!(validate_profile_name("staging-db.eu").is_ok());
+        assert_eq!(
+            validate_profile_name("../prod").unwrap_err().to_string(),
+            "invalid profile name '../prod': it contains a path separator"
+        );
+        assert!(matches!(
+            validate_profile_name(" "),
+            Err(ConfigError::BadProfileName { reason: "it is empty", .. })
+        ));
+        assert!(validate_profile_name(".hidden").is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use anyhow::{bail, Context, Result};
+use schemars::schema::{Schema, SchemaObject};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+
+use crate::SshConfig;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Format> {
+        match value.to_lowercase().as_str() {
+            "toml" => Ok(Format::Toml),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "json" => Ok(Format::Json),
+            _ => bail!("unknown format '{}' (expected toml, yaml or json)", value),
+        }
+    }
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Format> {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| ext.parse().ok())
+    }
+
+    // Piped input has no extension, so the first line that isn't blank or a comment decides:
+    // `{` opens JSON, a `[table]` or a `key = value` is TOML, and anything else is YAML.
+    pub fn detect(input: &str) -> Format {
+        let Some(line) = input.lines().map(str::trim).find(|line| !line.is_empty() && !line.starts_with('#')) else {
+            return Format::Toml;
+        };
+        if line.starts_with('{') {
+            return Format::Json;
+        }
+        if line.starts_with('[') {
+            return Format::Toml;
+        }
+        match (line.find('='), line.find(':')) {
+            (Some(equals), Some(colon)) if equals < colon => Format::Toml,
+            (Some(_), None) => Format::Toml,
+            _ => Format::Yaml,
+        }
+    }
+}
+
+pub fn serialize(config: &SshConfig, format: Format) -> Result<String> {
+    // Going through a TOML value keeps every format in the same shape as the profile on disk,
+    // with unset options omitted rather than written out as nulls.
+    let value = toml::Value::try_from(config).context("Failed to serialize profile")?;
+    match format {
+        Format::Toml => toml::to_string(&value).context("Failed to serialize profile as TOML"),
+        Format::Yaml => serde_yaml::to_string(&value).context("Failed to serialize profile as YAML"),
+        Format::Json => serde_json::to_string_pretty(&value)
+            .map(|json| json + "\n")
+            .context("Failed to serialize profile as JSON"),
+    }
+}
+
+// Errors name the field they are about, since JSON and YAML messages otherwise only give a position.
+pub fn deserialize(input: &str, format: Format) -> Result<SshConfig> {
+    let name = match format {
+        Format::Toml => "TOML",
+        Format::Yaml => "YAML",
+        Format::Json => "JSON",
+    };
+    check_keys(input, format).context(format!("Failed to parse {} profile", name))?;
+    match format {
+        Format::Toml => with_field(serde_path_to_error::deserialize(toml::Deserializer::new(input)))
+            .context("Failed to parse TOML profile"),
+        Format::Yaml => with_field(serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(input)))
+            .context("Failed to parse YAML profile"),
+        Format::Json => with_field(serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(input)))
+            .context("Failed to parse JSON profile"),
+    }
+}
+
+fn with_field<E: std::error::Error + Send + Sync + 'static>(
+    result: Result<SshConfig, serde_path_to_error::Error<E>>,
+) -> Result<SshConfig> {
+    result.map_err(|err| match err.path().to_string().as_str() {
+        "." => anyhow::Error::new(err.into_inner()),
+        path => anyhow::anyhow!("{}: {}", path, err.into_inner()),
+    })
+}
+
+// Just the keys of a profile and of each of its forwards, so they can be checked before the values.
+#[derive(Deserialize)]
+struct Keys {
+    #[serde(default)]
+    forwards: Vec<BTreeMap<String, IgnoredAny>>,
+    #[serde(flatten)]
+    profile: BTreeMap<String, IgnoredAny>,
+}
+
+struct KnownKeys {
+    profile: BTreeSet<String>,
+    forward: BTreeSet<String>,
+}
+
+// serde can't reject unknown keys itself because the ssh options are flattened into the profile, and
+// a misspelt key would otherwise be dropped without a word, leaving that setting at its default.
+// Input that doesn't parse at all is left for the deserializer to report.
+pub fn check_keys(input: &str, format: Format) -> Result<()> {
+    let keys: Option<Keys> = match format {
+        Format::Toml => toml::from_str(input).ok(),
+        Format::Yaml => serde_yaml::from_str(input).ok(),
+        Format::Json => serde_json::from_str(input).ok(),
+    };
+    let Some(keys) = keys else {
+        return Ok(());
+    };
+    let known = known_keys();
+    let mut problems = unknown_keys(keys.profile.into_keys(), "", &known.profile);
+    for (i, forward) in keys.forwards.into_iter().enumerate() {
+        problems.extend(unknown_keys(forward.into_keys(), &format!("forwards[{}].", i), &known.forward));
+    }
+    if !problems.is_empty() {
+        bail!("{}", problems.join("; "));
+    }
+    Ok(())
+}
+
+fn unknown_keys(keys: impl Iterator<Item = String>, path: &str, known: &BTreeSet<String>) -> Vec<String> {
+    keys.filter(|key| !known.contains(key))
+        .map(|key| match closest_key(&key, known) {
+            Some(suggestion) => format!("unknown key `{}{}`, did you mean `{}`?", path, key, suggestion),
+            None => format!("unknown key `{}{}`", path, key),
+        })
+        .collect()
+}
+
+fn known_keys() -> &'static KnownKeys {
+    static KEYS: OnceLock<KnownKeys> = OnceLock::new();
+    KEYS.get_or_init(|| {
+        let schema = schemars::schema_for!(SshConfig);
+        let forward = match schema.definitions.get("LocalForward") {
+            Some(Schema::Object(forward)) => properties(forward),
+            _ => BTreeSet::new(),
+        };
+        KnownKeys {
+            profile: properties(&schema.schema),
+            forward,
+        }
+    })
+}
+
+fn properties(schema: &SchemaObject) -> BTreeSet<String> {
+    schema.object.as_ref().map(|object| object.properties.keys().cloned().collect()).unwrap_or_default()
+}
+
+// Only suggests keys close enough to be a typo of, about one edit in every three characters.
+fn closest_key<'a>(key: &str, known: &'a BTreeSet<String>) -> Option<&'a str> {
+    let limit = (key.chars().count() / 3).max(1);
+    known
+        .iter()
+        .map(|candidate| (edit_distance(key, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate.as_str())
+}
+
+// Levenshtein distance, one row at a time.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a_char != *b_char);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// Derived from the profile struct itself, so the schema never drifts from what `load_profile` accepts.
+pub fn profile_schema() -> Result<String> {
+    let schema = schemars::schema_for!(SshConfig);
+    serde_json::to_string_pretty(&schema).context("Failed to serialize profile schema")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_config() -> SshConfig {
+        SshConfig {
+            jump_host_user: "jumper".to_string(),
+            jump_host: "bastion.example.com".to_string(),
+            target_host_user: "deploy".to_string(),
+            target_host: "10.0.0.5".to_string(),
+            jump_port: 22,
+            target_port: 2222,
+            port_forward: Some(5432),
+            tags: vec!["prod".to_string()],
+            description: Some("prod DB, ask Sam before touching".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let config = sample_config();
+
+        let yaml = serialize(&config, Format::Yaml).unwrap();
+        let loaded = deserialize(&yaml, Format::Yaml).unwrap();
+
+        assert!(yaml.contains("jump_host: bastion.example.com"));
+        assert_eq!(loaded.description, config.description);
+        assert!(!yaml.contains("null"));
+        assert_eq!(toml::to_string(&loaded).unwrap(), toml::to_string(&config).unwrap());
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let config = sample_config();
+
+        let toml_str = serialize(&config, Format::Toml).unwrap();
+        let loaded = deserialize(&toml_str, Format::Toml).unwrap();
+
+        assert_eq!(toml::to_string(&loaded).unwrap(), toml::to_string(&config).unwrap());
+    }
+
+    #[test]
+    fn test_format_detection() {
+        assert_eq!(Format::from_path(Path::new("prod.yaml")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("prod.YML")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("prod.toml")), Some(Format::Toml));
+        assert_eq!(Format::from_path(Path::new("prod")), None);
+        assert_eq!("json".parse::<Format>().unwrap(), Format::Json);
+        assert!("ini".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(Format::detect("{\"jump_host\": \"bastion\"}"), Format::Json);
+        assert_eq!(Format::detect("# generated\n\njump_host = \"bastion:2200\"\n"), Format::Toml);
+        assert_eq!(Format::detect("[[forwards]]\nlocal_port = 8080\n"), Format::Toml);
+        assert_eq!(Format::detect("---\njump_host: bastion\n"), Format::Yaml);
+        assert_eq!(Format::detect("proxy_command: ssh -W %h:%p -o A=b gw\n"), Format::Yaml);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let config = sample_config();
+
+        let json = serialize(&config, Format::Json).unwrap();
+        let loaded = deserialize(&json, Format::Json).unwrap();
+
+        assert!(json.contains("\"jump_host\": \"bastion.example.com\""));
+        assert_eq!(toml::to_string(&loaded).unwrap(), toml::to_string(&config).unwrap());
+    }
+
+    #[test]
+    fn test_errors_name_the_field() {
+        let json = r#"{"jump_host_user": "j", "jump_host": "b", "target_host_user": "t", "target_host": "h",
+                       "jump_port": 22, "target_port": "twenty-two"}"#;
+        let err = deserialize(json, Format::Json).unwrap_err();
+        assert!(format!("{:#}", err).contains("target_port: invalid type"), "{:#}", err);
+
+        let yaml = "jump_host_user: j\njump_host: b\ntarget_host_user: t\ntarget_host: h\njump_port: 22\n\
+                    target_port: 22\nforwards:\n  - local_port: http\n";
+        let err = deserialize(yaml, Format::Yaml).unwrap_err();
+        assert!(format!("{:#}", err).contains("forwards[0].local_port"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let toml_str = "jump_host_user = \"j\"\njump_hostt = \"bastion\"\ntarget_host_user = \"t\"\n\
+                        target_host = \"h\"\ntarget_port = 22\n";
+        let err = deserialize(toml_str, Format::Toml).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "Failed to parse TOML profile: unknown key `jump_hostt`, did you mean `jump_host`?"
+        );
+
+        // Flattened options are known keys too.
+        let json = r#"{"target_host_user": "t", "target_host": "h", "target_port": 22, "compresion": true,
+                       "colour": "red"}"#;
+        let err = check_keys(json, Format::Json).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown key `colour`; unknown key `compresion`, did you mean `compression`?"
+        );
+        assert!(check_keys("target_host: h\nserver_alive_interval: 30\n", Format::Yaml).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_forward_keys_are_rejected() {
+        let toml_str = "target_host_user = \"t\"\ntarget_host = \"h\"\ntarget_port = 22\n\n\
+                        [[forwards]]\nlocal_port = 8080\nremote_port = 80\n\n\
+                        [[forwards]]\nlocal_port = 8443\nremote_port = 443\nbind_adress = \"0.0.0.0\"\n";
+        let err = deserialize(toml_str, Format::Toml).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "Failed to parse TOML profile: unknown key `forwards[1].bind_adress`, did you mean `bind_address`?"
+        );
+
+        let yaml = "target_host: h\nforwards:\n  - local_port: 8080\n    remote_port: 80\n    remote_hots: db\n";
+        let err = check_keys(yaml, Format::Yaml).unwrap_err();
+        assert_eq!(err.to_string(), "unknown key `forwards[0].remote_hots`, did you mean `remote_host`?");
+        // Keys of the profile itself aren't accepted inside a forward.
+        let json = r#"{"forwards": [{"local_port": 8080, "remote_port": 80, "tags": []}]}"#;
+        assert_eq!(check_keys(json, Format::Json).unwrap_err().to_string(), "unknown key `forwards[0].tags`");
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("jump_host", "jump_host"), 0);
+        assert_eq!(edit_distance("jump_hostt", "jump_host"), 1);
+        assert_eq!(edit_distance("tagz", "tags"), 1);
+        assert_eq!(edit_distance("", "tags"), 4);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_profile_schema() {
+        let schema: serde_json::Value = serde_json::from_str(&profile_schema().unwrap()).unwrap();
+
+        let required = schema["required"].as_array().unwrap();
+        assert!(required.contains(&"target_host".into()));
+        // Profiles that use a proxy command have no jump host.
+        assert!(!required.contains(&"jump_host".into()));
+        assert!(!required.contains(&"port_forward".into()));
+        // Flattened options appear at the top level, like they do in the profile file.
+        assert!(schema["properties"]["server_alive_interval"].is_object());
+        assert!(schema["definitions"]["LocalForward"].is_object());
+    }
+}
@@ -0,0 +1,303 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{bracket_host, is_valid_port, ConfigError};
+
+// Binding here makes a forward reachable from every machine that can reach this one.
+pub const ALL_INTERFACES: &str = "0.0.0.0";
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct LocalForward {
+    pub bind_address: Option<String>,
+    pub local_port: u16,
+    pub remote_host: Option<String>,
+    pub remote_port: u16,
+    // URL scheme for `connect --print-url`, for services that aren't plain http.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
+}
+
+impl LocalForward {
+    pub fn listen_spec(&self) -> String {
+        match &self.bind_address {
+            Some(bind_address) => format!("{}:{}", bracket_host(bind_address), self.local_port),
+            None => self.local_port.to_string(),
+        }
+    }
+
+    // The remote end of the forward is resolved on the target, so it defaults to the target itself.
+    pub fn destination(&self, target_host: &str) -> String {
+        let host = self.remote_host.as_deref().unwrap_or(target_host);
+        format!("{}:{}", bracket_host(host), self.remote_port)
+    }
+
+    // Where a client on this machine reaches the forward. A wildcard bind listens on loopback too.
+    pub fn local_host(&self) -> &str {
+        match self.bind_address.as_deref() {
+            None | Some("") | Some("*") | Some("localhost") | Some(ALL_INTERFACES) => "127.0.0.1",
+            Some("::") => "::1",
+            Some(address) => address,
+        }
+    }
+
+    // Without a scheme hint, a forward to port 443 is assumed to be https and anything else http.
+    pub fn url(&self) -> String {
+        let scheme = match self.scheme.as_deref() {
+            Some(scheme) => scheme,
+            None if self.remote_port == 443 => "https",
+            None => "http",
+        };
+        format!("{}://{}:{}", scheme, bracket_host(self.local_host()), self.local_port)
+    }
+}
+
+// Accepts ssh's `-L` syntax, `[bind_address:]port:host:hostport`, with IPv6 hosts in brackets.
+impl FromStr for LocalForward {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<LocalForward> {
+        let parts = split_forward_spec(spec);
+        let (bind_address, local_port, remote_host, remote_port) = match parts.as_slice() {
+            [local_port, remote_host, remote_port] => (None, local_port, remote_host, remote_port),
+            [bind_address, local_port, remote_host, remote_port] => {
+                (Some(bind_address.to_string()), local_port, remote_host, remote_port)
+            }
+            _ => bail!("invalid forward '{}' (expected [bind_address:]port:host:hostport)", spec),
+        };
+        if remote_host.is_empty() {
+            bail!("invalid forward '{}': remote host is empty", spec);
+        }
+
+        Ok(LocalForward {
+            bind_address,
+            local_port: parse_forward_port(local_port, spec)?,
+            remote_host: Some(remote_host.to_string()),
+            remote_port: parse_forward_port(remote_port, spec)?,
+            scheme: None,
+        })
+    }
+}
+
+pub fn forward_problems(forward: &LocalForward) -> Vec<ConfigError> {
+    let mut problems = Vec::new();
+    if !is_valid_port(forward.local_port) {
+        problems.push(ConfigError::InvalidPort {
+            field: "forward local_port".to_string(),
+            port: 0,
+        });
+    }
+    if !is_valid_port(forward.remote_port) {
+        problems.push(ConfigError::InvalidPort {
+            field: format!("forward {} remote_port", forward.local_port),
+            port: 0,
+        });
+    }
+    if forward.remote_host.as_deref().is_some_and(|host| host.trim().is_empty()) {
+        problems.push(ConfigError::EmptyHost(format!("forward {} remote_host", forward.local_port)));
+    }
+    if let Some(scheme) = &forward.scheme {
+        let valid = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+        if !valid {
+            problems.push(ConfigError::Invalid(format!(
+                "forward {} scheme '{}' is not a URL scheme such as https",
+                forward.local_port, scheme
+            )));
+        }
+    }
+    problems
+}
+
+// ssh refuses to start when two forwards listen on the same local port.
+pub fn duplicate_local_ports(forwards: &[LocalForward]) -> Vec<u16> {
+    let mut seen = Vec::new();
+    let mut duplicates = Vec::new();
+    for forward in forwards {
+        if seen.contains(&forward.local_port) {
+            if !duplicates.contains(&forward.local_port) {
+                duplicates.push(forward.local_port);
+            }
+        } else {
+            seen.push(forward.local_port);
+        }
+    }
+    duplicates
+}
+
+pub fn local_port_problem(port: u16, used: &[u16]) -> Option<String> {
+    if !is_valid_port(port) {
+        Some("Please enter a valid port number between 1 and 65535".to_string())
+    } else if used.contains(&port) {
+        Some(format!("Local port {} is already used by another forward", port))
+    } else {
+        None
+    }
+}
+
+pub const PRIVILEGED_PORT_LIMIT: u16 = 1024;
+
+// The lowest local port this process may listen on, or None when it may use any. Root can bind
+// anything, Linux can lower the limit with a sysctl, and macOS and Windows have none.
+pub fn lowest_bindable_port() -> Option<u16> {
+    #[cfg(unix)]
+    {
+        // SAFETY: geteuid has no preconditions and can't fail.
+        if unsafe { libc::geteuid() } == 0 {
+            return None;
+        }
+    }
+    if cfg!(any(target_os = "macos", windows)) {
+        return None;
+    }
+    let lowest = std::fs::read_to_string("/proc/sys/net/ipv4/ip_unprivileged_port_start")
+        .ok()
+        .and_then(|start| start.trim().parse::<u16>().ok())
+        .unwrap_or(PRIVILEGED_PORT_LIMIT);
+    (lowest > 1).then_some(lowest)
+}
+
+pub fn privileged_ports(forwards: &[LocalForward], lowest_bindable: u16) -> Vec<u16> {
+    forwards
+        .iter()
+        .map(|forward| forward.local_port)
+        .filter(|port| *port < lowest_bindable)
+        .collect()
+}
+
+fn split_forward_spec(spec: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_brackets = false;
+    for c in spec.chars() {
+        match c {
+            '[' => in_brackets = true,
+            ']' => in_brackets = false,
+            ':' if !in_brackets => parts.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    parts.push(current);
+    parts
+}
+
+fn parse_forward_port(port: &str, spec: &str) -> Result<u16> {
+    let port = port
+        .parse::<u16>()
+        .context(format!("invalid port '{}' in forward '{}'", port, spec))?;
+    if !is_valid_port(port) {
+        bail!("invalid port 0 in forward '{}'", spec);
+    }
+    Ok(port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_forward() {
+        let forward: LocalForward = "5432:internal-db:5432".parse().unwrap();
+
+        assert_eq!(forward.bind_address, None);
+        assert_eq!(forward.local_port, 5432);
+        assert_eq!(forward.remote_host.as_deref(), Some("internal-db"));
+        assert_eq!(forward.remote_port, 5432);
+    }
+
+    #[test]
+    fn test_parse_forward_with_bind_address_and_ipv6() {
+        let forward: LocalForward = "[::1]:8080:[fd00::5]:80".parse().unwrap();
+
+        assert_eq!(forward.listen_spec(), "[::1]:8080");
+        assert_eq!(forward.destination("10.0.0.5"), "[fd00::5]:80");
+    }
+
+    #[test]
+    fn test_forward_problems() {
+        let mut forward: LocalForward = "8080:web:80".parse().unwrap();
+        assert!(forward_problems(&forward).is_empty());
+
+        forward.remote_port = 0;
+        forward.remote_host = Some(" ".to_string());
+        let problems = forward_problems(&forward);
+        assert_eq!(
+            problems[0],
+            ConfigError::InvalidPort {
+                field: "forward 8080 remote_port".to_string(),
+                port: 0
+            }
+        );
+        assert_eq!(
+            problems.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec!["forward 8080 remote_port 0 is out of range", "forward 8080 remote_host is empty"]
+        );
+    }
+
+    #[test]
+    fn test_url() {
+        let mut forward: LocalForward = "8080:web:80".parse().unwrap();
+        assert_eq!(forward.url(), "http://127.0.0.1:8080");
+
+        forward.bind_address = Some(ALL_INTERFACES.to_string());
+        forward.remote_port = 443;
+        assert_eq!(forward.url(), "https://127.0.0.1:8080");
+
+        forward.bind_address = Some("::1".to_string());
+        forward.scheme = Some("grpc".to_string());
+        assert_eq!(forward.url(), "grpc://[::1]:8080");
+    }
+
+    #[test]
+    fn test_forward_problems_rejects_bad_scheme() {
+        let mut forward: LocalForward = "8080:web:80".parse().unwrap();
+        forward.scheme = Some("git+ssh".to_string());
+        assert!(forward_problems(&forward).is_empty());
+
+        forward.scheme = Some("https://".to_string());
+        assert_eq!(
+            forward_problems(&forward)[0].to_string(),
+            "forward 8080 scheme 'https://' is not a URL scheme such as https"
+        );
+    }
+
+    #[test]
+    fn test_duplicate_local_ports() {
+        let forwards: Vec<LocalForward> = ["8080:web:80", "5432:db:5432", "8080:other:80", "8080:third:80"]
+            .iter()
+            .map(|spec| spec.parse().unwrap())
+            .collect();
+
+        assert_eq!(duplicate_local_ports(&forwards), vec![8080]);
+        assert!(duplicate_local_ports(&forwards[..2]).is_empty());
+    }
+
+    #[test]
+    fn test_privileged_ports() {
+        let forwards: Vec<LocalForward> =
+            ["80:web:80", "8080:web:80", "443:web:443"].iter().map(|spec| spec.parse().unwrap()).collect();
+
+        assert_eq!(privileged_ports(&forwards, PRIVILEGED_PORT_LIMIT), vec![80, 443]);
+        assert_eq!(privileged_ports(&forwards, 80), Vec::<u16>::new());
+    }
+
+    #[test]
+    fn test_local_port_problem() {
+        assert_eq!(local_port_problem(8080, &[5432]), None);
+        assert!(local_port_problem(0, &[]).is_some());
+        assert_eq!(
+            local_port_problem(5432, &[5432]).as_deref(),
+            Some("Local port 5432 is already used by another forward")
+        );
+    }
+
+    #[test]
+    fn test_parse_forward_rejects_bad_specs() {
+        assert!("8080".parse::<LocalForward>().is_err());
+        assert!("0:db:5432".parse::<LocalForward>().is_err());
+        assert!("8080::5432".parse::<LocalForward>().is_err());
+        assert!("8080:db:http".parse::<LocalForward>().is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+// Profiles can hold encrypted passwords, so everything under the profiles directory is readable
+// by its owner only.
+#[cfg(unix)]
+const PRIVATE_FILE_MODE: u32 = 0o600;
+#[cfg(unix)]
+const PRIVATE_DIR_MODE: u32 = 0o700;
+
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    fs::create_dir_all(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(PRIVATE_DIR_MODE))?;
+    }
+    Ok(())
+}
+
+// Writes go to a temporary file next to the target and are renamed over it, so a crash leaves
+// either the old or the new contents, never a truncated file. `fs::rename` replaces an existing
+// target on Windows as well (MoveFileEx with MOVEFILE_REPLACE_EXISTING).
+pub fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp_path = temp_path_for(path);
+    let result = write_and_sync(&temp_path, contents).and_then(|()| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+pub fn is_dir_writable(path: &Path) -> bool {
+    let probe = path.join(format!(".write-probe.{}.tmp", std::process::id()));
+    let writable = create_private_file(&probe).is_ok();
+    let _ = fs::remove_file(&probe);
+    writable
+}
+
+// What `path` reports about the profiles directory, for people setting it up and for scripts.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DirStatus {
+    pub path: String,
+    pub exists: bool,
+    pub writable: bool,
+}
+
+impl DirStatus {
+    pub fn of(path: &Path) -> DirStatus {
+        DirStatus {
+            path: path.to_string_lossy().to_string(),
+            exists: path.exists(),
+            writable: path.is_dir() && is_dir_writable(path),
+        }
+    }
+}
+
+fn write_and_sync(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = create_private_file(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+fn create_private_file(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(PRIVATE_FILE_MODE);
+        // The mode only applies to newly created files, so a leftover temp file is fixed up too.
+        let file = options.open(path)?;
+        file.set_permissions(fs::Permissions::from_mode(PRIVATE_FILE_MODE))?;
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    options.open(path)
+}
+
+fn temp_path_for(path: &Path) -> PathBuf {
+    let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", file_name, std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_private_replaces_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("prod.toml");
+        fs::write(&path, "old").unwrap();
+
+        write_private(&path, b"new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_write_private_cleans_up_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("prod.toml");
+        fs::create_dir(&target).unwrap();
+
+        assert!(write_private(&target, b"new").is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_is_dir_writable() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(is_dir_writable(dir.path()));
+        assert!(!is_dir_writable(&dir.path().join("missing")));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_dir_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles_dir = dir.path().join(".rush-tunnel");
+
+        let status = DirStatus::of(&profiles_dir);
+        assert!(!status.exists && !status.writable);
+        assert_eq!(status.path, profiles_dir.to_string_lossy());
+
+        create_private_dir(&profiles_dir).unwrap();
+        assert!(DirStatus::of(&profiles_dir).writable);
+
+        // A file where the directory should be exists, but can't hold profiles.
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let status = DirStatus::of(&file);
+        assert!(status.exists && !status.writable);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_private_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let profiles_dir = dir.path().join(".rush-tunnel");
+        let path = profiles_dir.join("prod.toml");
+        create_private_dir(&profiles_dir).unwrap();
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, b"new").unwrap();
+
+        assert_eq!(fs::metadata(&profiles_dir).unwrap().permissions().mode() & 0o777, 0o700);
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
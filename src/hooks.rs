@@ -0,0 +1,80 @@
+use std::process::Command;
+
+use tracing::{info, warn};
+
+use crate::{style, SshConfig};
+
+// What a hook can see of the profile, since it runs as a plain shell command without it.
+pub fn hook_env(config: &SshConfig) -> Vec<(&'static str, String)> {
+    let mut env = Vec::new();
+    if !config.jump_host.is_empty() {
+        env.push(("RUSH_JUMP_USER", config.jump_host_user.clone()));
+        env.push(("RUSH_JUMP_HOST", config.jump_host.clone()));
+        env.push(("RUSH_JUMP_PORT", config.jump_port.to_string()));
+    }
+    env.push(("RUSH_TARGET_USER", config.target_host_user.clone()));
+    env.push(("RUSH_TARGET_HOST", config.target_host.clone()));
+    env.push(("RUSH_TARGET_PORT", config.target_port.to_string()));
+    let ports: Vec<String> = config.local_forwards().iter().map(|forward| forward.local_port.to_string()).collect();
+    env.push(("RUSH_LOCAL_PORTS", ports.join(",")));
+    env
+}
+
+// A failing hook is reported and otherwise ignored, so it never takes the tunnel down with it.
+pub fn run_hook(name: &str, command: &str, env: &[(&str, String)], quiet: bool) {
+    info!(hook = name, command, "running hook");
+    let failure = match shell(command).envs(env.iter().map(|(key, value)| (*key, value))).status() {
+        Ok(status) if status.success() => return,
+        Ok(status) => format!("exited with {}", status),
+        Err(e) => format!("could not be started: {}", e),
+    };
+    warn!(hook = name, command, failure, "hook failed");
+    if !quiet {
+        eprintln!("{}", style::warning(&format!("Warning: the {} hook {}", name, failure)));
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_env() {
+        let config = SshConfig::builder()
+            .jump_host_user("jumper")
+            .jump_host("bastion")
+            .target_host_user("deploy")
+            .target_host("10.0.0.5")
+            .target_port(2222)
+            .port_forward(5432)
+            .build()
+            .unwrap();
+
+        let env = hook_env(&config);
+
+        assert!(env.contains(&("RUSH_JUMP_HOST", "bastion".to_string())));
+        assert!(env.contains(&("RUSH_TARGET_PORT", "2222".to_string())));
+        assert!(env.contains(&("RUSH_LOCAL_PORTS", "5432".to_string())));
+
+        let proxied = SshConfig {
+            jump_host: String::new(),
+            proxy_command: Some("cloudflared access ssh --hostname %h".to_string()),
+            ..config
+        };
+        assert!(!hook_env(&proxied).iter().any(|(key, _)| key.starts_with("RUSH_JUMP_")));
+    }
+}
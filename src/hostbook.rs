@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{get_profiles_dir, SshConfig, DEFAULT_SSH_PORT};
+
+// Jump hosts shared by several profiles, which name them with `jump_ref`.
+pub const HOST_BOOK_FILE: &str = "hosts.toml";
+
+// The file isn't encrypted, so a password is only ever referenced by the file holding it.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BookHost {
+    pub user: String,
+    pub address: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub password_file: Option<String>,
+}
+
+fn default_port() -> u16 {
+    DEFAULT_SSH_PORT
+}
+
+pub type HostBook = BTreeMap<String, BookHost>;
+
+pub fn load() -> Result<HostBook> {
+    let profiles_dir = get_profiles_dir()?;
+    let path = Path::new(&profiles_dir).join(HOST_BOOK_FILE);
+    debug!(path = %path.display(), exists = path.exists(), "loading host book");
+    if !path.exists() {
+        return Ok(HostBook::new());
+    }
+    let toml_str = fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+    toml::from_str(&toml_str).context(format!("Failed to parse {}", path.display()))
+}
+
+// The book wins over whatever the profile has inline, so a host changed in the book changes every
+// profile that names it, even ones saved after an earlier resolution.
+pub fn resolve(config: &mut SshConfig, book: &HostBook) -> Result<()> {
+    let Some(name) = &config.jump_ref else {
+        return Ok(());
+    };
+    let host = book
+        .get(name)
+        .with_context(|| format!("jump_ref '{}' is not defined in {}", name, HOST_BOOK_FILE))?;
+    config.jump_host_user = host.user.clone();
+    config.jump_host = host.address.clone();
+    config.jump_port = host.port;
+    if host.password_file.is_some() {
+        config.jump_password_file = host.password_file.clone();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book() -> HostBook {
+        toml::from_str(
+            "[bastion]\nuser = \"jumper\"\naddress = \"bastion.example.com\"\nport = 2222\n\
+             password_file = \"~/.bastion.pass\"\n\n[bastion-eu]\nuser = \"jumper\"\naddress = \"eu.example.com\"\n",
+        )
+        .unwrap()
+    }
+
+    fn profile(toml_str: &str) -> SshConfig {
+        let target = "target_host_user = \"deploy\"\ntarget_host = \"10.0.0.5\"\ntarget_port = 22\n";
+        toml::from_str(&format!("{}{}", toml_str, target)).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_jump_ref() {
+        let mut config = profile("jump_ref = \"bastion\"\n");
+
+        resolve(&mut config, &book()).unwrap();
+
+        assert_eq!(config.jump_host_user, "jumper");
+        assert_eq!(config.jump_host, "bastion.example.com");
+        assert_eq!(config.jump_port, 2222);
+        assert_eq!(config.jump_password_file.as_deref(), Some("~/.bastion.pass"));
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn test_book_wins_over_inline_values() {
+        let mut config = profile(
+            "jump_ref = \"bastion-eu\"\njump_host_user = \"old\"\njump_host = \"old-bastion\"\njump_port = 2200\n\
+             jump_password_file = \"~/.jump.pass\"\n",
+        );
+
+        resolve(&mut config, &book()).unwrap();
+
+        assert_eq!(config.jump_host, "eu.example.com");
+        assert_eq!(config.jump_port, 22);
+        // The book has no password file for this host, so the profile's own is kept.
+        assert_eq!(config.jump_password_file.as_deref(), Some("~/.jump.pass"));
+    }
+
+    #[test]
+    fn test_unknown_jump_ref() {
+        let mut config = profile("jump_ref = \"nope\"\n");
+
+        let err = resolve(&mut config, &book()).unwrap_err();
+
+        assert_eq!(err.to_string(), "jump_ref 'nope' is not defined in hosts.toml");
+        let mut inline = profile("jump_host_user = \"j\"\njump_host = \"b\"\n");
+        resolve(&mut inline, &HostBook::new()).unwrap();
+        assert_eq!(inline.jump_host, "b");
+    }
+}
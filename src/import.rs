@@ -0,0 +1,562 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use tracing::debug;
+
+use crate::config::SshOptions;
+use crate::forward::LocalForward;
+use crate::paths::expand_path;
+use crate::{join_problems, split_host_port, SshConfig, DEFAULT_SSH_PORT};
+
+// The same limit ssh applies. Cycles are also caught directly, so this only bounds deep chains.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// Directives keep ssh's own order: for each keyword the first value from a matching block wins.
+struct Block {
+    patterns: Vec<String>,
+    directives: Vec<(String, String)>,
+}
+
+pub struct FileSummary {
+    pub path: PathBuf,
+    pub hosts: usize,
+}
+
+pub struct ParsedSshConfig {
+    blocks: Vec<Block>,
+    pub files: Vec<FileSummary>,
+    pub warnings: Vec<String>,
+}
+
+// Relative `Include` paths are resolved against `include_dir`, which ssh fixes at ~/.ssh for the
+// user config whichever file is being read.
+pub fn read_ssh_config(path: &Path, include_dir: &Path) -> Result<ParsedSshConfig> {
+    let mut parsed = ParsedSshConfig {
+        // Directives before the first `Host` line apply to every host.
+        blocks: vec![Block {
+            patterns: vec!["*".to_string()],
+            directives: Vec::new(),
+        }],
+        files: Vec::new(),
+        warnings: Vec::new(),
+    };
+    let contents = fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+    parse_file(path, &contents, include_dir, &mut vec![canonical(path)], &mut parsed);
+    Ok(parsed)
+}
+
+fn parse_file(
+    path: &Path,
+    contents: &str,
+    include_dir: &Path,
+    stack: &mut Vec<PathBuf>,
+    parsed: &mut ParsedSshConfig,
+) {
+    let file_index = parsed.files.len();
+    parsed.files.push(FileSummary {
+        path: path.to_path_buf(),
+        hosts: 0,
+    });
+    for line in contents.lines() {
+        let Some((keyword, value)) = split_directive(line) else {
+            continue;
+        };
+        match keyword.as_str() {
+            "host" => {
+                let patterns = split_args(&value);
+                parsed.files[file_index].hosts += patterns.iter().filter(|pattern| is_concrete(pattern)).count();
+                parsed.blocks.push(Block {
+                    patterns,
+                    directives: Vec::new(),
+                });
+            }
+            // Match conditions depend on the connection itself, so their directives are left out.
+            "match" => parsed.blocks.push(Block {
+                patterns: Vec::new(),
+                directives: Vec::new(),
+            }),
+            "include" => {
+                for pattern in split_args(&value) {
+                    include(&pattern, include_dir, stack, parsed);
+                }
+            }
+            _ => parsed.blocks.last_mut().unwrap().directives.push((keyword, value)),
+        }
+    }
+}
+
+fn include(pattern: &str, include_dir: &Path, stack: &mut Vec<PathBuf>, parsed: &mut ParsedSshConfig) {
+    let pattern = match expand_path(pattern) {
+        Ok(pattern) => pattern,
+        Err(e) => {
+            parsed.warnings.push(format!("Include {}: {:#}", pattern, e));
+            return;
+        }
+    };
+    let pattern = if pattern.is_relative() { include_dir.join(pattern) } else { pattern };
+    // Like ssh, a pattern that matches nothing is not an error.
+    for path in expand_glob(&pattern) {
+        let canonical = canonical(&path);
+        if stack.contains(&canonical) {
+            parsed.warnings.push(format!("{}: skipped, it is already being read (include cycle)", path.display()));
+            continue;
+        }
+        if stack.len() > MAX_INCLUDE_DEPTH {
+            parsed.warnings.push(format!(
+                "{}: skipped, includes are nested more than {} deep",
+                path.display(),
+                MAX_INCLUDE_DEPTH
+            ));
+            continue;
+        }
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                parsed.warnings.push(format!("{}: {}", path.display(), e));
+                continue;
+            }
+        };
+        debug!(path = %path.display(), depth = stack.len(), "reading included ssh config");
+        stack.push(canonical);
+        parse_file(&path, &contents, include_dir, stack, parsed);
+        stack.pop();
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Expands `*` and `?` in any component, as ssh's glob(3) call does, in sorted order.
+fn expand_glob(pattern: &Path) -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let name = component.as_os_str().to_string_lossy();
+        let is_pattern = matches!(component, Component::Normal(_)) && name.contains(['*', '?']);
+        if !is_pattern {
+            for candidate in &mut candidates {
+                candidate.push(component);
+            }
+            continue;
+        }
+        let mut matches = Vec::new();
+        for candidate in &candidates {
+            let Ok(entries) = fs::read_dir(candidate) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                // Hidden entries only match a pattern that asks for them, as in a shell.
+                if file_name.starts_with('.') && !name.starts_with('.') {
+                    continue;
+                }
+                if wildcard_match(&name, &file_name) {
+                    matches.push(entry.path());
+                }
+            }
+        }
+        matches.sort();
+        candidates = matches;
+    }
+    candidates.into_iter().filter(|path| path.is_file()).collect()
+}
+
+// `Keyword value`, `Keyword=value` and `Keyword = value` are all accepted, as by ssh.
+fn split_directive(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let end = line.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(line.len());
+    let keyword = line[..end].to_lowercase();
+    let rest = line[end..].trim_start();
+    let value = rest.strip_prefix('=').unwrap_or(rest).trim();
+    Some((keyword, value.to_string()))
+}
+
+fn split_args(value: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut in_arg = false;
+    for c in value.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern.split_first() {
+            None => text.is_empty(),
+            Some(('*', rest)) => (0..=text.len()).any(|skip| matches(rest, &text[skip..])),
+            Some(('?', rest)) => !text.is_empty() && matches(rest, &text[1..]),
+            Some((c, rest)) => text.first() == Some(c) && matches(rest, &text[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    matches(&pattern, &text)
+}
+
+fn is_concrete(pattern: &str) -> bool {
+    !pattern.contains(['*', '?']) && !pattern.starts_with('!')
+}
+
+// A negated pattern rules the host out even when another pattern in the block matches it.
+fn block_matches(patterns: &[String], host: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard_match(negated, host) => return false,
+            Some(_) => {}
+            None => matched |= wildcard_match(pattern, host),
+        }
+    }
+    matched
+}
+
+#[derive(Default)]
+struct HostSettings {
+    values: HashMap<String, String>,
+    // ProxyJump and ProxyCommand exclude each other, so whichever comes first wins.
+    proxy: Option<(String, String)>,
+    local_forwards: Vec<String>,
+}
+
+impl HostSettings {
+    fn get(&self, keyword: &str) -> Option<&str> {
+        self.values.get(keyword).map(String::as_str)
+    }
+}
+
+impl ParsedSshConfig {
+    fn settings(&self, host: &str) -> HostSettings {
+        let mut settings = HostSettings::default();
+        for block in self.blocks.iter().filter(|block| block_matches(&block.patterns, host)) {
+            for (keyword, value) in &block.directives {
+                match keyword.as_str() {
+                    "localforward" => settings.local_forwards.push(value.clone()),
+                    "proxyjump" | "proxycommand" => {
+                        settings.proxy.get_or_insert_with(|| (keyword.clone(), value.clone()));
+                    }
+                    _ => {
+                        settings.values.entry(keyword.clone()).or_insert_with(|| value.clone());
+                    }
+                }
+            }
+        }
+        settings
+    }
+
+    // Every concrete alias from a `Host` line, in file order. Wildcard patterns only contribute
+    // settings to the aliases they match.
+    pub fn host_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for pattern in self.blocks.iter().flat_map(|block| &block.patterns) {
+            if is_concrete(pattern) && !names.contains(pattern) {
+                names.push(pattern.clone());
+            }
+        }
+        names
+    }
+
+    pub fn to_profile(&self, host: &str) -> Result<SshConfig> {
+        if host.contains(['/', '\\']) || host.starts_with('.') {
+            bail!("'{}' can't be used as a profile name", host);
+        }
+        let settings = self.settings(host);
+        // ssh ignores LocalCommand unless it is permitted, so an unpermitted one isn't carried over.
+        let permit_local_command =
+            parse_yes_no("PermitLocalCommand", settings.get("permitlocalcommand"))?.unwrap_or(false);
+        let mut config = SshConfig {
+            target_host: settings
+                .get("hostname")
+                .map(|name| name.replace("%h", host).replace("%%", "%"))
+                .unwrap_or_else(|| host.to_string()),
+            target_host_user: user_or_local(settings.get("user"))?,
+            target_port: parse_port(settings.get("port"))?,
+            jump_port: DEFAULT_SSH_PORT,
+            agent_forwarding: parse_yes_no("ForwardAgent", settings.get("forwardagent"))?.unwrap_or(false),
+            local_command: settings.get("localcommand").filter(|_| permit_local_command).map(str::to_string),
+            options: parse_options(&settings)?,
+            ..Default::default()
+        };
+        match &settings.proxy {
+            Some((keyword, value)) if keyword == "proxycommand" && !value.eq_ignore_ascii_case("none") => {
+                config.proxy_command = Some(value.clone());
+            }
+            Some((keyword, value)) if keyword == "proxyjump" && !value.eq_ignore_ascii_case("none") => {
+                if value.contains(',') {
+                    bail!("ProxyJump {} chains several jump hosts, but a profile has one", value);
+                }
+                let (user, jump_host, port) = split_jump_spec(value)?;
+                // The jump host is often an alias defined in the same config.
+                let jump = self.settings(&jump_host);
+                config.jump_host = jump.get("hostname").map(str::to_string).unwrap_or(jump_host);
+                config.jump_host_user = user_or_local(user.as_deref().or(jump.get("user")))?;
+                config.jump_port = match port {
+                    Some(port) => port,
+                    None => parse_port(jump.get("port"))?,
+                };
+            }
+            _ => bail!("no ProxyJump or ProxyCommand, so there is no jump host to tunnel through"),
+        }
+        for forward in &settings.local_forwards {
+            let spec = split_args(forward).join(":");
+            let forward: LocalForward = spec.parse().context(format!("unsupported LocalForward {}", forward))?;
+            config.forwards.push(forward);
+        }
+
+        let problems = config.validate();
+        if !problems.is_empty() {
+            bail!("{}", join_problems(&problems, ", "));
+        }
+        Ok(config)
+    }
+}
+
+// Connection settings come from the ssh config, while what it can't hold (saved passwords and key
+// passphrase, password files, tags and the description) is kept. Options it leaves unset keep the profile's values, and so do
+// the forwards when it has no LocalForward at all. A pre_proxy is kept unless the host now goes
+// through a ProxyCommand, which it can't be combined with.
+pub fn merge_profile(existing: &SshConfig, imported: SshConfig) -> SshConfig {
+    let existing = existing.clone();
+    SshConfig {
+        schema_version: existing.schema_version,
+        forwards: if imported.forwards.is_empty() { existing.forwards } else { imported.forwards },
+        tags: existing.tags,
+        description: existing.description,
+        pre_proxy: existing.pre_proxy.filter(|_| imported.proxy_command.is_none()),
+        options: imported.options.or(existing.options),
+        enc1: existing.enc1,
+        enc2: existing.enc2,
+        verifier: existing.verifier,
+        enc_key_passphrase: existing.enc_key_passphrase,
+        jump_password_file: existing.jump_password_file,
+        target_password_file: existing.target_password_file,
+        ..imported
+    }
+}
+
+// ssh logs in as the local user when no User is given.
+fn user_or_local(user: Option<&str>) -> Result<String> {
+    match user {
+        Some(user) => Ok(user.to_string()),
+        None => std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .context("no User is set and the local user name is unknown"),
+    }
+}
+
+fn parse_port(port: Option<&str>) -> Result<u16> {
+    match port {
+        Some(port) => port.parse().context(format!("invalid Port {}", port)),
+        None => Ok(DEFAULT_SSH_PORT),
+    }
+}
+
+// Accepts `[user@]host[:port]`, with IPv6 hosts in brackets, and the `ssh://` form of the same.
+fn split_jump_spec(spec: &str) -> Result<(Option<String>, String, Option<u16>)> {
+    let spec = spec.strip_prefix("ssh://").unwrap_or(spec);
+    let (user, host_port) = match spec.rsplit_once('@') {
+        Some((user, host_port)) => (Some(user.to_string()), host_port),
+        None => (None, spec),
+    };
+    let (host, port) = split_host_port(host_port).context(format!("invalid ProxyJump {}", spec))?;
+    Ok((user, host, port))
+}
+
+fn parse_yes_no(keyword: &str, value: Option<&str>) -> Result<Option<bool>> {
+    match value.map(str::to_lowercase).as_deref() {
+        None => Ok(None),
+        Some("yes") => Ok(Some(true)),
+        Some("no") => Ok(Some(false)),
+        Some(other) => bail!("{} {} is not yes or no", keyword, other),
+    }
+}
+
+fn parse_options(settings: &HostSettings) -> Result<SshOptions> {
+    let number = |keyword: &str, value: Option<&str>| {
+        value
+            .map(|value| value.parse::<u32>().context(format!("invalid {} {}", keyword, value)))
+            .transpose()
+    };
+    Ok(SshOptions {
+        server_alive_interval: number("ServerAliveInterval", settings.get("serveraliveinterval"))?,
+        compression: parse_yes_no("Compression", settings.get("compression"))?,
+        strict_host_key_checking: settings.get("stricthostkeychecking").map(str::to_string),
+        auth_methods: settings
+            .get("preferredauthentications")
+            .map(|methods| methods.split(',').map(str::to_string).collect()),
+        identity_agent: settings.get("identityagent").map(str::to_string),
+        identity_file: settings.get("identityfile").map(str::to_string),
+        password_prompts: number("NumberOfPasswordPrompts", settings.get("numberofpasswordprompts"))?,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> ParsedSshConfig {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config");
+        fs::write(&path, contents).unwrap();
+        read_ssh_config(&path, dir.path()).unwrap()
+    }
+
+    #[test]
+    fn test_to_profile_resolves_jump_alias() {
+        let parsed = parse(
+            "Host bastion\n    HostName bastion.example.com\n    User jumper\n    Port 2200\n\n\
+             Host db db-alias\n    HostName 10.0.0.5\n    User=deploy\n    ProxyJump bastion\n    \
+             LocalForward 15432 localhost:5432\n    ForwardAgent yes\n\n\
+             Host *\n    ServerAliveInterval 30\n    User nobody\n",
+        );
+
+        assert_eq!(parsed.host_names(), vec!["bastion", "db", "db-alias"]);
+        let config = parsed.to_profile("db").unwrap();
+        assert_eq!(config.target_host, "10.0.0.5");
+        assert_eq!(config.target_host_user, "deploy");
+        assert_eq!(config.jump_host, "bastion.example.com");
+        assert_eq!(config.jump_host_user, "jumper");
+        assert_eq!(config.jump_port, 2200);
+        assert_eq!(config.forwards, vec!["15432:localhost:5432".parse::<LocalForward>().unwrap()]);
+        assert!(config.agent_forwarding);
+        assert_eq!(config.options.server_alive_interval, Some(30));
+        assert_eq!(parsed.to_profile("db-alias").unwrap().target_host, "10.0.0.5");
+    }
+
+    #[test]
+    fn test_to_profile_skips_hosts_without_jump() {
+        let parsed = parse("Host plain\n    User me\n\nHost chained\n    User me\n    ProxyJump a,b\n");
+
+        assert!(parsed.to_profile("plain").unwrap_err().to_string().contains("no ProxyJump or ProxyCommand"));
+        assert!(parsed.to_profile("chained").unwrap_err().to_string().contains("several jump hosts"));
+    }
+
+    #[test]
+    fn test_to_profile_with_proxy_command() {
+        let parsed =
+            parse("Host app\n    User me\n    ProxyCommand cloudflared access ssh --hostname %h\n    ProxyJump x\n");
+
+        let config = parsed.to_profile("app").unwrap();
+        assert_eq!(config.proxy_command.as_deref(), Some("cloudflared access ssh --hostname %h"));
+        assert!(config.jump_host.is_empty());
+    }
+
+    #[test]
+    fn test_merge_profile_keeps_secrets_tags_and_description() {
+        let parsed = parse("Host db\n    HostName 10.0.0.9\n    User deploy\n    ProxyJump jumper@bastion\n");
+        let mut existing = parsed.to_profile("db").unwrap();
+        existing.schema_version = 1;
+        existing.target_host = "10.0.0.5".to_string();
+        existing.tags = vec!["prod".to_string()];
+        existing.description = Some("ask Sam first".to_string());
+        existing.enc1 = Some("secret".to_string());
+        existing.target_password_file = Some("~/db.pass".to_string());
+        existing.options.compression = Some(true);
+        existing.forwards = vec!["15432:localhost:5432".parse().unwrap()];
+
+        let merged = merge_profile(&existing, parsed.to_profile("db").unwrap());
+
+        assert_eq!(merged.target_host, "10.0.0.9");
+        assert_eq!(merged.tags, vec!["prod"]);
+        assert_eq!(merged.description.as_deref(), Some("ask Sam first"));
+        assert_eq!(merged.enc1.as_deref(), Some("secret"));
+        assert_eq!(merged.target_password_file.as_deref(), Some("~/db.pass"));
+        assert_eq!(merged.options.compression, Some(true));
+        assert_eq!(merged.forwards, existing.forwards);
+        assert_eq!(merge_profile(&merged, parsed.to_profile("db").unwrap()), merged);
+    }
+
+    #[test]
+    fn test_negated_patterns() {
+        assert!(block_matches(&["*.internal".to_string()], "DB.internal"));
+        assert!(!block_matches(&["*.internal".to_string(), "!db.internal".to_string()], "db.internal"));
+        assert!(!block_matches(&["!db".to_string()], "web"));
+    }
+
+    #[test]
+    fn test_split_jump_spec() {
+        assert_eq!(
+            split_jump_spec("jumper@[2001:db8::1]:2200").unwrap(),
+            (Some("jumper".to_string()), "2001:db8::1".to_string(), Some(2200))
+        );
+        assert_eq!(split_jump_spec("ssh://bastion:22").unwrap(), (None, "bastion".to_string(), Some(22)));
+        assert_eq!(split_jump_spec("bastion").unwrap(), (None, "bastion".to_string(), None));
+    }
+
+    #[test]
+    fn test_include_follows_globs_and_counts_hosts() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("config.d")).unwrap();
+        fs::write(dir.path().join("config.d/work"), "Host work1 work2\n    User me\n    ProxyJump gw\n").unwrap();
+        fs::write(dir.path().join("config.d/home"), "Host nas\n    User me\n    ProxyJump gw\n").unwrap();
+        fs::write(dir.path().join("config.d/.hidden"), "Host hidden\n").unwrap();
+        let path = dir.path().join("config");
+        fs::write(&path, "Include config.d/*\nHost gw\n    HostName gateway.example.com\n    User me\n").unwrap();
+
+        let parsed = read_ssh_config(&path, dir.path()).unwrap();
+
+        let counts: Vec<_> = parsed
+            .files
+            .iter()
+            .map(|file| (file.path.file_name().unwrap().to_str().unwrap(), file.hosts))
+            .collect();
+        assert_eq!(counts, vec![("config", 1), ("home", 1), ("work", 2)]);
+        assert_eq!(parsed.host_names(), vec!["nas", "work1", "work2", "gw"]);
+        assert_eq!(parsed.to_profile("work2").unwrap().jump_host, "gateway.example.com");
+        assert!(parsed.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_include_cycle_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config");
+        fs::write(&path, "Host a\n    Include other\n").unwrap();
+        fs::write(dir.path().join("other"), "Include config\nHost b\n").unwrap();
+
+        let parsed = read_ssh_config(&path, dir.path()).unwrap();
+
+        assert_eq!(parsed.files.len(), 2);
+        assert_eq!(parsed.host_names(), vec!["a", "b"]);
+        assert_eq!(parsed.warnings.len(), 1);
+        assert!(parsed.warnings[0].contains("include cycle"));
+    }
+
+    #[test]
+    fn test_include_depth_is_limited() {
+        let dir = tempfile::tempdir().unwrap();
+        for level in 0..20 {
+            fs::write(dir.path().join(format!("level{}", level)), format!("Include level{}\n", level + 1)).unwrap();
+        }
+
+        let parsed = read_ssh_config(&dir.path().join("level0"), dir.path()).unwrap();
+
+        assert_eq!(parsed.files.len(), MAX_INCLUDE_DEPTH + 1);
+        assert!(parsed.warnings[0].contains("nested more than 16 deep"));
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::hostbook::{self, HostBook};
+use crate::{fsutil, get_profiles_dir, read_profile_file, SshConfig};
+
+// Parsed profiles for commands that go through all of them (`profiles`, `search`, `--tag`), so a
+// profile that hasn't changed isn't read and parsed again. An entry is only used while the file's
+// size and modification time still match; anything else is parsed from the file as usual. Secrets
+// are left out, so whatever needs them loads the profile itself.
+pub const INDEX_FILE: &str = "index.json";
+// Bumped whenever an entry's shape changes, so an older index is rebuilt rather than misread.
+const INDEX_VERSION: u32 = 1;
+const RECENT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Deserialize)]
+struct Index {
+    version: u32,
+    profiles: BTreeMap<String, Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    modified_ns: u64,
+    len: u64,
+    config: SshConfig,
+}
+
+// Like `load_profile` for each name, each with its own result, and with secrets left out. `names`
+// is the whole listing, so entries for any other profile are dropped.
+pub fn load_profiles(names: &[String]) -> Result<Vec<(String, Result<SshConfig>)>> {
+    let profiles_dir = get_profiles_dir()?;
+    Ok(load_profiles_in(Path::new(&profiles_dir), names, &hostbook::load()))
+}
+
+fn load_profiles_in(
+    profiles_dir: &Path,
+    names: &[String],
+    book: &Result<HostBook>,
+) -> Vec<(String, Result<SshConfig>)> {
+    let index_path = profiles_dir.join(INDEX_FILE);
+    let mut index = read_index(&index_path);
+    let mut changed = false;
+
+    let mut loaded = Vec::new();
+    for name in names {
+        let path = profiles_dir.join(format!("{}.toml", name));
+        let cached = index
+            .profiles
+            .get(name)
+            .filter(|entry| file_stamp(&path) == Some((entry.modified_ns, entry.len)))
+            .map(|entry| entry.config.clone());
+        let config = match cached {
+            Some(config) => Ok(config),
+            None => {
+                let config = read_profile_file(name, &path).map(|config| config.without_secrets());
+                // Stamped after reading, since an upgraded profile has just been rewritten. One changed in
+                // between is too recent to be indexed.
+                match (&config, file_stamp(&path)) {
+                    (Ok(config), Some((modified_ns, len))) if !is_recent(modified_ns) => {
+                        let entry = Entry {
+                            modified_ns,
+                            len,
+                            config: config.clone(),
+                        };
+                        index.profiles.insert(name.clone(), entry);
+                        changed = true;
+                    }
+                    _ => changed |= index.profiles.remove(name).is_some(),
+                }
+                config
+            }
+        };
+        let config = config.and_then(|mut config| {
+            if config.jump_ref.is_some() {
+                let book = book.as_ref().map_err(|e| anyhow!("{:#}", e))?;
+                hostbook::resolve(&mut config, book)?;
+            }
+            Ok(config)
+        });
+        loaded.push((name.clone(), config));
+    }
+
+    let before = index.profiles.len();
+    let listed: BTreeSet<&String> = names.iter().collect();
+    index.profiles.retain(|name, _| listed.contains(name));
+    if changed || index.profiles.len() != before {
+        // Without an index every listing still works, just by parsing each profile again.
+        if let Err(e) = write_index(&index_path, &index) {
+            warn!(path = %index_path.display(), error = %format!("{:#}", e), "could not write profile index");
+        }
+    }
+    loaded
+}
+
+// A missing, unreadable or outdated index is the same as an empty one.
+fn read_index(path: &Path) -> Index {
+    let index = fs::read_to_string(path).ok().and_then(|json| serde_json::from_str::<Index>(&json).ok());
+    match index {
+        Some(index) if index.version == INDEX_VERSION => index,
+        _ => {
+            debug!(path = %path.display(), "no usable profile index, parsing every profile");
+            Index {
+                version: INDEX_VERSION,
+                profiles: BTreeMap::new(),
+            }
+        }
+    }
+}
+
+fn write_index(path: &Path, index: &Index) -> Result<()> {
+    let json = serde_json::to_string(index).context("Failed to serialize profile index")?;
+    fsutil::write_private(path, json.as_bytes()).context(format!("Failed to write {}", path.display()))
+}
+
+// On filesystems with coarse timestamps, a file written again within the same tick keeps its
+// modification time, and maybe its size. Files changed that recently are parsed until they settle.
+fn is_recent(modified_ns: u64) -> bool {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_nanos() as u64).unwrap_or_default();
+    now.saturating_sub(modified_ns) < RECENT.as_nanos() as u64
+}
+
+fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((modified.as_nanos() as u64, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    // At the current schema version, so loading doesn't rewrite it.
+    const PROFILE: &str = "schema_version = 1\njump_host_user = \"jumper\"\njump_host = \"bastion\"\njump_port = 22\n\
+                           target_host_user = \"deploy\"\ntarget_host = \"10.0.0.5\"\ntarget_port = 22\n";
+
+    // Always stamped with the same time in the past, so it is old enough to be indexed.
+    fn write_profile(dir: &Path, name: &str, contents: &str) {
+        let path = dir.join(format!("{}.toml", name));
+        fs::write(&path, contents).unwrap();
+        let past = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        File::options().write(true).open(&path).unwrap().set_modified(past).unwrap();
+    }
+
+    fn load(dir: &Path, names: &[&str]) -> Vec<Result<SshConfig>> {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        load_profiles_in(dir, &names, &Ok(HostBook::new())).into_iter().map(|(_, config)| config).collect()
+    }
+
+    #[test]
+    fn test_unchanged_profiles_come_from_the_index() {
+        let dir = tempfile::tempdir().unwrap();
+        write_profile(dir.path(), "db", &format!("{}enc1 = \"secret\"\nenc2 = \"secret\"\n", PROFILE));
+
+        assert_eq!(load(dir.path(), &["db"])[0].as_ref().unwrap().target_host, "10.0.0.5");
+        let index = fs::read_to_string(dir.path().join(INDEX_FILE)).unwrap();
+        assert!(index.contains("10.0.0.5") && !index.contains("secret"));
+
+        // Same size and modification time, so the file isn't read again.
+        let len = PROFILE.len() + "enc1 = \"secret\"\nenc2 = \"secret\"\n".len();
+        write_profile(dir.path(), "db", &"#".repeat(len));
+        assert_eq!(load(dir.path(), &["db"])[0].as_ref().unwrap().target_host, "10.0.0.5");
+    }
+
+    #[test]
+    fn test_changed_and_removed_profiles_are_rescanned() {
+        let dir = tempfile::tempdir().unwrap();
+        write_profile(dir.path(), "db", PROFILE);
+        write_profile(dir.path(), "cache", PROFILE);
+        load(dir.path(), &["cache", "db"]);
+
+        write_profile(dir.path(), "db", &PROFILE.replace("10.0.0.5", "10.0.0.66"));
+        fs::remove_file(dir.path().join("cache.toml")).unwrap();
+        let loaded = load(dir.path(), &["cache", "db"]);
+
+        assert!(loaded[0].is_err());
+        assert_eq!(loaded[1].as_ref().unwrap().target_host, "10.0.0.66");
+        let index = read_index(&dir.path().join(INDEX_FILE));
+        assert_eq!(index.profiles.keys().collect::<Vec<_>>(), ["db"]);
+    }
+
+    #[test]
+    fn test_unusable_index_is_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        write_profile(dir.path(), "db", PROFILE);
+        fs::write(dir.path().join(INDEX_FILE), "{\"version\": 0, \"profiles\": {}}").unwrap();
+        assert!(load(dir.path(), &["db"])[0].is_ok());
+
+        fs::write(dir.path().join(INDEX_FILE), "not json").unwrap();
+        assert!(load(dir.path(), &["db"])[0].is_ok());
+        assert_eq!(read_index(&dir.path().join(INDEX_FILE)).profiles.len(), 1);
+
+        // A profile saved just now isn't indexed until its timestamp can be trusted.
+        fs::write(dir.path().join("new.toml"), PROFILE).unwrap();
+        assert!(load(dir.path(), &["new"])[0].is_ok());
+        assert!(!read_index(&dir.path().join(INDEX_FILE)).profiles.contains_key("new"));
+    }
+}
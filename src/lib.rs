@@ -0,0 +1,2106 @@
+//! Library behind the `rush-tunnel` CLI: profiles, ssh argument building and tunnel setup.
+//!
+//! ```no_run
+//! use rush_tunnel::{establish_tunnel, SshConfig, TunnelOptions};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let config = SshConfig {
+//!     jump_host_user: "jumper".to_string(),
+//!     jump_host: "bastion.example.com".to_string(),
+//!     target_host_user: "deploy".to_string(),
+//!     target_host: "10.0.0.5".to_string(),
+//!     jump_port: 22,
+//!     target_port: 22,
+//!     port_forward: Some(5432),
+//!     ..Default::default()
+//! };
+//!
+//! let outcome = establish_tunnel(&config, &mut TunnelOptions::default()).await?;
+//! println!("ssh exited with {} after {:?}", outcome.status, outcome.duration);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, thread};
+
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, info, warn};
+use zeroize::Zeroizing;
+
+use crate::askpass::Credentials;
+use crate::config::{GlobalConfig, SshOptions, GLOBAL_CONFIG_FILE};
+use crate::crypto::{create_verifier, decrypt_password, encrypt_password_with, verify_master_password, Cipher};
+use crate::forward::{duplicate_local_ports, forward_problems, LocalForward, ALL_INTERFACES};
+
+pub mod askpass;
+pub mod backup;
+mod builder;
+pub mod config;
+pub mod copy_id;
+pub mod crypto;
+pub mod doctor;
+mod error;
+pub mod formats;
+pub mod forward;
+pub mod fsutil;
+pub mod hooks;
+pub mod hostbook;
+pub mod import;
+pub mod index;
+pub mod migrate;
+pub mod multiplex;
+pub mod openssh;
+pub mod paths;
+pub mod pidfile;
+pub mod plaintext;
+pub mod proxy;
+pub mod purge;
+pub mod report;
+pub mod select;
+pub mod state;
+pub mod style;
+pub mod table;
+
+pub use crate::builder::SshConfigBuilder;
+pub use crate::error::{join_problems, validate_profile_name, ConfigError, UnlockError};
+
+#[derive(Default)]
+pub struct TunnelOptions {
+    pub quiet: bool,
+    pub assume_yes: bool,
+    pub exec: Option<String>,
+    pub forward_only: bool,
+    pub agent_forwarding: bool,
+    // `Some(true)` forces a pseudo-terminal (`-t`), `Some(false)` disables it (`-T`), `None` leaves it to ssh.
+    pub tty: Option<bool>,
+    // Connects to the jump host itself and ignores the target and its forwards.
+    pub jump_only: bool,
+    // Bounds the whole session, unlike ssh's ConnectTimeout which only covers connecting.
+    pub session_timeout: Option<Duration>,
+    // Prints each forward's URL once its local port accepts connections.
+    pub print_urls: bool,
+    // Times to start ssh again after it failed to resolve a host name. Anything else, such as an
+    // authentication or host key failure, is never retried.
+    pub dns_retries: u32,
+    // Skips the check that refuses forwards on ports only root can bind.
+    pub allow_privileged_ports: bool,
+    // ssh fails rather than prompting (BatchMode=yes). That covers the prompts askpass would answer,
+    // so it only works where no password or key passphrase is needed.
+    pub batch: bool,
+    pub credentials: Option<Credentials>,
+}
+
+// How a tunnel run by `establish_tunnel` ended. `duration` runs from spawning ssh until it exited.
+#[derive(Clone, Copy, Debug)]
+pub struct TunnelOutcome {
+    pub status: ExitStatus,
+    pub duration: Duration,
+    // The SIGINT or SIGTERM rush-tunnel received and passed on to ssh, if that is what ended it.
+    pub interrupted_by: Option<i32>,
+}
+
+// Returned by `establish_tunnel` when ssh was stopped because the session timeout ran out.
+#[derive(Debug)]
+pub struct SessionTimedOut(pub Duration);
+
+impl fmt::Display for SessionTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Session timed out after {}s", self.0.as_secs())
+    }
+}
+
+impl std::error::Error for SessionTimedOut {}
+
+pub struct EncryptedPasswords {
+    pub enc1: String,
+    pub enc2: String,
+    pub verifier: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default, Clone, Debug, PartialEq)]
+pub struct SshConfig {
+    #[serde(default)]
+    pub schema_version: u32,
+    // The jump host is left empty for profiles that connect through `proxy_command` instead.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub jump_host_user: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub jump_host: String,
+    pub target_host_user: String,
+    pub target_host: String,
+    #[serde(default = "default_ssh_port")]
+    pub jump_port: u16,
+    pub target_port: u16,
+    // Name of a host in `hosts.toml`, whose user, address, port and password file replace the
+    // inline jump host when the profile is loaded.
+    pub jump_ref: Option<String>,
+    // Run to reach the target (ssh's ProxyCommand, e.g. `cloudflared access ssh --hostname %h`).
+    pub proxy_command: Option<String>,
+    // HTTP or SOCKS proxy the jump host is reached through, e.g. `http://proxy.corp:3128`.
+    pub pre_proxy: Option<String>,
+    pub port_forward: Option<u16>,
+    pub bind_address: Option<String>,
+    pub forward_remote_host: Option<String>,
+    pub forward_remote_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forwards: Vec<LocalForward>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Free-text note for whoever uses the profile; only shown, never passed to ssh.
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub agent_forwarding: bool,
+    // Shares one master connection per target between runs, so only the first one authenticates.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub multiplex: bool,
+    // Seconds the master stays open after the last session closes; defaults to 600.
+    pub control_persist: Option<u32>,
+    // Run on this machine once connected (ssh's LocalCommand), e.g. to open a browser. `$VAR`s are
+    // expanded when connecting.
+    pub local_command: Option<String>,
+    // Shell commands run on this machine once the tunnel is up and after it closes, e.g. to mount
+    // and unmount an sshfs.
+    pub on_connect: Option<String>,
+    pub on_disconnect: Option<String>,
+    #[serde(flatten)]
+    pub options: SshOptions,
+    pub enc1: Option<String>,
+    pub enc2: Option<String>,
+    pub verifier: Option<String>,
+    // Passphrase of `identity_file`, encrypted with the same master password as the passwords. Only
+    // saved with `tunnel --key-passphrase`.
+    pub enc_key_passphrase: Option<String>,
+    // Read at connect time, for passwords kept by other tooling such as a secret manager.
+    pub jump_password_file: Option<String>,
+    pub target_password_file: Option<String>,
+}
+
+impl SshConfig {
+    pub fn validate(&self) -> Vec<ConfigError> {
+        let mut problems = Vec::new();
+        // The target is reached through exactly one of a jump host or a proxy command.
+        let mut required = Vec::new();
+        if self.proxy_command.is_none() {
+            required.extend([("jump_host_user", &self.jump_host_user), ("jump_host", &self.jump_host)]);
+        }
+        required.extend([("target_host_user", &self.target_host_user), ("target_host", &self.target_host)]);
+        for (name, value) in required {
+            if normalize_required(value).is_none() {
+                problems.push(ConfigError::MissingField(name));
+            }
+        }
+        if let Some(command) = &self.proxy_command {
+            if normalize_required(command).is_none() {
+                problems.push(ConfigError::Invalid("proxy_command is empty".to_string()));
+            }
+            if !self.jump_host.trim().is_empty() {
+                problems.push(ConfigError::Invalid(
+                    "jump_host and proxy_command are both set; use one or the other".to_string(),
+                ));
+            }
+            if self.jump_ref.is_some() {
+                problems.push(ConfigError::Invalid(
+                    "jump_ref and proxy_command are both set; use one or the other".to_string(),
+                ));
+            }
+        }
+        if let Some(pre_proxy) = &self.pre_proxy {
+            if let Err(e) = pre_proxy.parse::<proxy::PreProxy>() {
+                problems.push(ConfigError::Invalid(e.to_string()));
+            }
+            if self.proxy_command.is_some() {
+                problems.push(ConfigError::Invalid(
+                    "pre_proxy and proxy_command are both set; go through the proxy in proxy_command".to_string(),
+                ));
+            }
+        }
+        for (name, port) in [
+            ("jump_port", Some(self.jump_port).filter(|_| self.proxy_command.is_none())),
+            ("target_port", Some(self.target_port)),
+            ("port_forward", self.port_forward),
+            ("forward_remote_port", self.forward_remote_port),
+        ] {
+            if let Some(port) = port.filter(|port| !is_valid_port(*port)) {
+                problems.push(ConfigError::InvalidPort {
+                    field: name.to_string(),
+                    port,
+                });
+            }
+        }
+        if let Some(host) = &self.forward_remote_host {
+            if normalize_required(host).is_none() {
+                problems.push(ConfigError::EmptyHost("forward_remote_host".to_string()));
+            }
+        }
+        for (name, command) in [
+            ("local_command", &self.local_command),
+            ("on_connect", &self.on_connect),
+            ("on_disconnect", &self.on_disconnect),
+        ] {
+            if command.as_deref().is_some_and(|command| command.trim().is_empty()) {
+                problems.push(ConfigError::Invalid(format!("{} is empty", name)));
+            }
+        }
+        if has_unobservable_hooks(self) {
+            problems.push(ConfigError::Invalid(UNOBSERVABLE_HOOKS.to_string()));
+        }
+        if self.control_persist.is_some() && !self.multiplex {
+            problems.push(ConfigError::Invalid("control_persist is set but multiplex is off".to_string()));
+        }
+        problems.extend(self.options.problems().into_iter().map(ConfigError::Invalid));
+        problems.extend(self.forwards.iter().flat_map(forward_problems));
+        for port in duplicate_local_ports(&self.local_forwards()) {
+            problems.push(ConfigError::Invalid(format!("local port {} is used by more than one forward", port)));
+        }
+        problems
+    }
+
+    pub fn without_secrets(&self) -> SshConfig {
+        SshConfig {
+            enc1: None,
+            enc2: None,
+            verifier: None,
+            enc_key_passphrase: None,
+            ..self.clone()
+        }
+    }
+
+    // Each secret the profile has is replaced by a placeholder, so a printed config still shows
+    // which ones are saved.
+    pub fn with_masked_secrets(&self) -> SshConfig {
+        let mask = |secret: &Option<String>| secret.as_ref().map(|_| MASKED_SECRET.to_string());
+        SshConfig {
+            enc1: mask(&self.enc1),
+            enc2: mask(&self.enc2),
+            verifier: mask(&self.verifier),
+            enc_key_passphrase: mask(&self.enc_key_passphrase),
+            ..self.clone()
+        }
+    }
+
+    pub fn has_saved_passwords(&self) -> bool {
+        self.enc1.is_some() && self.enc2.is_some()
+    }
+
+    // Whether connecting asks for the master password.
+    pub fn has_saved_secrets(&self) -> bool {
+        self.has_saved_passwords() || self.enc_key_passphrase.is_some()
+    }
+
+    pub fn set_encrypted_passwords(&mut self, encrypted: Option<EncryptedPasswords>) {
+        self.enc1 = encrypted.as_ref().map(|e| e.enc1.clone());
+        self.enc2 = encrypted.as_ref().map(|e| e.enc2.clone());
+        self.verifier = encrypted.map(|e| e.verifier);
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag.trim()))
+    }
+
+    pub fn matches_query(&self, query: &str) -> bool {
+        let query = query.trim().to_lowercase();
+        [
+            &self.jump_host_user,
+            &self.jump_host,
+            &self.target_host_user,
+            &self.target_host,
+        ]
+        .into_iter()
+        .chain(&self.tags)
+        .any(|value| value.to_lowercase().contains(&query))
+    }
+
+    // Shares every forward on the LAN instead of only with this machine.
+    pub fn bind_forwards_publicly(&mut self) {
+        if self.port_forward.is_some() {
+            self.bind_address = Some(ALL_INTERFACES.to_string());
+        }
+        for forward in &mut self.forwards {
+            forward.bind_address = Some(ALL_INTERFACES.to_string());
+        }
+    }
+
+    // The single forward entered at creation time comes first, followed by any extra forwards.
+    // Its remote port defaults to the same port as the local side.
+    pub fn local_forwards(&self) -> Vec<LocalForward> {
+        let primary = self.port_forward.map(|local_port| LocalForward {
+            bind_address: self.bind_address.clone(),
+            local_port,
+            remote_host: self.forward_remote_host.clone(),
+            remote_port: self.forward_remote_port.unwrap_or(local_port),
+            scheme: None,
+        });
+        primary.into_iter().chain(self.forwards.iter().cloned()).collect()
+    }
+}
+
+pub fn normalize_required(input: &str) -> Option<String> {
+    let trimmed = input.trim();
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.to_string())
+    }
+}
+
+pub fn is_valid_port(port: u16) -> bool {
+    port != 0
+}
+
+pub(crate) const DEFAULT_SSH_PORT: u16 = 22;
+pub const MASKED_SECRET: &str = "********";
+
+fn default_ssh_port() -> u16 {
+    DEFAULT_SSH_PORT
+}
+
+pub fn encrypt_passwords(
+    master_password: &str,
+    jump: &str,
+    target: &str,
+    cipher: Cipher,
+) -> Result<EncryptedPasswords> {
+    let enc1 = encrypt_password_with(master_password, jump, cipher).context("Failed to encrypt jump host password")?;
+    let enc2 = encrypt_password_with(master_password, target, cipher).context("Failed to encrypt target host password")?;
+    let verifier = create_verifier(master_password).context("Failed to create master password verifier")?;
+    Ok(EncryptedPasswords { enc1, enc2, verifier })
+}
+
+pub fn encrypt_key_passphrase(master_password: &str, passphrase: &str, cipher: Cipher) -> Result<String> {
+    encrypt_password_with(master_password, passphrase, cipher).context("Failed to encrypt key passphrase")
+}
+
+// Decrypts a profile's saved passwords with the current master password and encrypts them again with
+// the new one, without touching the profile. `None` when it has no saved passwords.
+pub fn reencrypt_passwords(
+    config: &SshConfig,
+    current_master_password: &str,
+    new_master_password: &str,
+    cipher: Cipher,
+) -> Result<Option<EncryptedPasswords>> {
+    if !config.has_saved_passwords() {
+        return Ok(None);
+    }
+    let Some(credentials) = unlock_for_reencryption(config, current_master_password)? else {
+        return Ok(None);
+    };
+    encrypt_passwords(new_master_password, &credentials.jump_password, &credentials.target_password, cipher).map(Some)
+}
+
+// Like `reencrypt_passwords`, for the saved key passphrase.
+pub fn reencrypt_key_passphrase(
+    config: &SshConfig,
+    current_master_password: &str,
+    new_master_password: &str,
+    cipher: Cipher,
+) -> Result<Option<String>> {
+    if config.enc_key_passphrase.is_none() {
+        return Ok(None);
+    }
+    let passphrase = unlock_for_reencryption(config, current_master_password)?.and_then(|c| c.key_passphrase);
+    passphrase
+        .map(|passphrase| encrypt_key_passphrase(new_master_password, &passphrase, cipher))
+        .transpose()
+}
+
+fn unlock_for_reencryption(config: &SshConfig, current_master_password: &str) -> Result<Option<Credentials>> {
+    decrypt_saved_passwords(config, current_master_password).map_err(|e| match e {
+        UnlockError::WrongMasterPassword => anyhow::anyhow!("the current master password doesn't match"),
+        e => e.into(),
+    })
+}
+
+// The verifier only makes the error clearer: one that is missing, unreadable or written for another
+// master password never stops the passwords decrypting, so older or damaged profiles stay usable.
+// `None` when the profile has no saved passwords or key passphrase; a profile with only the passphrase
+// gets empty passwords, which askpass never answers with.
+pub fn decrypt_saved_passwords(config: &SshConfig, master_password: &str) -> Result<Option<Credentials>, UnlockError> {
+    if !config.has_saved_secrets() {
+        return Ok(None);
+    }
+    let passwords = match (&config.enc1, &config.enc2) {
+        (Some(enc1), Some(enc2)) => Some((enc1, enc2)),
+        _ => None,
+    };
+    let verified = match config.verifier.as_deref().filter(|verifier| !verifier.trim().is_empty()) {
+        Some(verifier) => match verify_master_password(master_password, verifier) {
+            Ok(matches) => Some(matches),
+            Err(e) => {
+                warn!(error = %e, "ignoring unreadable master password verifier");
+                None
+            }
+        },
+        None => None,
+    };
+    let decrypted = passwords
+        .map(|(enc1, enc2)| {
+            let jump = decrypt_secret(master_password, enc1).map_err(|e| format!("jump host password: {}", e))?;
+            let target = decrypt_secret(master_password, enc2).map_err(|e| format!("target host password: {}", e))?;
+            Ok((jump, target))
+        })
+        .transpose()
+        .and_then(|passwords| {
+            let passphrase = config
+                .enc_key_passphrase
+                .as_deref()
+                .map(|enc| decrypt_secret(master_password, enc).map_err(|e| format!("key passphrase: {}", e)))
+                .transpose()?;
+            Ok((passwords.unwrap_or_default(), passphrase))
+        });
+    match (decrypted, verified) {
+        (Ok(((jump_password, target_password), key_passphrase)), verified) => {
+            if verified == Some(false) {
+                warn!("master password verifier is out of date; the saved passwords decrypted anyway");
+            }
+            Ok(Some(Credentials {
+                jump_password,
+                target_password,
+                key_passphrase: key_passphrase.map(Zeroizing::new),
+            }))
+        }
+        (Err(e), Some(true)) => Err(UnlockError::CorruptPasswords(e)),
+        (Err(e), _) => {
+            debug!(error = e, "saved passwords did not decrypt");
+            Err(UnlockError::WrongMasterPassword)
+        }
+    }
+}
+
+// Insecure plaintext passwords decode with any master password; callers check for the override
+// before using a profile that has them.
+fn decrypt_secret(master_password: &str, stored: &str) -> Result<String, String> {
+    if plaintext::is_plaintext(stored) {
+        return plaintext::decode(stored).map_err(|e| e.to_string());
+    }
+    decrypt_password(master_password, stored).map_err(|e| e.to_string())
+}
+
+static PROFILES_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_profiles_dir(profiles_dir: PathBuf) {
+    PROFILES_DIR_OVERRIDE.get_or_init(|| profiles_dir);
+}
+
+pub fn get_profiles_dir() -> Result<String> {
+    if let Some(profiles_dir) = PROFILES_DIR_OVERRIDE.get() {
+        return Ok(profiles_dir.to_string_lossy().to_string());
+    }
+
+    let home_dir = dirs::home_dir().context("Failed to get home directory")?;
+    let profiles_dir = home_dir.join(".rush-tunnel");
+    Ok(profiles_dir.to_string_lossy().to_string())
+}
+
+pub fn apply_global_defaults(config: &mut SshConfig, cli_options: SshOptions) -> Result<()> {
+    let global = GlobalConfig::load()?;
+    config.options = cli_options.or(std::mem::take(&mut config.options)).or(global.defaults);
+    config.options.expand_identity_agent()?;
+    config.options.expand_identity_file()?;
+    if let Some(command) = &config.local_command {
+        config.local_command = Some(paths::expand_env(command).context("Invalid local_command")?);
+    }
+    debug!(options = ?config.options, "merged ssh options");
+    Ok(())
+}
+
+pub fn load_profile(profile_name: &str) -> Result<SshConfig> {
+    let profiles_dir = get_profiles_dir()?;
+    let profile_path = Path::new(&profiles_dir).join(format!("{}.toml", profile_name));
+    debug!(profile = profile_name, path = %profile_path.display(), "loading profile");
+    let mut config = read_profile_file(profile_name, &profile_path)?;
+    if config.jump_ref.is_some() {
+        hostbook::resolve(&mut config, &hostbook::load()?)?;
+    }
+    debug!(
+        profile = profile_name,
+        forwards = config.local_forwards().len(),
+        saved_passwords = config.has_saved_passwords(),
+        "loaded profile"
+    );
+    Ok(config)
+}
+
+// The profile as saved, upgraded if needed but with its jump_ref still unresolved.
+fn read_profile_file(profile_name: &str, profile_path: &Path) -> Result<SshConfig> {
+    if !profile_path.exists() {
+        anyhow::bail!("No such profile (expected {})", profile_path.display());
+    }
+
+    let toml_str = fs::read_to_string(profile_path).context(format!("Failed to read {}", profile_path.display()))?;
+    let mut config: SshConfig = formats::check_keys(&toml_str, formats::Format::Toml)
+        .and_then(|()| Ok(toml::from_str(&toml_str)?))
+        .context(format!("{} exists but could not be parsed", profile_path.display()))?;
+    if migrate::migrate(&mut config).context(format!("Failed to upgrade {}", profile_path.display()))? {
+        // Loading still succeeds from a read-only profiles directory; the upgrade is retried next time.
+        if let Err(e) = write_profile_file(profile_path, &config) {
+            warn!(profile = profile_name, error = %format!("{:#}", e), "could not rewrite migrated profile");
+        }
+    }
+    Ok(config)
+}
+
+pub fn save_profile(profile_name: &str, ssh_config: &SshConfig) -> Result<()> {
+    validate_profile_name(profile_name)?;
+    let profiles_dir = get_profiles_dir()?;
+    fsutil::create_private_dir(Path::new(&profiles_dir)).context("Failed to create profiles directory")?;
+    let profile_path = Path::new(&profiles_dir).join(format!("{}.toml", profile_name));
+    // Profiles are always written in the latest schema, whatever shape the caller built them in.
+    let mut ssh_config = ssh_config.clone();
+    migrate::migrate(&mut ssh_config)?;
+    debug!(
+        profile = profile_name,
+        path = %profile_path.display(),
+        saved_passwords = ssh_config.has_saved_passwords(),
+        "saving profile"
+    );
+    let is_new = !profile_path.exists();
+    write_profile_file(&profile_path, &ssh_config)?;
+    // Recorded again for a profile saved under the name of one that was deleted.
+    if is_new {
+        state::record_created(profile_name, SystemTime::now())?;
+    }
+    Ok(())
+}
+
+fn write_profile_file(profile_path: &Path, ssh_config: &SshConfig) -> Result<()> {
+    let toml_str = toml::to_string(ssh_config).context("Failed to serialize profile")?;
+    fsutil::write_private(profile_path, toml_str.as_bytes()).context("Failed to write profile file")
+}
+
+pub struct ProfilesScan {
+    pub profiles: Vec<String>,
+    pub skipped: Vec<(String, String)>,
+}
+
+pub enum ProfilesDirEntry {
+    Profile(String),
+    Internal,
+    Skipped(&'static str),
+}
+
+pub fn list_profiles() -> Result<Option<Vec<String>>> {
+    Ok(scan_profiles()?.map(|scan| scan.profiles))
+}
+
+pub fn scan_profiles() -> Result<Option<ProfilesScan>> {
+    let profiles_dir = get_profiles_dir()?;
+    let profiles_path = Path::new(&profiles_dir);
+
+    if !profiles_path.exists() {
+        return Ok(None);
+    }
+
+    let mut scan = ProfilesScan {
+        profiles: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for entry in fs::read_dir(profiles_path)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                scan.skipped.push(("?".to_string(), format!("unreadable entry: {}", e)));
+                continue;
+            }
+        };
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let is_dir = entry.file_type().map(|kind| kind.is_dir()).unwrap_or(false);
+        match classify_profiles_dir_entry(&file_name, is_dir) {
+            ProfilesDirEntry::Profile(name) => scan.profiles.push(name),
+            ProfilesDirEntry::Internal => {}
+            ProfilesDirEntry::Skipped(reason) => scan.skipped.push((file_name, reason.to_string())),
+        }
+    }
+    sort_profile_names(&mut scan.profiles);
+
+    Ok(Some(scan))
+}
+
+// Names that differ only by case are the same file on case-insensitive filesystems (macOS, Windows),
+// so saving one silently replaces the other.
+pub fn case_variants<'a>(profile_name: &str, profiles: &'a [String]) -> Vec<&'a str> {
+    let lowercase = profile_name.to_lowercase();
+    profiles
+        .iter()
+        .filter(|profile| profile.as_str() != profile_name && profile.to_lowercase() == lowercase)
+        .map(String::as_str)
+        .collect()
+}
+
+// Directory order is arbitrary, so listings are sorted alphabetically, ignoring case.
+pub fn sort_profile_names(names: &mut [String]) {
+    names.sort_by(|a, b| a.to_lowercase().cmp(&b.to_lowercase()).then_with(|| a.cmp(b)));
+}
+
+pub fn classify_profiles_dir_entry(file_name: &str, is_dir: bool) -> ProfilesDirEntry {
+    if is_dir {
+        return ProfilesDirEntry::Skipped("directory");
+    }
+    let internal = [
+        GLOBAL_CONFIG_FILE,
+        hostbook::HOST_BOOK_FILE,
+        state::DEFAULT_PROFILE,
+        state::LAST_PROFILE,
+        state::APPROVED_LOCAL_COMMANDS,
+        state::LAST_USED,
+        state::CREATED,
+        index::INDEX_FILE,
+    ];
+    if internal.contains(&file_name)
+        || file_name.starts_with(multiplex::CONTROL_SOCKET_PREFIX)
+        || file_name.ends_with(pidfile::PID_FILE_SUFFIX)
+    {
+        return ProfilesDirEntry::Internal;
+    }
+    match file_name.strip_suffix(".toml") {
+        Some(name) if !name.is_empty() => ProfilesDirEntry::Profile(name.to_string()),
+        _ => ProfilesDirEntry::Skipped("not a .toml file"),
+    }
+}
+
+pub async fn establish_tunnel(config: &SshConfig, options: &mut TunnelOptions) -> Result<TunnelOutcome> {
+    let (ssh_binary, args) = prepare_tunnel(config, options)?;
+    // Taken out of the options so the saved passwords and key passphrase aren't held for the whole
+    // session, and only kept here while a retry could still need them.
+    let mut credentials = options.credentials.take();
+    let mut signals = TerminationSignals::install()?;
+    let started = Instant::now();
+    let mut retries = 0;
+    loop {
+        info!(ssh_binary, saved_passwords = credentials.is_some(), "spawning ssh");
+        let mut command = tokio::process::Command::from(ssh_command(&ssh_binary, &args, config, credentials.as_ref())?);
+        // stdin and stdout stay the terminal's, so ssh decides on a remote pty itself and reads the window
+        // size from it. ssh also stays in our process group, which is the terminal's foreground group, so
+        // the kernel sends it SIGWINCH on every resize; passing ours on would make it report each twice.
+        // ssh's stderr is only taken over when its messages are needed to spot a resolve failure.
+        if options.dns_retries > 0 {
+            command.stderr(Stdio::piped());
+        }
+        let mut child = command
+            .spawn()
+            .context("Failed to establish SSH tunnel with port forwarding")?;
+        drop(command);
+        // No retries are left, so nothing needs them again.
+        if retries == options.dns_retries {
+            credentials = None;
+        }
+        let stderr_watcher = child.stderr.take().map(watch_stderr);
+        let ssh_exited = Arc::new(AtomicBool::new(false));
+        let established = Arc::new(AtomicBool::new(false));
+        let watcher =
+            watch_forwards(&ssh_binary, config, options, Arc::clone(&ssh_exited), Arc::clone(&established));
+        // The session timeout covers every attempt together.
+        let waited = wait_for_tunnel(&mut child, options.session_timeout, started, &mut signals).await;
+        ssh_exited.store(true, Ordering::Relaxed);
+        if let Some(watcher) = watcher {
+            let _ = watcher.join();
+        }
+        // Only a tunnel that came up has anything for on_disconnect to undo.
+        if let Some(command) = config.on_disconnect.as_deref().filter(|_| established.load(Ordering::Relaxed)) {
+            hooks::run_hook("on_disconnect", command, &hooks::hook_env(config), options.quiet);
+        }
+        let stderr = match stderr_watcher {
+            Some(watcher) => watcher.await.unwrap_or_default(),
+            None => String::new(),
+        };
+        let (status, interrupted_by) = waited?;
+        info!(%status, ?interrupted_by, "ssh exited");
+
+        // ssh exits with 255 for its own errors, as opposed to the remote command's status.
+        if interrupted_by.is_none()
+            && status.code() == Some(255)
+            && retries < options.dns_retries
+            && is_resolve_failure(&stderr)
+        {
+            retries += 1;
+            warn!(retry = retries, of = options.dns_retries, "ssh could not resolve a host name; retrying");
+            if !options.quiet {
+                eprintln!(
+                    "{}",
+                    style::warning(&format!(
+                        "Could not resolve a host name, retrying in {}s ({}/{})",
+                        DNS_RETRY_DELAY.as_secs(),
+                        retries,
+                        options.dns_retries
+                    ))
+                );
+            }
+            tokio::time::sleep(DNS_RETRY_DELAY).await;
+            continue;
+        }
+        let duration = started.elapsed();
+        info!(duration_secs = duration.as_secs_f64(), retries, "tunnel finished");
+        return Ok(TunnelOutcome {
+            status,
+            duration,
+            interrupted_by,
+        });
+    }
+}
+
+const DNS_RETRY_DELAY: Duration = Duration::from_secs(1);
+// ssh reports connection problems before anything else, so only the start of its output is kept.
+const STDERR_SCAN_LIMIT: usize = 64 * 1024;
+
+// Copies ssh's stderr through to ours as it arrives, and returns what it saw of it.
+fn watch_stderr(mut stderr: tokio::process::ChildStderr) -> tokio::task::JoinHandle<String> {
+    tokio::spawn(async move {
+        let mut seen = Vec::new();
+        let mut buf = [0; 4096];
+        let mut out = tokio::io::stderr();
+        while let Ok(read) = stderr.read(&mut buf).await {
+            if read == 0 {
+                break;
+            }
+            let _ = out.write_all(&buf[..read]).await;
+            let keep = read.min(STDERR_SCAN_LIMIT.saturating_sub(seen.len()));
+            seen.extend_from_slice(&buf[..keep]);
+        }
+        String::from_utf8_lossy(&seen).into_owned()
+    })
+}
+
+// A failed lookup is worth another try; a rejected login or a changed host key never is, even if
+// a lookup failed along the way.
+fn is_resolve_failure(stderr: &str) -> bool {
+    const FAIL_FAST: &[&str] =
+        &["Permission denied", "Host key verification failed", "HOST IDENTIFICATION HAS CHANGED"];
+    stderr.contains("Could not resolve hostname") && !FAIL_FAST.iter().any(|message| stderr.contains(message))
+}
+
+// A SIGINT or SIGTERM is passed on to ssh, which then gets to close its connections before we
+// carry on; a second one kills it outright.
+async fn wait_for_tunnel(
+    child: &mut tokio::process::Child,
+    session_timeout: Option<Duration>,
+    started: Instant,
+    signals: &mut TerminationSignals,
+) -> Result<(ExitStatus, Option<i32>)> {
+    let signal = tokio::select! {
+        status = wait_until(child, session_timeout.map(|timeout| started + timeout)) => {
+            return match status? {
+                Some(status) => Ok((status, None)),
+                None => {
+                    stop_child(child).await?;
+                    let timeout = session_timeout.unwrap_or_default();
+                    info!(timeout_secs = timeout.as_secs(), "ssh stopped after session timeout");
+                    Err(SessionTimedOut(timeout).into())
+                }
+            };
+        }
+        signal = signals.recv() => signal,
+    };
+    info!(signal, "passing signal on to ssh");
+    send_signal(child, signal);
+    let status = tokio::select! {
+        status = child.wait() => status,
+        _ = signals.recv() => {
+            let _ = child.start_kill();
+            child.wait().await
+        }
+    };
+    Ok((status.context("Failed to wait for ssh")?, Some(signal)))
+}
+
+// SIGINT and SIGTERM, caught for as long as this is held so they can be passed on to ssh.
+#[cfg(unix)]
+pub struct TerminationSignals {
+    interrupt: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+}
+
+#[cfg(unix)]
+impl TerminationSignals {
+    pub fn install() -> Result<TerminationSignals> {
+        use tokio::signal::unix::{signal, SignalKind};
+        Ok(TerminationSignals {
+            interrupt: signal(SignalKind::interrupt()).context("Failed to handle SIGINT")?,
+            terminate: signal(SignalKind::terminate()).context("Failed to handle SIGTERM")?,
+        })
+    }
+
+    pub async fn recv(&mut self) -> i32 {
+        tokio::select! {
+            _ = self.interrupt.recv() => libc::SIGINT,
+            _ = self.terminate.recv() => libc::SIGTERM,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub struct TerminationSignals;
+
+#[cfg(not(unix))]
+impl TerminationSignals {
+    pub fn install() -> Result<TerminationSignals> {
+        Ok(TerminationSignals)
+    }
+
+    // Ctrl-C; Windows has no SIGTERM to catch.
+    pub async fn recv(&mut self) -> i32 {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+        2
+    }
+}
+
+#[cfg(unix)]
+fn send_signal(child: &mut tokio::process::Child, signal: i32) -> bool {
+    // `None` once the child has been reaped, when there is nothing left to signal.
+    let Some(pid) = child.id() else {
+        return false;
+    };
+    // SAFETY: the pid belongs to our own child, which hasn't been reaped yet.
+    unsafe { libc::kill(pid as libc::pid_t, signal) == 0 }
+}
+
+#[cfg(not(unix))]
+fn send_signal(child: &mut tokio::process::Child, _signal: i32) -> bool {
+    child.start_kill().is_ok()
+}
+
+const URL_PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+
+// ssh only opens its forwards and its control socket after logging in, so a hook can only tell the
+// tunnel is up from one of those.
+const UNOBSERVABLE_HOOKS: &str =
+    "on_connect and on_disconnect need a forward, or multiplex, to tell when the tunnel is up";
+
+fn has_unobservable_hooks(config: &SshConfig) -> bool {
+    (config.on_connect.is_some() || config.on_disconnect.is_some())
+        && config.local_forwards().is_empty()
+        && !config.multiplex
+}
+
+// Runs beside the wait on ssh, since ssh gives no signal once its forwards are listening. The
+// tunnel counts as established once every forward listens, or, when it has none, once the
+// multiplexed master answers `ssh -O check`, and that is when on_connect runs. A port that never
+// opens is simply not printed; the thread stops as soon as ssh exits. Jump-only sessions have
+// neither forwards nor hooks, so nothing is watched.
+fn watch_forwards(
+    ssh_binary: &str,
+    config: &SshConfig,
+    options: &TunnelOptions,
+    ssh_exited: Arc<AtomicBool>,
+    established: Arc<AtomicBool>,
+) -> Option<thread::JoinHandle<()>> {
+    if options.jump_only || (!options.print_urls && config.on_connect.is_none() && config.on_disconnect.is_none()) {
+        return None;
+    }
+    let mut pending = config.local_forwards();
+    // With no forwards to wait for, hooks are only allowed with multiplex, so its master is asked instead.
+    let mut check = match get_profiles_dir() {
+        Ok(profiles_dir) if pending.is_empty() && config.multiplex => {
+            let mut check = Command::new(ssh_binary);
+            check.args(multiplex::check_args(config, Path::new(&profiles_dir)));
+            check.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+            Some(check)
+        }
+        _ => None,
+    };
+    let print_urls = options.print_urls;
+    let on_connect = config.on_connect.clone();
+    let env = hooks::hook_env(config);
+    let quiet = options.quiet;
+    Some(thread::spawn(move || {
+        if let Some(check) = &mut check {
+            while !ssh_exited.load(Ordering::Relaxed) && !check.status().is_ok_and(|status| status.success()) {
+                thread::sleep(CHILD_POLL_INTERVAL);
+            }
+        }
+        while !pending.is_empty() && !ssh_exited.load(Ordering::Relaxed) {
+            pending.retain(|forward| {
+                if !is_listening(forward) {
+                    return true;
+                }
+                if print_urls {
+                    println!("{}", style::field("Forward URL:", &forward.url()));
+                }
+                false
+            });
+            thread::sleep(CHILD_POLL_INTERVAL);
+        }
+        if !pending.is_empty() || ssh_exited.load(Ordering::Relaxed) {
+            return;
+        }
+        established.store(true, Ordering::Relaxed);
+        if let Some(command) = on_connect {
+            hooks::run_hook("on_connect", &command, &env, quiet);
+        }
+    }))
+}
+
+fn is_listening(forward: &LocalForward) -> bool {
+    let Ok(addresses) = (forward.local_host(), forward.local_port).to_socket_addrs() else {
+        return false;
+    };
+    addresses
+        .into_iter()
+        .any(|address| TcpStream::connect_timeout(&address, URL_PROBE_TIMEOUT).is_ok())
+}
+
+const CHILD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const CHILD_STOP_GRACE: Duration = Duration::from_secs(2);
+
+// `None` once the deadline passes with the child still running; without a deadline it waits for good.
+async fn wait_until(child: &mut tokio::process::Child, deadline: Option<Instant>) -> Result<Option<ExitStatus>> {
+    let status = match deadline {
+        Some(deadline) => match tokio::time::timeout_at(deadline.into(), child.wait()).await {
+            Ok(status) => status,
+            Err(_) => return Ok(None),
+        },
+        None => child.wait().await,
+    };
+    status.map(Some).context("Failed to wait for ssh")
+}
+
+// ssh gets a SIGTERM first so it can restore the terminal and close its connections, and is only
+// killed if it hasn't exited after a short grace period. Either way the child is reaped.
+async fn stop_child(child: &mut tokio::process::Child) -> Result<()> {
+    #[cfg(unix)]
+    {
+        let deadline = Instant::now() + CHILD_STOP_GRACE;
+        if send_signal(child, libc::SIGTERM) && wait_until(child, Some(deadline)).await?.is_some() {
+            return Ok(());
+        }
+    }
+    // Fails only if the child already exited, which the wait below picks up.
+    let _ = child.start_kill();
+    child.wait().await.context("Failed to wait for ssh")?;
+    Ok(())
+}
+
+// Starts the tunnel without waiting for it, detached from the terminal's stdin so several can run
+// side by side. Pair it with `forward_only`, since there is no terminal for a remote shell.
+pub fn spawn_tunnel(config: &SshConfig, options: &mut TunnelOptions) -> Result<BackgroundTunnel> {
+    let (ssh_binary, args) = prepare_tunnel(config, options)?;
+    // Dropped along with the command once ssh has started, as in `establish_tunnel`.
+    let credentials = options.credentials.take();
+    let mut command = ssh_command(&ssh_binary, &args, config, credentials.as_ref())?;
+    command.stdin(Stdio::null());
+    info!(ssh_binary, saved_passwords = credentials.is_some(), "spawning ssh in the background");
+    let child = command
+        .spawn()
+        .context("Failed to establish SSH tunnel with port forwarding")?;
+    let ssh_exited = Arc::new(AtomicBool::new(false));
+    let established = Arc::new(AtomicBool::new(false));
+    let watcher = watch_forwards(&ssh_binary, config, options, Arc::clone(&ssh_exited), Arc::clone(&established));
+    Ok(BackgroundTunnel {
+        child,
+        on_disconnect: config.on_disconnect.clone(),
+        env: hooks::hook_env(config),
+        quiet: options.quiet,
+        ssh_exited,
+        established,
+        watcher,
+    })
+}
+
+// A tunnel started by `spawn_tunnel`. Its hooks run as they do in `establish_tunnel`: on_connect
+// once it is up, and on_disconnect from `finish` once ssh has exited.
+pub struct BackgroundTunnel {
+    pub child: Child,
+    on_disconnect: Option<String>,
+    env: Vec<(&'static str, String)>,
+    quiet: bool,
+    ssh_exited: Arc<AtomicBool>,
+    established: Arc<AtomicBool>,
+    watcher: Option<thread::JoinHandle<()>>,
+}
+
+impl BackgroundTunnel {
+    // Call once ssh has exited. Only a tunnel that came up runs on_disconnect.
+    pub fn finish(self) {
+        self.ssh_exited.store(true, Ordering::Relaxed);
+        if let Some(watcher) = self.watcher {
+            let _ = watcher.join();
+        }
+        if let Some(command) = self.on_disconnect.as_deref().filter(|_| self.established.load(Ordering::Relaxed)) {
+            hooks::run_hook("on_disconnect", command, &self.env, self.quiet);
+        }
+    }
+}
+
+// Checks the tunnel can be started and prints what it connects to, returning ssh and its arguments.
+fn prepare_tunnel(config: &SshConfig, options: &TunnelOptions) -> Result<(String, Vec<String>)> {
+    let ssh_binary = GlobalConfig::load()?.ssh_binary()?;
+    let Some(ssh_path) = find_executable(&ssh_binary) else {
+        anyhow::bail!("{} not found on PATH; install OpenSSH client", ssh_binary);
+    };
+    debug!(ssh_binary, path = %ssh_path.display(), "resolved ssh binary");
+    if let Some(pre_proxy) = &config.pre_proxy {
+        let tool = pre_proxy.parse::<proxy::PreProxy>()?.find_tool()?;
+        debug!(pre_proxy, ?tool, "resolved pre_proxy tool");
+    }
+    if !options.jump_only && has_unobservable_hooks(config) {
+        anyhow::bail!("{}", UNOBSERVABLE_HOOKS);
+    }
+    // ssh won't create the directory its control socket goes in.
+    if config.multiplex {
+        fsutil::create_private_dir(Path::new(&get_profiles_dir()?)).context("Failed to create profiles directory")?;
+    }
+
+    if !options.quiet {
+        println!("{}", style::heading("SSH Configuration:"));
+        match &config.proxy_command {
+            Some(command) => println!("{}", style::field("Proxy Command:", command)),
+            None => {
+                let jump = format!("{}@{}:{}", config.jump_host_user, bracket_host(&config.jump_host), config.jump_port);
+                println!("{}", style::field("Jump Host:", &jump));
+            }
+        }
+        if let Some(pre_proxy) = &config.pre_proxy {
+            println!("{}", style::field("Via Proxy:", pre_proxy));
+        }
+        if !options.jump_only {
+            let target = format!(
+                "{}@{}:{}",
+                config.target_host_user,
+                bracket_host(&config.target_host),
+                config.target_port
+            );
+            println!("{}", style::field("Target Host:", &target));
+        }
+        if let Some(rate) = config.options.limit_rate {
+            println!("{}", style::field("Limit rate:", &format!("{} Kbit/s (not applied to this tunnel)", rate)));
+        }
+    }
+    let forwards = if options.jump_only { Vec::new() } else { config.local_forwards() };
+    // ssh would only fail to bind them once connected, after asking for passwords.
+    if let Some(lowest) = forward::lowest_bindable_port().filter(|_| !options.allow_privileged_ports) {
+        let ports = forward::privileged_ports(&forwards, lowest);
+        if !ports.is_empty() {
+            let ports: Vec<String> = ports.iter().map(u16::to_string).collect();
+            let ports = match ports.as_slice() {
+                [port] => format!("local port {} is", port),
+                _ => format!("local ports {} are", ports.join(", ")),
+            };
+            anyhow::bail!(
+                "{} below {}, which only root can listen on; use a higher port, or pass --allow-privileged \
+                 if you can bind it",
+                ports,
+                lowest
+            );
+        }
+    }
+    // Without a shell, the forwards are all there is to the session.
+    if options.forward_only && !options.quiet {
+        for forward in &forwards {
+            let forward = format!("{} -> {}", forward.listen_spec(), forward.destination(&config.target_host));
+            println!("{}", style::field("Forward:", &forward));
+        }
+    }
+    for forward in &forwards {
+        let Some(bind_address) = forward.bind_address.as_deref().filter(|address| !is_loopback_address(address)) else {
+            continue;
+        };
+        eprintln!(
+            "{}",
+            style::warning(&format!(
+                "Warning: the forward on port {} binds to {}, so it is reachable from other machines",
+                forward.local_port, bind_address
+            ))
+        );
+    }
+
+    // A forwarded agent lets anyone with root on the target use your keys for as long as you're connected.
+    if config.agent_forwarding || options.agent_forwarding {
+        eprintln!(
+            "{}",
+            style::warning(&format!(
+                "Warning: agent forwarding is on, so anyone with root on {} can use your SSH keys while connected",
+                if options.jump_only { &config.jump_host } else { &config.target_host }
+            ))
+        );
+    }
+
+    Ok((ssh_binary, build_ssh_args(config, options)))
+}
+
+// Built again for every spawn, so the copy of the saved passwords in its environment is dropped
+// as soon as ssh has started rather than kept for retries.
+fn ssh_command(
+    ssh_binary: &str,
+    args: &[String],
+    config: &SshConfig,
+    credentials: Option<&Credentials>,
+) -> Result<Command> {
+    let mut command = Command::new(ssh_binary);
+    command.args(args);
+    // Saved passwords only ever travel through the child's environment, never through the log.
+    if let Some(credentials) = credentials {
+        askpass::configure(&mut command, config, credentials)?;
+    }
+    Ok(command)
+}
+
+pub fn build_ssh_args(config: &SshConfig, options: &TunnelOptions) -> Vec<String> {
+    let pre_proxy = proxy::resolve(config);
+    // Options that protect the jump host's account as much as the target's, so they go to both hops.
+    let mut hop_options = Vec::new();
+    // When the passwords are answered for us, trying keys first only adds failed attempts that can
+    // get an account rate-limited or locked. A saved key passphrase means the key is meant to be tried.
+    let answers_passwords_only =
+        options.credentials.as_ref().is_some_and(|c| c.has_passwords() && c.key_passphrase.is_none());
+    if answers_passwords_only && config.options.auth_methods.is_none() {
+        hop_options.push("PreferredAuthentications=password".to_string());
+    }
+    // A stale saved password is answered the same way every time, so further attempts only hang
+    // or lock the account.
+    if options.credentials.is_some() && config.options.password_prompts.is_none() {
+        hop_options.push("NumberOfPasswordPrompts=1".to_string());
+    }
+    let mut args = if options.jump_only {
+        let mut args = vec![
+            format!("{}@{}", config.jump_host_user, bracket_host(&config.jump_host)),
+            "-p".to_string(),
+            config.jump_port.to_string(),
+        ];
+        if let Some((proxy, tool)) = &pre_proxy {
+            args.extend(["-o".to_string(), format!("ProxyCommand={}", proxy.command(*tool))]);
+        }
+        args
+    } else {
+        // Each argument reaches ssh as-is, so the proxy command needs no extra quoting.
+        let mut args = match (&config.proxy_command, &pre_proxy) {
+            (Some(command), _) => vec!["-o".to_string(), format!("ProxyCommand={}", command)],
+            (None, Some((proxy, tool))) => vec![
+                "-o".to_string(),
+                format!("ProxyCommand={}", proxy::jump_proxy_command(config, Some((proxy, *tool)), &hop_options)),
+            ],
+            (None, None) if !hop_options.is_empty() => vec![
+                "-o".to_string(),
+                format!("ProxyCommand={}", proxy::jump_proxy_command(config, None, &hop_options)),
+            ],
+            (None, None) => vec![
+                "-J".to_string(),
+                format!(
+                    "{}@{}:{}",
+                    config.jump_host_user,
+                    bracket_host(&config.jump_host),
+                    config.jump_port
+                ),
+            ],
+        };
+        args.extend([
+            format!("{}@{}", config.target_host_user, bracket_host(&config.target_host)),
+            "-p".to_string(),
+            config.target_port.to_string(),
+        ]);
+        args
+    };
+    // Forwards are resolved from the target, so they have nowhere to go on a jump-only session.
+    let forwards = if options.jump_only { Vec::new() } else { config.local_forwards() };
+    for forward in &forwards {
+        args.push("-L".to_string());
+        args.push(format!("{}:{}", forward.listen_spec(), forward.destination(&config.target_host)));
+    }
+    // By default ssh only warns when a forward can't bind and connects without it, which is easy to miss.
+    if !forwards.is_empty() && config.options.exit_on_forward_failure.is_none() {
+        args.push("-o".to_string());
+        args.push("ExitOnForwardFailure=yes".to_string());
+    }
+    for option in hop_options {
+        args.extend(["-o".to_string(), option]);
+    }
+    if config.agent_forwarding || options.agent_forwarding {
+        args.push("-A".to_string());
+    }
+    // The command belongs to the target's session, so a jump-only session leaves it out.
+    if let Some(command) = config.local_command.as_ref().filter(|_| !options.jump_only) {
+        args.extend(["-o".to_string(), "PermitLocalCommand=yes".to_string()]);
+        args.extend(["-o".to_string(), format!("LocalCommand={}", command)]);
+    }
+    if config.multiplex {
+        if let Ok(profiles_dir) = get_profiles_dir() {
+            args.extend(multiplex::ssh_args(config, Path::new(&profiles_dir)));
+        }
+    }
+    if options.batch {
+        args.extend(["-o".to_string(), "BatchMode=yes".to_string()]);
+    }
+    args.extend(config.options.to_ssh_args());
+    if options.forward_only {
+        args.push("-N".to_string());
+    }
+    match options.tty {
+        Some(true) => args.push("-t".to_string()),
+        Some(false) => args.push("-T".to_string()),
+        None => {}
+    }
+    // ssh treats everything after the destination and its options as the remote command.
+    if let Some(command) = &options.exec {
+        args.push(command.clone());
+    }
+    debug!(?args, "built ssh arguments");
+    args
+}
+
+pub fn is_loopback_address(address: &str) -> bool {
+    address == "localhost"
+        || address
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<std::net::IpAddr>()
+            .map(|ip| ip.is_loopback())
+            .unwrap_or(false)
+}
+
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    let candidate = Path::new(name);
+    if candidate.components().count() > 1 {
+        return candidate.is_file().then(|| candidate.to_path_buf());
+    }
+
+    let path_var = std::env::var_os("PATH")?;
+    std::env::split_paths(&path_var).find_map(|dir| {
+        [name.to_string(), format!("{}.exe", name)]
+            .into_iter()
+            .map(|file_name| dir.join(file_name))
+            .find(|path| path.is_file())
+    })
+}
+
+// IPv6 literals contain colons, so they must be bracketed wherever a port or user is attached.
+pub fn bracket_host(host: &str) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    }
+}
+
+// Splits `host:port`, `[ipv6]:port` or a bare host. An unbracketed address with several colons is
+// an IPv6 literal without a port.
+pub fn split_host_port(address: &str) -> Result<(String, Option<u16>)> {
+    let address = address.trim();
+    let (host, port) = if let Some(bracketed) = address.strip_prefix('[') {
+        let (host, rest) = bracketed
+            .split_once(']')
+            .context(format!("invalid address '{}': missing ']'", address))?;
+        match rest {
+            "" => (host, None),
+            _ => (
+                host,
+                Some(rest.strip_prefix(':').context(format!("invalid address '{}'", address))?),
+            ),
+        }
+    } else if address.matches(':').count() == 1 {
+        let (host, port) = address.split_once(':').unwrap_or_default();
+        (host, Some(port))
+    } else {
+        (address, None)
+    };
+    if host.is_empty() {
+        anyhow::bail!("invalid address '{}': host is empty", address);
+    }
+    let port = port
+        .map(|port| {
+            port.parse::<u16>()
+                .ok()
+                .filter(|port| is_valid_port(*port))
+                .context(format!("invalid port '{}' in '{}' (must be between 1 and 65535)", port, address))
+        })
+        .transpose()?;
+    Ok((host.to_string(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_config(jump_host: &str, target_host: &str) -> SshConfig {
+        SshConfig {
+            jump_host_user: "jumper".to_string(),
+            jump_host: jump_host.to_string(),
+            target_host_user: "deploy".to_string(),
+            target_host: target_host.to_string(),
+            jump_port: 22,
+            target_port: 2222,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_case_variants() {
+        let profiles: Vec<String> = ["Prod", "prod", "staging"].iter().map(|s| s.to_string()).collect();
+
+        assert_eq!(case_variants("PROD", &profiles), vec!["Prod", "prod"]);
+        assert_eq!(case_variants("prod", &profiles), vec!["Prod"]);
+        assert!(case_variants("staging", &profiles).is_empty());
+        assert!(case_variants("dev", &profiles).is_empty());
+    }
+
+    #[test]
+    fn test_sort_profile_names() {
+        let mut names: Vec<String> = ["staging", "Prod", "api", "prod"].iter().map(|s| s.to_string()).collect();
+
+        sort_profile_names(&mut names);
+
+        assert_eq!(names, vec!["api", "Prod", "prod", "staging"]);
+    }
+
+    #[test]
+    fn test_normalize_required() {
+        assert_eq!(normalize_required("  deploy  "), Some("deploy".to_string()));
+        assert_eq!(normalize_required("host\t\n"), Some("host".to_string()));
+        assert_eq!(normalize_required(""), None);
+        assert_eq!(normalize_required("   "), None);
+    }
+
+    #[test]
+    fn test_find_executable() {
+        assert!(find_executable("sh").is_some());
+        assert!(find_executable("/bin/sh").is_some());
+        assert!(find_executable("rush-tunnel-no-such-binary").is_none());
+        assert!(find_executable("/no/such/dir/ssh").is_none());
+    }
+
+    fn problem_messages(config: &SshConfig) -> Vec<String> {
+        config.validate().iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_validate_accepts_complete_profile() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.port_forward = Some(8080);
+
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn test_validate_reports_logical_problems() {
+        let mut config = sample_config("  ", "10.0.0.5");
+        config.target_port = 0;
+        config.port_forward = Some(0);
+        config.target_host_user = String::new();
+
+        assert_eq!(
+            config.validate(),
+            vec![
+                ConfigError::MissingField("jump_host"),
+                ConfigError::MissingField("target_host_user"),
+                ConfigError::InvalidPort {
+                    field: "target_port".to_string(),
+                    port: 0
+                },
+                ConfigError::InvalidPort {
+                    field: "port_forward".to_string(),
+                    port: 0
+                },
+            ]
+        );
+        assert_eq!(config.validate()[0].to_string(), "Missing jump host address");
+    }
+
+    #[test]
+    fn test_validate_proxy_command() {
+        let mut config = sample_config("", "10.0.0.5");
+        config.jump_host_user = String::new();
+        config.jump_port = 0;
+        config.proxy_command = Some("cloudflared access ssh --hostname %h".to_string());
+        assert!(config.validate().is_empty());
+
+        config.jump_host = "bastion".to_string();
+        config.proxy_command = Some(" ".to_string());
+        assert_eq!(
+            problem_messages(&config),
+            vec![
+                "proxy_command is empty",
+                "jump_host and proxy_command are both set; use one or the other"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_reports_forward_problems() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.port_forward = Some(8080);
+        config.forwards.push("8080:web:80".parse().unwrap());
+        config.forwards.push("5432:db:5432".parse().unwrap());
+        config.forwards[1].remote_port = 0;
+
+        assert_eq!(
+            problem_messages(&config),
+            vec!["forward 5432 remote_port 0 is out of range", "local port 8080 is used by more than one forward"]
+        );
+    }
+
+    #[test]
+    fn test_has_tag_is_case_insensitive() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.tags = vec!["Prod".to_string(), "team-a".to_string()];
+
+        assert!(config.has_tag("prod"));
+        assert!(config.has_tag("TEAM-A"));
+        assert!(!config.has_tag("staging"));
+    }
+
+    #[test]
+    fn test_matches_query() {
+        let mut config = sample_config("bastion.example.com", "db-01.internal");
+        config.tags = vec!["Postgres".to_string()];
+
+        assert!(config.matches_query("BASTION"));
+        assert!(config.matches_query("db-01"));
+        assert!(config.matches_query("deploy"));
+        assert!(config.matches_query("postgres"));
+        assert!(!config.matches_query("redis"));
+    }
+
+    #[test]
+    fn test_classify_profiles_dir_entry() {
+        assert!(matches!(classify_profiles_dir_entry("prod.toml", false), ProfilesDirEntry::Profile(name) if name == "prod"));
+        assert!(matches!(classify_profiles_dir_entry("config.toml", false), ProfilesDirEntry::Internal));
+        assert!(matches!(classify_profiles_dir_entry("last", false), ProfilesDirEntry::Internal));
+        assert!(matches!(classify_profiles_dir_entry("approved_local_commands", false), ProfilesDirEntry::Internal));
+        assert!(matches!(classify_profiles_dir_entry("cm-deploy@10.0.0.5:22", false), ProfilesDirEntry::Internal));
+        assert!(matches!(classify_profiles_dir_entry("staging.pid", false), ProfilesDirEntry::Internal));
+        assert!(matches!(classify_profiles_dir_entry("hosts.toml", false), ProfilesDirEntry::Internal));
+        assert!(matches!(classify_profiles_dir_entry("notes.txt", false), ProfilesDirEntry::Skipped(_)));
+        assert!(matches!(classify_profiles_dir_entry("prod.toml.tmp", false), ProfilesDirEntry::Skipped(_)));
+        assert!(matches!(classify_profiles_dir_entry("old.toml", true), ProfilesDirEntry::Skipped("directory")));
+    }
+
+    #[test]
+    fn test_bracket_host() {
+        assert_eq!(bracket_host("10.0.0.1"), "10.0.0.1");
+        assert_eq!(bracket_host("bastion.example.com"), "bastion.example.com");
+        assert_eq!(bracket_host("::1"), "[::1]");
+        assert_eq!(bracket_host("fe80::1%eth0"), "[fe80::1%eth0]");
+        assert_eq!(bracket_host("[::1]"), "[::1]");
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        let split = |address| split_host_port(address).unwrap();
+        assert_eq!(split("10.0.0.1:2222"), ("10.0.0.1".to_string(), Some(2222)));
+        assert_eq!(split("10.0.0.1"), ("10.0.0.1".to_string(), None));
+        assert_eq!(split("bastion.example.com:22"), ("bastion.example.com".to_string(), Some(22)));
+        assert_eq!(split(" bastion "), ("bastion".to_string(), None));
+        assert_eq!(split("[2001:db8::1]:2200"), ("2001:db8::1".to_string(), Some(2200)));
+        assert_eq!(split("[::1]"), ("::1".to_string(), None));
+        assert_eq!(split("fe80::1%eth0"), ("fe80::1%eth0".to_string(), None));
+
+        for address in ["bastion:", "bastion:0", "bastion:ssh", ":22", "[::1", "[::1]2200", "[]:22"] {
+            assert!(split_host_port(address).is_err(), "{}", address);
+        }
+    }
+
+    #[test]
+    fn test_build_ssh_args_ipv4() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.port_forward = Some(8080);
+
+        assert_eq!(
+            build_ssh_args(&config, &TunnelOptions::default()),
+            vec![
+                "-J",
+                "jumper@192.168.1.1:22",
+                "deploy@10.0.0.5",
+                "-p",
+                "2222",
+                "-L",
+                "8080:10.0.0.5:8080",
+                "-o",
+                "ExitOnForwardFailure=yes"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exit_on_forward_failure() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        assert!(!build_ssh_args(&config, &TunnelOptions::default()).contains(&"ExitOnForwardFailure=yes".to_string()));
+
+        config.port_forward = Some(8080);
+        config.options.exit_on_forward_failure = Some(false);
+        let args = build_ssh_args(&config, &TunnelOptions::default());
+        assert!(args.ends_with(&["-o".to_string(), "ExitOnForwardFailure=no".to_string()]));
+        assert!(!args.contains(&"ExitOnForwardFailure=yes".to_string()));
+    }
+
+    #[test]
+    fn test_build_ssh_args_proxy_command() {
+        let mut config = sample_config("", "10.0.0.5");
+        config.proxy_command = Some("cloudflared access ssh --hostname %h".to_string());
+
+        assert_eq!(
+            build_ssh_args(&config, &TunnelOptions::default()),
+            vec![
+                "-o",
+                "ProxyCommand=cloudflared access ssh --hostname %h",
+                "deploy@10.0.0.5",
+                "-p",
+                "2222"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_resolve_failure() {
+        assert!(is_resolve_failure(
+            "ssh: Could not resolve hostname bastion: Temporary failure in name resolution\r\n\
+             Connection closed by UNKNOWN port 65535\r\n"
+        ));
+        assert!(!is_resolve_failure("jumper@bastion: Permission denied (publickey,password).\r\n"));
+        assert!(!is_resolve_failure("Host key verification failed.\r\n"));
+        assert!(!is_resolve_failure(
+            "ssh: Could not resolve hostname old-name: Name or service not known\r\n\
+             deploy@10.0.0.5: Permission denied (password).\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_build_ssh_args_pre_proxy() {
+        let mut config = sample_config("bastion", "10.0.0.5");
+        config.pre_proxy = Some("socks5://127.0.0.1:1080".to_string());
+
+        let args = build_ssh_args(&config, &TunnelOptions::default());
+        assert!(!args.contains(&"-J".to_string()));
+        assert_eq!(args[0], "-o");
+        assert_eq!(
+            args[1],
+            "ProxyCommand=ssh -o 'ProxyCommand=nc -X 5 -x 127.0.0.1:1080 %%h %%p' -p 22 -W '[%h]:%p' 'jumper@bastion'"
+        );
+        assert_eq!(args[2], "deploy@10.0.0.5");
+
+        let options = TunnelOptions {
+            jump_only: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            build_ssh_args(&config, &options),
+            vec!["jumper@bastion", "-p", "22", "-o", "ProxyCommand=nc -X 5 -x 127.0.0.1:1080 %h %p"]
+        );
+
+        config.pre_proxy = Some("proxy.corp:3128".to_string());
+        assert!(config.validate()[0].to_string().starts_with("invalid pre_proxy 'proxy.corp:3128'"));
+    }
+
+    fn config_with_passwords(master_password: &str) -> SshConfig {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        let encrypted = encrypt_passwords(master_password, "jump-pw", "target-pw", Cipher::default()).unwrap();
+        config.set_encrypted_passwords(Some(encrypted));
+        config
+    }
+
+    fn jump_password(config: &SshConfig, master_password: &str) -> Result<String, UnlockError> {
+        decrypt_saved_passwords(config, master_password).map(|credentials| credentials.unwrap().jump_password)
+    }
+
+    #[test]
+    fn test_decrypt_without_a_usable_verifier() {
+        let mut config = config_with_passwords("master");
+
+        for verifier in [None, Some(""), Some("not-a-hash")] {
+            config.verifier = verifier.map(str::to_string);
+            assert_eq!(jump_password(&config, "master").unwrap(), "jump-pw");
+            assert_eq!(jump_password(&config, "wrong"), Err(UnlockError::WrongMasterPassword));
+        }
+    }
+
+    #[test]
+    fn test_decrypt_with_a_mismatched_verifier() {
+        let mut config = config_with_passwords("master");
+
+        // A verifier for another master password doesn't lock out the one the passwords were saved with.
+        config.verifier = Some(create_verifier("other").unwrap());
+        assert_eq!(jump_password(&config, "master").unwrap(), "jump-pw");
+
+        // When the verifier accepts a password the passwords don't decrypt with, they are what's broken.
+        assert!(matches!(jump_password(&config, "other"), Err(UnlockError::CorruptPasswords(_))));
+    }
+
+    #[test]
+    fn test_reencrypt_passwords() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        assert!(reencrypt_passwords(&config, "old", "new", Cipher::default()).unwrap().is_none());
+
+        let encrypted = encrypt_passwords("old master", "jump-pw", "target-pw", Cipher::default()).unwrap();
+        config.set_encrypted_passwords(Some(encrypted));
+        let err = reencrypt_passwords(&config, "wrong", "new master", Cipher::default()).err().unwrap();
+        assert!(err.to_string().contains("doesn't match"));
+
+        let rotated = reencrypt_passwords(&config, "old master", "new master", Cipher::Aes256Gcm).unwrap().unwrap();
+        assert!(verify_master_password("new master", &rotated.verifier).unwrap());
+        assert_eq!(decrypt_password("new master", &rotated.enc1).unwrap(), "jump-pw");
+        assert_eq!(decrypt_password("new master", &rotated.enc2).unwrap(), "target-pw");
+        assert!(decrypt_password("old master", &rotated.enc2).is_err());
+    }
+
+    #[test]
+    fn test_misspelt_profile_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.toml");
+        fs::write(
+            &path,
+            "schema_version = 1\njump_host_user = \"jumper\"\njump_host = \"bastion\"\n\
+             target_host_user = \"deploy\"\ntarget_host = \"10.0.0.5\"\ntarget_port = 22\n\
+             strict_host_key_cheking = \"yes\"\n",
+        )
+        .unwrap();
+
+        let err = read_profile_file("db", &path).unwrap_err();
+
+        assert!(
+            format!("{:#}", err).ends_with(
+                "exists but could not be parsed: unknown key `strict_host_key_cheking`, \
+                 did you mean `strict_host_key_checking`?"
+            ),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_plaintext_passwords_need_no_master_password() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.enc1 = Some(plaintext::encode("jump-pw"));
+        config.enc2 = Some(plaintext::encode("target-pw"));
+
+        assert_eq!(jump_password(&config, "anything").unwrap(), "jump-pw");
+        // change-master-password moves them to properly encrypted ones.
+        let encrypted = reencrypt_passwords(&config, "", "new master", Cipher::default()).unwrap().unwrap();
+        assert_eq!(decrypt_password("new master", &encrypted.enc2).unwrap(), "target-pw");
+    }
+
+    #[test]
+    fn test_key_passphrase_is_saved_alongside_passwords() {
+        let mut config = config_with_passwords("old master");
+        config.enc_key_passphrase = Some(encrypt_key_passphrase("old master", "key-pw", Cipher::default()).unwrap());
+
+        let credentials = decrypt_saved_passwords(&config, "old master").unwrap().unwrap();
+        assert_eq!(credentials.target_password, "target-pw");
+        assert_eq!(credentials.key_passphrase.as_deref().map(String::as_str), Some("key-pw"));
+
+        let rotated = reencrypt_key_passphrase(&config, "old master", "new master", Cipher::default()).unwrap();
+        assert_eq!(decrypt_password("new master", &rotated.unwrap()).unwrap(), "key-pw");
+        assert!(reencrypt_key_passphrase(&config, "wrong", "new master", Cipher::default()).is_err());
+    }
+
+    #[test]
+    fn test_key_passphrase_without_passwords() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.enc_key_passphrase = Some(encrypt_key_passphrase("master", "key-pw", Cipher::default()).unwrap());
+        assert!(config.has_saved_secrets() && !config.has_saved_passwords());
+
+        let credentials = decrypt_saved_passwords(&config, "master").unwrap().unwrap();
+        assert!(!credentials.has_passwords());
+        assert_eq!(credentials.key_passphrase.as_deref().map(String::as_str), Some("key-pw"));
+        assert!(matches!(decrypt_saved_passwords(&config, "wrong"), Err(UnlockError::WrongMasterPassword)));
+        // There are no passwords to move to the new master password.
+        assert!(reencrypt_passwords(&config, "master", "new", Cipher::default()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_saved_passwords_prefer_password_auth() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        let options = TunnelOptions {
+            credentials: Some(Credentials {
+                jump_password: "jump-secret".to_string(),
+                target_password: "target-secret".to_string(),
+                key_passphrase: None,
+            }),
+            ..Default::default()
+        };
+        let preferred = |args: Vec<String>| {
+            args.into_iter()
+                .filter(|arg| arg.starts_with("PreferredAuthentications"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(preferred(build_ssh_args(&config, &options)), vec!["PreferredAuthentications=password"]);
+        assert!(preferred(build_ssh_args(&config, &TunnelOptions::default())).is_empty());
+
+        config.options.auth_methods = Some(vec!["keyboard-interactive".to_string(), "password".to_string()]);
+        assert_eq!(
+            preferred(build_ssh_args(&config, &options)),
+            vec!["PreferredAuthentications=keyboard-interactive,password"]
+        );
+
+        // The key a passphrase was saved for has to be tried.
+        config.options.auth_methods = None;
+        let with_key = TunnelOptions {
+            credentials: Some(Credentials {
+                jump_password: "jump-secret".to_string(),
+                target_password: String::new(),
+                key_passphrase: Some(Zeroizing::new("key-secret".to_string())),
+            }),
+            ..Default::default()
+        };
+        assert!(preferred(build_ssh_args(&config, &with_key)).is_empty());
+    }
+
+    #[test]
+    fn test_saved_passwords_allow_one_prompt() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        let options = TunnelOptions {
+            credentials: Some(Credentials {
+                jump_password: "jump-secret".to_string(),
+                target_password: "target-secret".to_string(),
+                key_passphrase: None,
+            }),
+            ..Default::default()
+        };
+        let prompts = |args: Vec<String>| {
+            args.into_iter()
+                .filter(|arg| arg.starts_with("NumberOfPasswordPrompts"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(prompts(build_ssh_args(&config, &options)), vec!["NumberOfPasswordPrompts=1"]);
+        assert!(prompts(build_ssh_args(&config, &TunnelOptions::default())).is_empty());
+
+        config.options.password_prompts = Some(3);
+        assert_eq!(prompts(build_ssh_args(&config, &options)), vec!["NumberOfPasswordPrompts=3"]);
+    }
+
+    #[test]
+    fn test_saved_password_defaults_reach_the_jump_host() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        let options = TunnelOptions {
+            credentials: Some(Credentials {
+                jump_password: "jump-secret".to_string(),
+                target_password: "target-secret".to_string(),
+                key_passphrase: None,
+            }),
+            ..Default::default()
+        };
+        let hop = |args: Vec<String>| args.into_iter().find(|arg| arg.starts_with("ProxyCommand="));
+
+        // `-J` would start the jump host's ssh without it.
+        let args = build_ssh_args(&config, &options);
+        assert!(!args.contains(&"-J".to_string()));
+        assert_eq!(
+            hop(args).unwrap(),
+            "ProxyCommand=ssh -o 'PreferredAuthentications=password' -o 'NumberOfPasswordPrompts=1' -p 22 \
+             -W '[%h]:%p' 'jumper@192.168.1.1'"
+        );
+        assert!(hop(build_ssh_args(&config, &TunnelOptions::default())).is_none());
+
+        config.pre_proxy = Some("socks5://127.0.0.1:1080".to_string());
+        assert!(hop(build_ssh_args(&config, &options)).unwrap().contains(" -o 'NumberOfPasswordPrompts=1' -p 22 "));
+    }
+
+    #[test]
+    fn test_batch_mode() {
+        let config = sample_config("192.168.1.1", "10.0.0.5");
+        let batch = TunnelOptions {
+            batch: true,
+            ..Default::default()
+        };
+
+        assert!(build_ssh_args(&config, &batch).windows(2).any(|pair| pair == ["-o", "BatchMode=yes"]));
+        assert!(!build_ssh_args(&config, &TunnelOptions::default()).iter().any(|arg| arg.starts_with("BatchMode")));
+    }
+
+    #[test]
+    fn test_build_ssh_args_ipv6_jump_host() {
+        let config = sample_config("2001:db8::1", "10.0.0.5");
+
+        let args = build_ssh_args(&config, &TunnelOptions::default());
+
+        assert_eq!(args[1], "jumper@[2001:db8::1]:22");
+        assert_eq!(args[2], "deploy@10.0.0.5");
+    }
+
+    #[test]
+    fn test_build_ssh_args_ipv6_target_host() {
+        let mut config = sample_config("192.168.1.1", "::1");
+        config.port_forward = Some(8080);
+
+        let args = build_ssh_args(&config, &TunnelOptions::default());
+
+        assert_eq!(args[1], "jumper@192.168.1.1:22");
+        assert_eq!(args[2], "deploy@[::1]");
+        assert_eq!(args[6], "8080:[::1]:8080");
+    }
+
+    #[test]
+    fn test_build_ssh_args_with_bind_address() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.port_forward = Some(8080);
+        config.bind_address = Some("0.0.0.0".to_string());
+        assert_eq!(build_ssh_args(&config, &TunnelOptions::default())[6], "0.0.0.0:8080:10.0.0.5:8080");
+
+        config.bind_address = Some("::".to_string());
+        assert_eq!(build_ssh_args(&config, &TunnelOptions::default())[6], "[::]:8080:10.0.0.5:8080");
+    }
+
+    #[test]
+    fn test_forward_remote_port_is_independent_of_ssh_port() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.port_forward = Some(15432);
+        config.forward_remote_port = Some(5432);
+
+        let args = build_ssh_args(&config, &TunnelOptions::default());
+
+        assert_eq!(args[4], "2222");
+        assert_eq!(args[6], "15432:10.0.0.5:5432");
+    }
+
+    #[test]
+    fn test_forward_remote_host() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.port_forward = Some(5432);
+        config.forward_remote_host = Some("localhost".to_string());
+
+        assert_eq!(build_ssh_args(&config, &TunnelOptions::default())[6], "5432:localhost:5432");
+    }
+
+    #[test]
+    fn test_bind_forwards_publicly() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.port_forward = Some(8080);
+        config.forwards.push("127.0.0.1:15432:db:5432".parse().unwrap());
+
+        config.bind_forwards_publicly();
+
+        let args = build_ssh_args(&config, &TunnelOptions::default());
+        assert!(args.contains(&"0.0.0.0:8080:10.0.0.5:8080".to_string()));
+        assert!(args.contains(&"0.0.0.0:15432:db:5432".to_string()));
+    }
+
+    #[test]
+    fn test_build_ssh_args_with_extra_forwards() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.port_forward = Some(8080);
+        config.forwards.push("15432:internal-db:5432".parse().unwrap());
+
+        let args = build_ssh_args(&config, &TunnelOptions::default());
+
+        assert_eq!(
+            args[5..],
+            ["-L", "8080:10.0.0.5:8080", "-L", "15432:internal-db:5432", "-o", "ExitOnForwardFailure=yes"]
+        );
+    }
+
+    #[test]
+    fn test_profile_round_trip_with_forwards() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.options.compression = Some(true);
+        config.forwards.push("127.0.0.1:15432:internal-db:5432".parse().unwrap());
+
+        let toml_str = toml::to_string(&config).unwrap();
+        let loaded: SshConfig = toml::from_str(&toml_str).unwrap();
+
+        assert_eq!(loaded.forwards, config.forwards);
+        assert_eq!(loaded.options, config.options);
+        let without_forwards = toml::to_string(&sample_config("192.168.1.1", "10.0.0.5")).unwrap();
+        assert!(!without_forwards.contains("forwards"));
+    }
+
+    #[test]
+    fn test_build_ssh_args_with_exec() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.options.compression = Some(true);
+        let options = TunnelOptions {
+            exec: Some("uptime -p".to_string()),
+            ..Default::default()
+        };
+
+        let args = build_ssh_args(&config, &options);
+
+        assert_eq!(args.last().unwrap(), "uptime -p");
+        assert_eq!(args[args.len() - 2], "Compression=yes");
+    }
+
+    #[test]
+    fn test_build_ssh_args_local_command() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.local_command = Some("open http://localhost:8080".to_string());
+
+        let args = build_ssh_args(&config, &TunnelOptions::default());
+        assert!(args.ends_with(&[
+            "-o".to_string(),
+            "PermitLocalCommand=yes".to_string(),
+            "-o".to_string(),
+            "LocalCommand=open http://localhost:8080".to_string()
+        ]));
+
+        let options = TunnelOptions {
+            jump_only: true,
+            ..Default::default()
+        };
+        assert!(!build_ssh_args(&config, &options).iter().any(|arg| arg.contains("LocalCommand")));
+    }
+
+    #[test]
+    fn test_build_ssh_args_agent_forwarding() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        assert!(!build_ssh_args(&config, &TunnelOptions::default()).contains(&"-A".to_string()));
+
+        let options = TunnelOptions {
+            agent_forwarding: true,
+            ..Default::default()
+        };
+        assert!(build_ssh_args(&config, &options).contains(&"-A".to_string()));
+
+        config.agent_forwarding = true;
+        assert!(build_ssh_args(&config, &TunnelOptions::default()).contains(&"-A".to_string()));
+        assert!(!toml::to_string(&sample_config("192.168.1.1", "10.0.0.5"))
+            .unwrap()
+            .contains("agent_forwarding"));
+    }
+
+    #[test]
+    fn test_build_ssh_args_multiplex() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        assert!(!build_ssh_args(&config, &TunnelOptions::default()).contains(&"ControlMaster=auto".to_string()));
+
+        config.multiplex = true;
+        let args = build_ssh_args(&config, &TunnelOptions::default());
+        assert!(args.contains(&"ControlMaster=auto".to_string()));
+        assert!(args.iter().any(|arg| arg.starts_with("ControlPath=") && arg.ends_with("cm-%r@%h:%p")));
+        assert!(args.contains(&"ControlPersist=600".to_string()));
+        assert!(config.validate().is_empty());
+
+        config.multiplex = false;
+        config.control_persist = Some(60);
+        assert_eq!(problem_messages(&config), vec!["control_persist is set but multiplex is off"]);
+    }
+
+    #[test]
+    fn test_build_ssh_args_tty() {
+        let config = sample_config("192.168.1.1", "10.0.0.5");
+        let args_with_tty = |tty| {
+            let options = TunnelOptions {
+                exec: Some("top".to_string()),
+                tty,
+                ..Default::default()
+            };
+            build_ssh_args(&config, &options)
+        };
+
+        assert!(args_with_tty(Some(true)).ends_with(&["-t".to_string(), "top".to_string()]));
+        assert!(args_with_tty(Some(false)).ends_with(&["-T".to_string(), "top".to_string()]));
+        let args = args_with_tty(None);
+        assert!(!args.contains(&"-t".to_string()) && !args.contains(&"-T".to_string()));
+    }
+
+    #[test]
+    fn test_build_ssh_args_jump_only() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.jump_port = 2200;
+        config.port_forward = Some(8080);
+        let options = TunnelOptions {
+            jump_only: true,
+            exec: Some("hostname".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            build_ssh_args(&config, &options),
+            vec!["jumper@192.168.1.1", "-p", "2200", "hostname"]
+        );
+    }
+
+    #[test]
+    fn test_build_ssh_args_forward_only() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.port_forward = Some(8080);
+        let options = TunnelOptions {
+            forward_only: true,
+            ..Default::default()
+        };
+
+        let args = build_ssh_args(&config, &options);
+
+        assert_eq!(args.last().unwrap(), "-N");
+        assert!(!build_ssh_args(&config, &TunnelOptions::default()).contains(&"-N".to_string()));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_stop_child_reaps_after_deadline() {
+        let mut child = tokio::process::Command::new("sleep").arg("30").spawn().unwrap();
+        let started = Instant::now();
+
+        assert!(wait_until(&mut child, Some(started + Duration::from_millis(200))).await.unwrap().is_none());
+        stop_child(&mut child).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(child.try_wait().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_is_listening() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let forward: LocalForward = format!("{}:web:80", port).parse().unwrap();
+        assert!(is_listening(&forward));
+
+        drop(listener);
+        assert!(!is_listening(&forward));
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_returns_status_before_deadline() {
+        let mut child = tokio::process::Command::new("true").spawn().unwrap();
+
+        let status = wait_until(&mut child, Some(Instant::now() + Duration::from_secs(10))).await.unwrap();
+
+        assert!(status.unwrap().success());
+    }
+
+    #[test]
+    fn test_without_secrets_drops_verifier() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.enc1 = Some("secret1".to_string());
+        config.verifier = Some("hash".to_string());
+        config.enc_key_passphrase = Some("secret3".to_string());
+
+        let public = config.without_secrets();
+
+        assert!(public.enc1.is_none());
+        assert!(public.verifier.is_none());
+        assert!(public.enc_key_passphrase.is_none());
+    }
+
+    #[test]
+    fn test_with_masked_secrets() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.enc1 = Some("secret1".to_string());
+        config.verifier = Some("hash".to_string());
+
+        let masked = config.with_masked_secrets();
+
+        assert_eq!(masked.enc1.as_deref(), Some(MASKED_SECRET));
+        assert_eq!(masked.enc2, None);
+        assert_eq!(masked.verifier.as_deref(), Some(MASKED_SECRET));
+        assert_eq!(masked.target_host, config.target_host);
+    }
+
+    #[test]
+    fn test_profile_round_trip_with_options() {
+        let mut config = sample_config("192.168.1.1", "10.0.0.5");
+        config.options.compression = Some(true);
+
+        let toml_str = toml::to_string(&config).unwrap();
+        let loaded: SshConfig = toml::from_str(&toml_str).unwrap();
+
+        assert!(toml_str.contains("compression = true"));
+        assert!(!toml_str.contains("server_alive_interval"));
+        assert_eq!(loaded.options, config.options);
+    }
+
+    #[test]
+    fn test_is_loopback_address() {
+        assert!(is_loopback_address("127.0.0.1"));
+        assert!(is_loopback_address("localhost"));
+        assert!(is_loopback_address("::1"));
+        assert!(!is_loopback_address("0.0.0.0"));
+        assert!(!is_loopback_address("192.168.1.10"));
+    }
+}
@@ -1,326 +1,2932 @@
 use std::fs;
-use std::path::Path;
-use std::process::Command;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{Context, Result};
-use inquire::{Confirm, CustomType, Password, Select, Text};
-use serde::{Deserialize, Serialize};
+use inquire::validator::Validation;
+use inquire::{Confirm, CustomType, MultiSelect, Password, Select, Text};
+use structopt::clap::{AppSettings, Shell};
 use structopt::StructOpt;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::EnvFilter;
+use zeroize::Zeroizing;
 
-use crate::crypto::{encrypt_password, is_password_strong};
+use rush_tunnel::askpass::Credentials;
+use rush_tunnel::config::{AddressFamily, GlobalConfig, SshOptions};
+use rush_tunnel::crypto::is_password_strong;
+use rush_tunnel::doctor::{self, CheckStatus};
+use rush_tunnel::formats::Format;
+use rush_tunnel::forward::{duplicate_local_ports, local_port_problem, LocalForward};
+use rush_tunnel::proxy::PreProxy;
+use rush_tunnel::report::{ReportFormat, SessionReport};
+use rush_tunnel::table::{self, Column, ListFormat, ProfileSort, DEFAULT_COLUMNS};
+use rush_tunnel::{
+    apply_global_defaults, askpass, backup, bracket_host, case_variants, copy_id, decrypt_saved_passwords,
+    encrypt_key_passphrase, encrypt_passwords, establish_tunnel, formats, fsutil, get_profiles_dir, hostbook, import,
+    index, is_valid_port, join_problems, list_profiles, load_profile, migrate, multiplex, normalize_required, openssh,
+    paths, pidfile, plaintext, purge, reencrypt_key_passphrase, reencrypt_passwords, save_profile, scan_profiles,
+    select, spawn_tunnel, split_host_port, state, style, validate_profile_name, ConfigError, SessionTimedOut, SshConfig,
+    BackgroundTunnel, TerminationSignals, TunnelOptions, TunnelOutcome, UnlockError,
+};
 
-mod crypto;
+// Enough rows to see a screenful of profiles without scrolling; typing narrows the list further.
+const PROFILE_PAGE_SIZE: usize = 15;
+const TUNNEL_STARTUP_GRACE: Duration = Duration::from_secs(3);
+const TUNNEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// Same status as coreutils `timeout`, so scripts can tell a timed-out session from an ssh failure.
+const SESSION_TIMEOUT_EXIT_CODE: u8 = 124;
 
 #[derive(StructOpt)]
 #[structopt(name = "rush-tunnel", about = "SSH Tunnel CLI")]
-enum Cli {
-    #[structopt(about = "Interactive mode")]
-    Interactive,
+struct Cli {
+    #[structopt(short, long, global = true, help = "Suppress informational output")]
+    quiet: bool,
 
-    #[structopt(about = "Create SSH tunnel")]
-    Tunnel {
-        #[structopt(long, help = "Jump host username")]
-        jump_host_user: Option<String>,
+    #[structopt(
+        long,
+        global = true,
+        env = "RUSH_TUNNEL_PROFILES_DIR",
+        parse(try_from_str = paths::expand_path),
+        help = "Directory holding profiles (default: ~/.rush-tunnel)"
+    )]
+    profiles_dir: Option<PathBuf>,
 
-        #[structopt(long, help = "Jump host address")]
-        jump_host_address: Option<String>,
+    #[structopt(
+        long,
+        global = true,
+        help = "Log level: off, error, warn, info, debug or trace (default: RUST_LOG, else warn)"
+    )]
+    log_level: Option<LevelFilter>,
 
-        #[structopt(long, help = "Target host username")]
-        target_host_user: Option<String>,
+    #[structopt(long, global = true, help = "Disable colored output (also honours NO_COLOR)")]
+    no_color: bool,
 
-        #[structopt(long, help = "Target host address")]
-        target_host_address: Option<String>,
+    #[structopt(long, global = true, help = "Allow forwards on local ports below 1024 without running as root")]
+    allow_privileged: bool,
 
-        #[structopt(long, help = "Jump host SSH port (default: 22)")]
-        jump_port: Option<i16>,
+    #[structopt(
+        long,
+        global = true,
+        help = "Make ssh fail instead of prompting (BatchMode=yes), for scripts; needs key authentication"
+    )]
+    batch: bool,
 
-        #[structopt(long, help = "Target host SSH port (default: 22)")]
-        target_port: Option<i16>,
+    #[structopt(subcommand)]
+    command: Option<Subcommand>,
+}
 
-        #[structopt(long, help = "Port to forward (default: no)")]
-        port_forward: Option<i16>,
+// Parsed once per run, so a few hundred bytes for the flag-heavy variants don't matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(StructOpt)]
+enum Subcommand {
+    #[structopt(about = "Interactive mode")]
+    Interactive {
+        #[structopt(flatten)]
+        save: SaveArgs,
     },
 
+    #[structopt(about = "Create SSH tunnel")]
+    Tunnel(TunnelArgs),
+
     #[structopt(about = "Connect to SSH tunnel with profile name")]
     Connect {
-        #[structopt(long, help = "Profile name to use")]
-        profile: Option<String>,
+        #[structopt(
+            long,
+            number_of_values = 1,
+            help = "Profile name or pattern such as 'staging-*' (repeatable, to bring up several tunnels at once)"
+        )]
+        profile: Vec<String>,
+
+        #[structopt(long, conflicts_with = "profile", help = "Use the last successfully connected profile")]
+        last: bool,
+
+        #[structopt(long, conflicts_with_all = &["profile", "last"], help = "Connect to every profile")]
+        all: bool,
+
+        #[structopt(long, requires = "all", help = "With --all, only connect to profiles with this tag")]
+        tag: Option<String>,
+
+        #[structopt(long, help = "Run a command on the target instead of opening a shell")]
+        exec: Option<String>,
+
+        #[structopt(
+            long = "local-forward",
+            value_name = "forward",
+            number_of_values = 1,
+            help = "Extra forward for this run only, as [bind_address:]port:host:hostport (repeatable)"
+        )]
+        local_forwards: Vec<LocalForward>,
+
+        #[structopt(
+            long,
+            value_name = "local_port",
+            number_of_values = 1,
+            help = "Only open the profile's forward on this local port for this run (repeatable)"
+        )]
+        only_forward: Vec<u16>,
+
+        #[structopt(
+            long,
+            conflicts_with = "only-forward",
+            help = "Pick which of the profile's forwards to open for this run from a list"
+        )]
+        select_forward: bool,
+
+        #[structopt(short, long, help = "Don't ask for confirmation (saved passwords, --jump-only with forwards)")]
+        yes: bool,
+
+        #[structopt(short = "A", long = "agent-forward", help = "Forward your SSH agent to the target for this run")]
+        agent_forward: bool,
+
+        #[structopt(long, help = "Connect even if a forward can't bind its local port")]
+        no_exit_on_forward_failure: bool,
+
+        #[structopt(short = "4", long, help = "Connect over IPv4 only (ssh -4)")]
+        ipv4: bool,
+
+        #[structopt(short = "6", long, conflicts_with = "ipv4", help = "Connect over IPv6 only (ssh -6)")]
+        ipv6: bool,
+
+        #[structopt(long, help = "Connect to the jump host itself, without the target or forwards")]
+        jump_only: bool,
+
+        #[structopt(long, help = "Bind the forwards to all interfaces (0.0.0.0) for this run, sharing them")]
+        public_forward: bool,
+
+        #[structopt(long, value_name = "user", help = "Log in to the jump host as this user for this run")]
+        jump_user: Option<String>,
+
+        #[structopt(long, value_name = "user", help = "Log in to the target as this user for this run")]
+        target_user: Option<String>,
+
+        #[structopt(long, help = "Force a pseudo-terminal, e.g. for interactive --exec programs (ssh -t)")]
+        tty: bool,
+
+        #[structopt(long, conflicts_with = "tty", help = "Never allocate a pseudo-terminal (ssh -T)")]
+        no_tty: bool,
+
+        #[structopt(
+            long,
+            value_name = "secs",
+            help = "Close the session after this many seconds, exiting with status 124"
+        )]
+        session_timeout: Option<u64>,
+
+        #[structopt(long, value_name = "format", help = "Print how the connection ended to stderr on exit (json)")]
+        report: Option<ReportFormat>,
+
+        #[structopt(long, help = "Print each forward's URL, e.g. http://127.0.0.1:8080, once it is listening")]
+        print_url: bool,
+
+        #[structopt(
+            long,
+            conflicts_with_all = &["exec", "tty", "jump-only"],
+            help = "Only hold the forwards open, without a shell on the target (ssh -N), until Ctrl-C"
+        )]
+        local_forward_only: bool,
+
+        #[structopt(long, help = "Close the profile's tunnel already running in the background before starting")]
+        replace: bool,
+
+        #[structopt(
+            long,
+            value_name = "count",
+            default_value = "0",
+            help = "Retry this many times when ssh can't resolve a host name (never on other failures)"
+        )]
+        dns_retries: u32,
+
+        #[structopt(long, help = "Print the merged profile, passwords masked, and exit without connecting")]
+        print_config: bool,
+
+        #[structopt(
+            long,
+            requires = "print-config",
+            help = "Format of --print-config, toml, yaml or json (default: toml)"
+        )]
+        format: Option<Format>,
+
+        #[structopt(flatten)]
+        options: SshOptions,
+    },
+
+    #[structopt(about = "Reconnect to the last successfully connected profile")]
+    Reconnect {
+        #[structopt(short, long, help = "Connect with saved passwords without asking for confirmation")]
+        yes: bool,
+    },
+
+    #[structopt(about = "Set the profile used by connect when --profile is omitted")]
+    SetDefault {
+        #[structopt(help = "Profile name to use by default")]
+        profile: String,
+    },
+
+    #[structopt(about = "Clear the default profile")]
+    UnsetDefault,
+
+    #[structopt(about = "Remove saved passwords from a profile")]
+    ClearPasswords {
+        #[structopt(help = "Profile name to clear passwords from")]
+        profile: String,
+
+        #[structopt(short, long, help = "Clear the passwords without asking for confirmation")]
+        yes: bool,
+    },
+
+    #[structopt(about = "Encrypt the saved passwords of profiles again with a new master password")]
+    ChangeMasterPassword {
+        #[structopt(
+            help = "Profiles or patterns such as 'prod-*' to change (default: every profile with saved passwords)"
+        )]
+        profiles: Vec<String>,
+
+        #[structopt(long, help = "Check that every profile can be changed, without writing anything")]
+        dry_run: bool,
+
+        #[structopt(
+            long,
+            conflicts_with = "dry-run",
+            help = "Change the profiles that can be changed even if others can't"
+        )]
+        force: bool,
+    },
+
+    #[structopt(about = "Install your public key on a profile's target through its jump host (ssh-copy-id)")]
+    CopyId {
+        #[structopt(help = "Profile name whose target gets the key")]
+        profile: String,
+
+        #[structopt(
+            long,
+            parse(try_from_str = paths::expand_path),
+            help = "Public key to install (default: ~/.ssh/id_ed25519.pub, id_ecdsa.pub, id_rsa.pub or id_*.pub)"
+        )]
+        key: Option<PathBuf>,
     },
 
     #[structopt(about = "List all profiles")]
-    Profiles,
-
-    #[structopt(about = "Show the profiles directory path")]
-    Path,
-}
-
-#[derive(Serialize, Deserialize, Default)]
-pub struct SshConfig {
-    jump_host_user: String,
-    jump_host: String,
-    target_host_user: String,
-    target_host: String,
-    jump_port: i16,
-    target_port: i16,
-    port_forward: Option<i16>,
-    enc1: Option<String>,
-    enc2: Option<String>,
-}
-
-impl SshConfig {
-    fn from_interactive_input() -> Result<Self> {
-        let jump_host_user = prompt_input("Enter jump host username:")?;
-        let jump_host = prompt_input("Enter jump host address:")?;
-        let target_host_user = prompt_input("Enter target host username:")?;
-        let target_host = prompt_input("Enter target host address:")?;
-        let jump_port = prompt_port("Enter jump host SSH port (default: 22):", 22)?;
-        let target_port = prompt_port("Enter target host SSH port (default: 22):", 22)?;
-        let port_forward = CustomType::<i16>::new("Port-Forward? (default: no)")
-            .prompt_skippable()
-            .context("Failed to confirm port-forward")?;
-
-        let (enc1, enc2) = if Confirm::new("Save password?").with_default(false).prompt()? {
-            get_encrypted_passwords()?
-        } else {
-            (None, None)
-        };
+    Profiles {
+        #[structopt(long, help = "Only list profiles with this tag")]
+        tag: Option<String>,
 
-        Ok(SshConfig {
-            jump_host_user,
-            jump_host,
-            target_host_user,
-            target_host,
-            jump_port,
-            target_port,
-            port_forward,
-            enc1,
-            enc2,
-        })
+        #[structopt(short, long, help = "Also report skipped and unreadable files")]
+        verbose: bool,
+
+        #[structopt(long, default_value = "plain", help = "Output format: plain or table")]
+        format: ListFormat,
+
+        #[structopt(
+            long,
+            use_delimiter = true,
+            help = "Comma-separated table columns: name, jump, target, forward, tags (default: all but tags)"
+        )]
+        columns: Vec<Column>,
+
+        #[structopt(
+            long,
+            default_value = "name",
+            help = "Order by name, created, updated or last-used; times list the newest first"
+        )]
+        sort: ProfileSort,
+
+        #[structopt(long, help = "List in the opposite order")]
+        reverse: bool,
+    },
+
+    #[structopt(about = "Find profiles by host, user or tag")]
+    Search {
+        #[structopt(help = "Text to search for (case-insensitive)")]
+        query: String,
+    },
+
+    #[structopt(about = "Show the details of a profile")]
+    Show {
+        #[structopt(required = true, help = "Profile names or patterns such as 'staging-*' to show")]
+        profiles: Vec<String>,
+
+        #[structopt(long, help = "Print the profile as toml, yaml or json instead of a summary")]
+        format: Option<Format>,
+    },
+
+    #[structopt(about = "Show the profiles directory path, and whether it exists and is writable")]
+    Path {
+        #[structopt(long, help = "Create the directory (and its parents) if it doesn't exist")]
+        create: bool,
+
+        #[structopt(long, help = "Print the path, exists and writable as JSON")]
+        json: bool,
+    },
+
+    #[structopt(about = "Check that every profile parses and is usable")]
+    Validate,
+
+    #[structopt(about = "Report ssh, profiles directory and environment diagnostics")]
+    Doctor,
+
+    #[structopt(about = "Print a JSON Schema describing the profile file format")]
+    Schema,
+
+    #[structopt(about = "Export a profile as an ssh_config host block")]
+    Export {
+        #[structopt(help = "Profile name to export")]
+        profile: String,
+
+        #[structopt(long, help = "Append the block to ~/.ssh/config")]
+        append: bool,
+
+        #[structopt(long, conflicts_with = "append", help = "Export the profile as toml, yaml or json instead")]
+        format: Option<Format>,
+    },
+
+    #[structopt(about = "Import a profile from a toml, yaml or json file, or the hosts of an ssh config")]
+    Import {
+        #[structopt(
+            parse(try_from_str = paths::expand_path),
+            required_unless = "ssh-config",
+            help = "Path of the profile file to import (with --ssh-config, default: ~/.ssh/config)"
+        )]
+        file: Option<PathBuf>,
+
+        #[structopt(long, help = "Name to save the profile as (default: the file name)")]
+        name: Option<String>,
+
+        #[structopt(long, help = "File format, toml, yaml or json (default: from the file extension)")]
+        format: Option<Format>,
+
+        #[structopt(
+            long,
+            conflicts_with_all = &["name", "format"],
+            help = "Import every host with a ProxyJump or ProxyCommand from an ssh config, following Include"
+        )]
+        ssh_config: bool,
+
+        #[structopt(
+            long,
+            requires = "ssh-config",
+            help = "Update existing profiles in place, keeping their saved passwords and tags, instead of asking"
+        )]
+        merge: bool,
+    },
+
+    #[structopt(about = "Back up all profiles into a single archive")]
+    Backup {
+        #[structopt(parse(try_from_str = paths::expand_path), help = "Path of the backup file to write")]
+        out: PathBuf,
+    },
+
+    #[structopt(about = "Restore profiles from a backup archive")]
+    Restore {
+        #[structopt(parse(try_from_str = paths::expand_path), help = "Path of the backup file to restore")]
+        file: PathBuf,
+    },
+
+    #[structopt(about = "Delete all profiles, saved state and the global config, or only the named profiles")]
+    Purge {
+        #[structopt(help = "Only delete these profiles, by name or pattern such as 'staging-*'")]
+        profiles: Vec<String>,
+    },
+
+    #[structopt(about = "Remove control sockets left behind by multiplexed connections that are gone")]
+    CleanSockets,
+
+    #[structopt(about = "Print or install a shell completion script (bash, zsh, fish, powershell or elvish)")]
+    Completions {
+        #[structopt(
+            possible_values = &Shell::variants(),
+            case_insensitive = true,
+            help = "Shell to complete for (default: from $SHELL)"
+        )]
+        shell: Option<Shell>,
+
+        #[structopt(long, help = "Write the script where the shell looks for completions, instead of printing it")]
+        install: bool,
+    },
+
+    // Called by the bash and zsh completion scripts, so profile names stay current without
+    // regenerating the scripts.
+    #[structopt(name = "list-profile-names", setting = AppSettings::Hidden)]
+    ListProfileNames,
+}
+
+#[derive(StructOpt)]
+struct TunnelArgs {
+    #[structopt(long, value_name = "profile", help = "Start from this profile, overriding only the fields given")]
+    clone_from: Option<String>,
+
+    #[structopt(long, help = "Jump host username")]
+    jump_host_user: Option<String>,
+
+    #[structopt(long, help = "Jump host address, optionally with a port as host:port")]
+    jump_host_address: Option<String>,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["jump-host-user", "jump-host-address", "jump-port", "jump-password-file"],
+        help = "Command to reach the target through instead of a jump host (ssh ProxyCommand)"
+    )]
+    proxy_command: Option<String>,
+
+    #[structopt(
+        long,
+        value_name = "url",
+        conflicts_with = "proxy-command",
+        parse(try_from_str = parse_pre_proxy),
+        help = "HTTP or SOCKS proxy to reach the jump host through, e.g. http://proxy.corp:3128 (needs nc or corkscrew)"
+    )]
+    pre_proxy: Option<String>,
+
+    #[structopt(long, help = "Target host username")]
+    target_host_user: Option<String>,
+
+    #[structopt(long, help = "Target host address, optionally with a port as host:port")]
+    target_host_address: Option<String>,
+
+    #[structopt(long, help = "Jump host SSH port (default: 22)")]
+    jump_port: Option<u16>,
+
+    #[structopt(long, help = "Target host SSH port (default: 22)")]
+    target_port: Option<u16>,
+
+    #[structopt(long, help = "Port to forward (default: no)")]
+    port_forward: Option<u16>,
+
+    #[structopt(long, help = "Local address the forward binds to (default: 127.0.0.1)")]
+    bind_address: Option<String>,
+
+    #[structopt(
+        long,
+        conflicts_with = "bind-address",
+        help = "Bind the forwards to all interfaces (0.0.0.0), sharing them on the network"
+    )]
+    public_forward: bool,
+
+    #[structopt(long, help = "Host the forward connects to, as seen from the target (default: target host)")]
+    forward_remote_host: Option<String>,
+
+    #[structopt(long, help = "Port the forward connects to (default: same as the forwarded port)")]
+    forward_remote_port: Option<u16>,
+
+    #[structopt(
+        long = "local-forward",
+        value_name = "forward",
+        number_of_values = 1,
+        help = "Additional forward, as [bind_address:]port:host:hostport (repeatable)"
+    )]
+    local_forwards: Vec<LocalForward>,
+
+    #[structopt(long, use_delimiter = true, help = "Comma-separated tags for the profile")]
+    tags: Vec<String>,
+
+    #[structopt(long, value_name = "text", help = "Free-text note shown by show, e.g. who to ask before using it")]
+    description: Option<String>,
+
+    #[structopt(short = "A", long = "agent-forward", help = "Forward your SSH agent to the target")]
+    agent_forward: bool,
+
+    #[structopt(long, help = "Reuse one master connection per target across runs (ControlMaster)")]
+    multiplex: bool,
+
+    #[structopt(
+        long,
+        value_name = "secs",
+        requires = "multiplex",
+        help = "Seconds the master connection stays open after the last session (default: 600)"
+    )]
+    control_persist: Option<u32>,
+
+    #[structopt(long, value_name = "command", help = "Command to run on this machine once connected (LocalCommand)")]
+    local_command: Option<String>,
+
+    #[structopt(long, value_name = "command", help = "Shell command to run on this machine once the tunnel is up")]
+    on_connect: Option<String>,
+
+    #[structopt(long, value_name = "command", help = "Shell command to run on this machine after the tunnel closes")]
+    on_disconnect: Option<String>,
+
+    #[structopt(long, help = "Connect even if a forward can't bind its local port")]
+    no_exit_on_forward_failure: bool,
+
+    #[structopt(short = "4", long, help = "Connect over IPv4 only (ssh -4), saved with the profile")]
+    ipv4: bool,
+
+    #[structopt(
+        short = "6",
+        long,
+        conflicts_with = "ipv4",
+        help = "Connect over IPv6 only (ssh -6), saved with the profile"
+    )]
+    ipv6: bool,
+
+    #[structopt(long, help = "File to read the jump host password from at connect time")]
+    jump_password_file: Option<String>,
+
+    #[structopt(long, help = "File to read the target host password from at connect time")]
+    target_password_file: Option<String>,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["no-save", "insecure-plaintext"],
+        help = "Save the passphrase of --identity-file, encrypted, so ssh doesn't ask for it (needs a strong master password)"
+    )]
+    key_passphrase: bool,
+
+    // Only flags that make sense for a single run can be combined with it; the rest come from stdin.
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "clone-from", "jump-host-user", "jump-host-address", "proxy-command", "pre-proxy", "target-host-user",
+            "target-host-address", "jump-port", "target-port", "port-forward", "bind-address", "public-forward",
+            "forward-remote-host", "forward-remote-port", "tags", "description", "local-command", "on-connect",
+            "on-disconnect", "jump-password-file", "target-password-file", "profile-name", "no-save",
+            "save-password", "key-passphrase", "insecure-plaintext",
+        ],
+        help = "Read a whole profile from stdin and connect with it, without saving anything"
+    )]
+    stdin_config: bool,
+
+    #[structopt(
+        long,
+        help = "Format of --stdin-config and --print-config, toml, yaml or json (default: detected from the input, \
+                toml for output)"
+    )]
+    format: Option<Format>,
+
+    #[structopt(long, help = "Print the merged profile, passwords masked, and exit without saving or connecting")]
+    print_config: bool,
+
+    #[structopt(flatten)]
+    save: SaveArgs,
+
+    #[structopt(flatten)]
+    options: SshOptions,
+}
+
+#[derive(StructOpt, Clone)]
+struct SaveArgs {
+    #[structopt(
+        long,
+        value_name = "name",
+        parse(try_from_str = parse_profile_name),
+        help = "Save the profile under this name instead of asking for one"
+    )]
+    profile_name: Option<String>,
+
+    #[structopt(long, conflicts_with = "profile-name", help = "Connect without saving a profile")]
+    no_save: bool,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["no-save", "no-save-password"],
+        help = "Save the passwords with the profile without asking"
+    )]
+    save_password: bool,
+
+    #[structopt(long, help = "Don't save the passwords, without asking")]
+    no_save_password: bool,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["no-save", "no-save-password"],
+        help = "Save the passwords unencrypted, for testing only (needs RUSH_TUNNEL_INSECURE_PLAINTEXT=I_UNDERSTAND)"
+    )]
+    insecure_plaintext: bool,
+}
+
+impl SaveArgs {
+    // Nothing is saved with --no-save, so the passwords are asked for when connecting instead.
+    fn wants_saved_passwords(&self) -> Result<bool> {
+        if self.no_save || self.no_save_password {
+            return Ok(false);
+        }
+        if self.save_password || self.insecure_plaintext {
+            return Ok(true);
+        }
+        let default = GlobalConfig::load()?.save_password_default.unwrap_or(false);
+        Ok(Confirm::new("Save password?").with_default(default).prompt()?)
     }
+}
 
-    fn from_non_interactive_input(
-        jump_host_user: Option<String>,
-        jump_host_address: Option<String>,
-        target_host_user: Option<String>,
-        target_host_address: Option<String>,
-        jump_port: Option<i16>,
-        target_port: Option<i16>,
-        port_forward: Option<i16>,
-    ) -> Result<Self> {
-        let jump_host_user = jump_host_user.ok_or_else(|| anyhow::anyhow!("Missing jump host username"))?;
-        let jump_host = jump_host_address.ok_or_else(|| anyhow::anyhow!("Missing jump host address"))?;
-        let target_host_user = target_host_user.ok_or_else(|| anyhow::anyhow!("Missing target host username"))?;
-        let target_host = target_host_address.ok_or_else(|| anyhow::anyhow!("Missing target host address"))?;
-        let jump_port = jump_port.unwrap_or(22);
-        let target_port = target_port.unwrap_or(22);
-
-        let (enc1, enc2) = if Confirm::new("Save password?").with_default(false).prompt()? {
-            get_encrypted_passwords()?
-        } else {
-            (None, None)
-        };
+fn parse_pre_proxy(value: &str) -> Result<String> {
+    value.parse::<PreProxy>()?;
+    Ok(value.trim().to_string())
+}
 
-        Ok(SshConfig {
-            jump_host_user,
-            jump_host,
-            target_host_user,
-            target_host,
-            jump_port,
-            target_port,
-            port_forward,
-            enc1,
-            enc2,
-        })
+fn parse_profile_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    validate_profile_name(name)?;
+    Ok(name.to_string())
+}
+
+// Returns the name the profile was saved under, or None with --no-save.
+fn save_new_profile(save: SaveArgs, ssh_config: &SshConfig) -> Result<Option<String>> {
+    if save.no_save {
+        return Ok(None);
+    }
+    let profile_name = match save.profile_name {
+        Some(name) => name,
+        None => prompt_input("Enter profile name:")?,
+    };
+    save_or_overwrite_profile(&profile_name, ssh_config)?;
+    Ok(Some(profile_name))
+}
+
+const VIA_JUMP_HOST: &str = "Through a jump host";
+const VIA_PROXY_COMMAND: &str = "Through a proxy command (e.g. cloudflared access ssh)";
+
+fn from_interactive_input(save: &SaveArgs) -> Result<SshConfig> {
+    let connection = Select::new("How is the target reached?", vec![VIA_JUMP_HOST, VIA_PROXY_COMMAND])
+        .prompt()
+        .context("Failed to get connection method")?;
+    let (jump_host_user, jump_host, proxy_command) = if connection == VIA_PROXY_COMMAND {
+        (String::new(), (String::new(), None), Some(prompt_input("Enter proxy command:")?))
+    } else {
+        (
+            prompt_input("Enter jump host username:")?,
+            prompt_address("Enter jump host address:")?,
+            None,
+        )
+    };
+    let target_host_user = prompt_input("Enter target host username:")?;
+    let (target_host, target_address_port) = prompt_address("Enter target host address:")?;
+    // An address typed as `host:port` already answers the port question.
+    let (jump_host, jump_port) = match (proxy_command.as_ref(), jump_host) {
+        (Some(_), (host, _)) => (host, 22),
+        (None, (host, Some(port))) => (host, port),
+        (None, (host, None)) => (host, prompt_port("Enter jump host SSH port (default: 22):", 22)?),
+    };
+    let target_port = match target_address_port {
+        Some(port) => port,
+        None => prompt_port("Enter target host SSH port (default: 22):", 22)?,
+    };
+    let port_forward = CustomType::<u16>::new("Port-Forward? (default: no)")
+        .with_error_message("Please enter a valid port number between 1 and 65535")
+        .with_validator(validate_port_input)
+        .prompt_skippable()
+        .context("Failed to confirm port-forward")?;
+    let (bind_address, forward_remote_host, forward_remote_port) = match port_forward {
+        Some(local_port) => (
+            Some(prompt_input_with_default("Bind address for the forward:", "127.0.0.1")?),
+            Some(prompt_input_with_default("Remote host to forward to:", &target_host)?),
+            Some(prompt_port("Remote port to forward to:", local_port)?),
+        ),
+        None => (None, None, None),
+    };
+    let mut forwards = Vec::new();
+    if let Some(local_port) = port_forward {
+        let mut used_ports = vec![local_port];
+        while Confirm::new("Add another forward?").with_default(false).prompt()? {
+            let forward = prompt_forward(&target_host, &used_ports)?;
+            used_ports.push(forward.local_port);
+            forwards.push(forward);
+        }
+    }
+    let tags = parse_tags(
+        &Text::new("Tags (comma-separated, optional):")
+            .prompt()
+            .context("Failed to get tags")?,
+    );
+    let description = Text::new("Description (optional):")
+        .with_help_message("A note shown by `show`, e.g. who to ask before using this profile")
+        .prompt()
+        .context("Failed to get description")?;
+    let agent_forwarding = Confirm::new("Forward your SSH agent to the target?")
+        .with_default(false)
+        .with_help_message("Only needed to reach further hosts from the target with your local keys")
+        .prompt()?;
+    let identity_agent = Text::new("Identity agent socket (optional):")
+        .with_help_message("Only needed to use an agent other than $SSH_AUTH_SOCK, e.g. 1Password or gpg-agent")
+        .prompt()
+        .context("Failed to get identity agent")?;
+
+    let save_passwords = save.wants_saved_passwords()?;
+
+    let mut config = SshConfig {
+        jump_host_user,
+        jump_host,
+        target_host_user,
+        target_host,
+        jump_port,
+        target_port,
+        proxy_command,
+        port_forward,
+        bind_address,
+        forward_remote_host,
+        forward_remote_port,
+        forwards,
+        tags,
+        description: normalize_required(&description),
+        agent_forwarding,
+        options: SshOptions {
+            identity_agent: normalize_required(&identity_agent),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    save_secrets(&mut config, save_passwords, false, save.insecure_plaintext)?;
+    Ok(config)
+}
+
+// Checked like a config on stdin, so a profile that can't connect never reaches the disk.
+fn parse_imported_profile(input: &str, format: Format) -> Result<SshConfig> {
+    let config = formats::deserialize(input, format)?;
+    let problems = config.validate();
+    if !problems.is_empty() {
+        anyhow::bail!("Invalid profile: {}", join_problems(&problems, "; "));
+    }
+    Ok(config)
+}
+
+// With --clone-from, every field not given on the command line is taken from that profile,
+// except its saved passwords, which are asked for again.
+fn config_from_stdin(args: TunnelArgs) -> Result<SshConfig> {
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input).context("Failed to read the config from stdin")?;
+    let format = args.format.unwrap_or_else(|| Format::detect(&input));
+    let mut config = formats::deserialize(&input, format).context("Invalid config on stdin")?;
+    migrate::migrate(&mut config).context("Invalid config on stdin")?;
+    let problems = config.validate();
+    if !problems.is_empty() {
+        anyhow::bail!("Invalid config on stdin: {}", join_problems(&problems, "; "));
+    }
+
+    config.forwards.extend(args.local_forwards);
+    config.agent_forwarding |= args.agent_forward;
+    config.multiplex |= args.multiplex;
+    config.control_persist = args.control_persist.or(config.control_persist);
+    let mut options = args.options;
+    if args.no_exit_on_forward_failure {
+        options.exit_on_forward_failure = Some(false);
+    }
+    options.address_family = AddressFamily::from_flags(args.ipv4, args.ipv6);
+    apply_global_defaults(&mut config, options)?;
+    if let Some(port) = duplicate_local_ports(&config.local_forwards()).first() {
+        anyhow::bail!("Local port {} is used by more than one forward", port);
+    }
+    Ok(config)
+}
+
+fn from_non_interactive_input(args: TunnelArgs) -> Result<SshConfig> {
+    let base = match &args.clone_from {
+        Some(profile) => load_profile(profile)
+            .context(format!("Failed to load profile '{}'", profile))?
+            .without_secrets(),
+        None => SshConfig {
+            jump_port: 22,
+            target_port: 22,
+            ..Default::default()
+        },
+    };
+    let (jump_host_address, jump_port) = address_with_port(args.jump_host_address, args.jump_port, "--jump-port")?;
+    let (target_host_address, target_port) =
+        address_with_port(args.target_host_address, args.target_port, "--target-port")?;
+    let jump_host_given = args.jump_host_user.is_some() || jump_host_address.is_some();
+    let proxy_command = match args.proxy_command.as_deref() {
+        Some(command) => normalize_required(command),
+        None if jump_host_given => None,
+        None => base.proxy_command,
+    };
+    let (jump_host_user, jump_host) = match proxy_command {
+        Some(_) => (String::new(), String::new()),
+        None => (
+            required_arg(args.jump_host_user.or(Some(base.jump_host_user)), "jump_host_user")
+                .map_err(|e| anyhow::anyhow!("{} (or pass --proxy-command)", e))?,
+            required_arg(jump_host_address.or(Some(base.jump_host)), "jump_host")
+                .map_err(|e| anyhow::anyhow!("{} (or pass --proxy-command)", e))?,
+        ),
+    };
+    let pre_proxy = match proxy_command {
+        Some(_) => None,
+        None => args.pre_proxy.or(base.pre_proxy),
+    };
+    let target_host_user = required_arg(args.target_host_user.or(Some(base.target_host_user)), "target_host_user")?;
+    let target_host = required_arg(target_host_address.or(Some(base.target_host)), "target_host")?;
+    let jump_port = checked_port(jump_port, "--jump-port")?.unwrap_or(base.jump_port);
+    let target_port = checked_port(target_port, "--target-port")?.unwrap_or(base.target_port);
+    let port_forward = checked_port(args.port_forward, "--port-forward")?.or(base.port_forward);
+    let bind_address = args.bind_address.as_deref().and_then(normalize_required).or(base.bind_address);
+    let forward_remote_host = args
+        .forward_remote_host
+        .as_deref()
+        .and_then(normalize_required)
+        .or(base.forward_remote_host);
+    let forward_remote_port =
+        checked_port(args.forward_remote_port, "--forward-remote-port")?.or(base.forward_remote_port);
+    let forwards = if args.local_forwards.is_empty() { base.forwards } else { args.local_forwards };
+    let tags = if args.tags.is_empty() { base.tags } else { parse_tags(&args.tags.join(",")) };
+    let mut options = args.options.or(base.options);
+    if args.no_exit_on_forward_failure {
+        options.exit_on_forward_failure = Some(false);
     }
+    options.address_family = AddressFamily::from_flags(args.ipv4, args.ipv6).or(options.address_family);
+    let jump_password_file = match proxy_command {
+        Some(_) => None,
+        None => args
+            .jump_password_file
+            .as_deref()
+            .and_then(normalize_required)
+            .or(base.jump_password_file),
+    };
+    let target_password_file = args
+        .target_password_file
+        .as_deref()
+        .and_then(normalize_required)
+        .or(base.target_password_file);
+
+    let mut config = SshConfig {
+        jump_host_user,
+        jump_host,
+        target_host_user,
+        target_host,
+        jump_port,
+        target_port,
+        proxy_command,
+        pre_proxy,
+        port_forward,
+        bind_address,
+        forward_remote_host,
+        forward_remote_port,
+        forwards,
+        tags,
+        description: args.description.as_deref().and_then(normalize_required).or(base.description),
+        agent_forwarding: args.agent_forward || base.agent_forwarding,
+        multiplex: args.multiplex || base.multiplex,
+        control_persist: args.control_persist.or(base.control_persist),
+        local_command: args.local_command.or(base.local_command),
+        on_connect: args.on_connect.or(base.on_connect),
+        on_disconnect: args.on_disconnect.or(base.on_disconnect),
+        options,
+        jump_password_file,
+        target_password_file,
+        ..base
+    };
+    if args.public_forward {
+        config.bind_forwards_publicly();
+    }
+    // Catch clashing forwards before asking for passwords.
+    let problems = config.validate();
+    if !problems.is_empty() {
+        anyhow::bail!("Invalid tunnel: {}", join_problems(&problems, "; "));
+    }
+
+    if args.key_passphrase && config.options.identity_file.is_none() {
+        anyhow::bail!("--key-passphrase needs --identity-file, or a --clone-from profile with one");
+    }
+    // --print-config only shows the profile, so there is nothing to save the passwords to.
+    if !args.print_config {
+        let save_passwords = args.save.wants_saved_passwords()?;
+        save_secrets(&mut config, save_passwords, args.key_passphrase, args.save.insecure_plaintext)?;
+    }
+    Ok(config)
 }
 
 fn prompt_input(message: &str) -> Result<String> {
-    Text::new(message)
+    let input = Text::new(message)
+        .with_validator(validate_required_input)
         .prompt()
-        .context(format!("Failed to get {}", message))
+        .context(format!("Failed to get {}", message))?;
+    Ok(input.trim().to_string())
+}
+
+// Takes `host`, `host:port` or `[ipv6]:port`, returning the port only if one was typed.
+fn prompt_address(message: &str) -> Result<(String, Option<u16>)> {
+    let input = Text::new(message)
+        .with_help_message("Add :port for a port other than 22, e.g. bastion.example.com:2222")
+        .with_validator(|input: &str| {
+            Ok(match split_host_port(input) {
+                _ if normalize_required(input).is_none() => Validation::Invalid("This field is required".into()),
+                Ok(_) => Validation::Valid,
+                Err(e) => Validation::Invalid(e.to_string().into()),
+            })
+        })
+        .prompt()
+        .context(format!("Failed to get {}", message))?;
+    split_host_port(&input)
+}
+
+fn prompt_input_with_default(message: &str, default: &str) -> Result<String> {
+    let input = Text::new(message)
+        .with_default(default)
+        .with_validator(validate_required_input)
+        .prompt()
+        .context(format!("Failed to get {}", message))?;
+    Ok(input.trim().to_string())
 }
 
-fn prompt_port(message: &str, default: i16) -> Result<i16> {
-    CustomType::<i16>::new(message)
+fn prompt_port(message: &str, default: u16) -> Result<u16> {
+    CustomType::<u16>::new(message)
         .with_default(default)
         .with_error_message("Please enter a valid port number between 1 and 65535")
+        .with_validator(validate_port_input)
         .prompt()
         .context(format!("Failed to get {}", message))
 }
 
-fn get_encrypted_passwords() -> Result<(Option<String>, Option<String>)> {
-    let pj = Password::new("Enter password for jump host:").prompt()?;
-    let pt = Password::new("Enter password for target host:").prompt()?;
-    let mp = Password::new("Enter master password:").prompt()?;
+fn prompt_forward(target_host: &str, used_ports: &[u16]) -> Result<LocalForward> {
+    let used = used_ports.to_vec();
+    let local_port = CustomType::<u16>::new("Local port to forward:")
+        .with_error_message("Please enter a valid port number between 1 and 65535")
+        .with_validator(move |port: &u16| {
+            Ok(match local_port_problem(*port, &used) {
+                Some(problem) => Validation::Invalid(problem.into()),
+                None => Validation::Valid,
+            })
+        })
+        .prompt()
+        .context("Failed to get local port to forward")?;
+    let bind_address = prompt_input_with_default("Bind address for the forward:", "127.0.0.1")?;
+    let remote_host = prompt_input_with_default("Remote host to forward to:", target_host)?;
+    let remote_port = prompt_port("Remote port to forward to:", local_port)?;
 
-    if is_password_strong(&mp) {
-        let enc1 = Some(encrypt_password(&mp, &pj).expect("Failed to encrypt jump host password"));
-        let enc2 = Some(encrypt_password(&mp, &pt).expect("Failed to encrypt target host password"));
-        Ok((enc1, enc2))
+    Ok(LocalForward {
+        bind_address: Some(bind_address),
+        local_port,
+        remote_host: Some(remote_host),
+        remote_port,
+        scheme: None,
+    })
+}
+
+fn validate_required_input(input: &str) -> Result<Validation, inquire::CustomUserError> {
+    if normalize_required(input).is_some() {
+        Ok(Validation::Valid)
     } else {
-        println!("Master password is not strong enough.");
-        Ok((None, None))
+        Ok(Validation::Invalid("This field is required".into()))
     }
 }
 
-fn get_profiles_dir() -> Result<String> {
-    let home_dir = dirs::home_dir().context("Failed to get home directory")?;
-    let profiles_dir = home_dir.join(".rush-tunnel");
-    Ok(profiles_dir.to_string_lossy().to_string())
+fn validate_port_input(port: &u16) -> Result<Validation, inquire::CustomUserError> {
+    if is_valid_port(*port) {
+        Ok(Validation::Valid)
+    } else {
+        Ok(Validation::Invalid("Please enter a valid port number between 1 and 65535".into()))
+    }
 }
 
-fn load_profile(profile_name: &str) -> Result<SshConfig> {
-    let profiles_dir = get_profiles_dir()?;
-    let profile_path = Path::new(&profiles_dir).join(format!("{}.toml", profile_name));
-    let toml_str = fs::read_to_string(profile_path).context("Failed to read profile file")?;
-    toml::from_str(&toml_str).context("Failed to deserialize profile")
+fn parse_tags(input: &str) -> Vec<String> {
+    input
+        .split(',')
+        .filter_map(normalize_required)
+        .collect()
 }
 
-fn save_profile(profile_name: &str, ssh_config: &SshConfig) -> Result<()> {
-    let profiles_dir = get_profiles_dir()?;
-    fs::create_dir_all(&profiles_dir).context("Failed to create profiles directory")?;
-    let profile_path = Path::new(&profiles_dir).join(format!("{}.toml", profile_name));
-    let toml_str = toml::to_string(&ssh_config).context("Failed to serialize profile")?;
-    fs::write(profile_path, toml_str).context("Failed to write profile file")
+fn required_arg(value: Option<String>, field: &'static str) -> Result<String, ConfigError> {
+    value.as_deref().and_then(normalize_required).ok_or(ConfigError::MissingField(field))
 }
 
-fn save_or_overwrite_profile(profile_name: &str, ssh_config: &SshConfig) -> Result<()> {
-    let profiles_dir = get_profiles_dir()?;
-    let profile_path = Path::new(&profiles_dir).join(format!("{}.toml", profile_name));
-
-    if profile_path.exists()
-        && !Confirm::new(&format!("Profile '{}' already exists. Overwrite?", profile_name))
-        .prompt()?
-    {
-        return Ok(());
+// `--jump-host-address bastion:2222` is the same as also passing `--jump-port 2222`.
+fn address_with_port(
+    address: Option<String>,
+    port: Option<u16>,
+    port_flag: &str,
+) -> Result<(Option<String>, Option<u16>)> {
+    let Some(address) = address else {
+        return Ok((None, port));
+    };
+    let (host, address_port) = split_host_port(&address)?;
+    match (address_port, port) {
+        (Some(address_port), Some(port)) if address_port != port => {
+            anyhow::bail!("'{}' has port {}, but {} is {}", address, address_port, port_flag, port)
+        }
+        _ => Ok((Some(host), address_port.or(port))),
     }
-
-    save_profile(profile_name, ssh_config)
 }
 
-fn list_profiles() -> Result<Option<Vec<String>>> {
-    let profiles_dir = get_profiles_dir()?;
-    let profiles_path = Path::new(&profiles_dir);
-
-    if !profiles_path.exists() {
-        return Ok(None);
+fn checked_port(port: Option<u16>, flag: &str) -> Result<Option<u16>, ConfigError> {
+    match port {
+        Some(port) if !is_valid_port(port) => Err(ConfigError::InvalidPort {
+            field: flag.to_string(),
+            port,
+        }),
+        port => Ok(port),
     }
-
-    let profiles = fs::read_dir(profiles_path)?
-        .filter_map(|entry| {
-            entry.ok().and_then(|e| {
-                e.path()
-                    .extension()
-                    .and_then(|ext| if ext == "toml" { Some(e) } else { None })
-                    .and_then(|e| e.path().file_stem().map(|s| s.to_string_lossy().to_string()))
-            })
-        })
-        .collect::<Vec<_>>();
-
-    Ok(Some(profiles))
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::from_args();
+// Everything a new profile saves is encrypted with one master password, asked for once. Profiles
+// that use a proxy command have no jump host, so there is no jump password to ask for.
+fn save_secrets(config: &mut SshConfig, passwords: bool, key_passphrase: bool, insecure_plaintext: bool) -> Result<()> {
+    if insecure_plaintext {
+        plaintext::require_allowed("--insecure-plaintext")?;
+    }
+    let passwords = if passwords {
+        let pj = if config.proxy_command.is_none() {
+            Password::new("Enter password for jump host:").prompt()?
+        } else {
+            String::new()
+        };
+        Some((pj, Password::new("Enter password for target host:").prompt()?))
+    } else {
+        None
+    };
+    let key_passphrase = match config.options.identity_file.as_deref().filter(|_| key_passphrase) {
+        Some(key_file) => Some(Zeroizing::new(
+            Password::new(&format!("Enter passphrase for {}:", key_file)).prompt()?,
+        )),
+        None => None,
+    };
+    if passwords.is_none() && key_passphrase.is_none() {
+        return Ok(());
+    }
+    if let (true, Some((pj, pt))) = (insecure_plaintext, &passwords) {
+        config.enc1 = Some(plaintext::encode(pj));
+        config.enc2 = Some(plaintext::encode(pt));
+        config.verifier = None;
+        return Ok(());
+    }
+    let cipher = GlobalConfig::load()?.cipher()?;
 
-    match cli {
-        Cli::Interactive => {
-            let config = SshConfig::from_interactive_input()?;
-            let profile_name = prompt_input("Enter profile name:")?;
-            save_or_overwrite_profile(&profile_name, &config)?;
-            establish_tunnel(&config)?;
-            println!("SSH tunnel closed gracefully!");
-        }
-        Cli::Tunnel {
-            jump_host_user,
-            jump_host_address,
-            target_host_user,
-            target_host_address,
-            jump_port,
-            target_port,
-            port_forward,
-        } => {
-            let config = SshConfig::from_non_interactive_input(
-                jump_host_user,
-                jump_host_address,
-                target_host_user,
-                target_host_address,
-                jump_port,
-                target_port,
-                port_forward,
-            )?;
-            let profile_name = prompt_input("Enter profile name:")?;
-            save_or_overwrite_profile(&profile_name, &config)?;
-            establish_tunnel(&config)?;
-            println!("SSH tunnel closed gracefully!");
-        }
-        Cli::Connect { profile } => {
-            if profile.is_none() {
-                let profiles = list_profiles().expect("Failed to list profiles");
-                let selected = Select::<String>::new("Select profile:", profiles.unwrap()).prompt()?;
-                let ssh_config = load_profile(&selected)?;
-                establish_tunnel(&ssh_config)?;
-            } else {
-                let ssh_config =
-                    load_profile(&profile.clone().unwrap()).context(format!("Failed to load profile '{:?}'", &profile))?;
-                establish_tunnel(&ssh_config)?;
+    loop {
+        let mp = prompt_confirmed(|message| {
+            Password::new(message)
+                .without_confirmation()
+                .prompt()
+                .context("Failed to get master password")
+        })?;
+        if !is_password_strong(&mp) {
+            // Unlike the passwords, the profile isn't saved without the passphrase it was asked to keep.
+            if key_passphrase.is_some() {
+                anyhow::bail!("Master password is not strong enough to save a key passphrase with");
             }
-            println!("SSH tunnel closed gracefully!");
+            println!("Master password is not strong enough.");
+            return Ok(());
         }
-        Cli::Profiles => {
-            let profiles = list_profiles()?;
-            if let Some(profiles) = profiles {
-                for profile in profiles {
-                    println!("- {}", profile);
+
+        let encrypted = passwords
+            .as_ref()
+            .map(|(pj, pt)| encrypt_passwords(&mp, pj, pt, cipher))
+            .transpose()
+            .and_then(|passwords| {
+                let passphrase = key_passphrase.as_ref().map(|p| encrypt_key_passphrase(&mp, p, cipher)).transpose()?;
+                Ok((passwords, passphrase))
+            });
+        match encrypted {
+            Ok((passwords, passphrase)) => {
+                config.set_encrypted_passwords(passwords);
+                config.enc_key_passphrase = passphrase;
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("{:#}", e);
+                if !Confirm::new("Try again with another master password?")
+                    .with_default(true)
+                    .prompt()?
+                {
+                    return Err(e);
                 }
-            } else {
-                println!("No profiles found");
             }
         }
-        Cli::Path => {
-            let profiles_dir = get_profiles_dir()?;
-            println!("Profiles directory: {}", profiles_dir);
+    }
+}
+
+// A typo in the master password cannot be recovered from later, so it is entered twice.
+fn prompt_confirmed(mut prompt: impl FnMut(&str) -> Result<String>) -> Result<String> {
+    loop {
+        let first = prompt("Enter master password:")?;
+        let second = prompt("Confirm master password:")?;
+        if first == second {
+            return Ok(first);
         }
+        println!("Master passwords do not match, please try again.");
     }
+}
 
-    Ok(())
+fn saved_credentials_preview(config: &SshConfig, jump_only: bool) -> String {
+    if config.proxy_command.is_some() {
+        return format!(
+            "Connecting to {}@{}:{} via its proxy command using saved credentials",
+            config.target_host_user,
+            bracket_host(&config.target_host),
+            config.target_port
+        );
+    }
+    if jump_only {
+        return format!(
+            "Connecting to {}@{}:{} using saved credentials",
+            config.jump_host_user,
+            bracket_host(&config.jump_host),
+            config.jump_port
+        );
+    }
+    format!(
+        "Connecting to {}@{}:{} via {}@{}:{} using saved credentials",
+        config.target_host_user,
+        bracket_host(&config.target_host),
+        config.target_port,
+        config.jump_host_user,
+        bracket_host(&config.jump_host),
+        config.jump_port
+    )
 }
 
-fn establish_tunnel(config: &SshConfig) -> Result<()> {
-    println!("SSH Configuration:");
-    println!(
-        "  Jump Host:        {}@{}:{}",
-        config.jump_host_user, config.jump_host, config.jump_port
-    );
-    println!(
-        "  Target Host:      {}@{}:{}",
-        config.target_host_user, config.target_host, config.target_port
-    );
+// Password files take precedence over saved passwords, host by host. A host covered by neither is
+// asked for here, because once ssh answers prompts through askpass it no longer asks on the terminal.
+fn resolve_credentials(config: &SshConfig, batch: bool) -> Result<Option<Credentials>> {
+    let password_files = config.jump_password_file.is_some() || config.target_password_file.is_some();
+    if batch && (config.has_saved_secrets() || password_files) {
+        anyhow::bail!(
+            "--batch stops ssh from asking for passwords and key passphrases, so the ones this profile provides \
+             can't be used; connect without --batch, or set up key authentication with copy-id"
+        );
+    }
+    let jump_file = config.jump_password_file.as_deref().map(askpass::read_password_file).transpose()?;
+    let target_file = config.target_password_file.as_deref().map(askpass::read_password_file).transpose()?;
+    if jump_file.is_none() && target_file.is_none() {
+        return unlock_saved_passwords(config);
+    }
 
-    let jump_ssh_args = format!(
-        "-J {}@{}:{}",
-        config.jump_host_user, config.jump_host, config.jump_port
-    );
+    let mut saved = match (&jump_file, &target_file) {
+        (Some(_), Some(_)) if config.enc_key_passphrase.is_none() => None,
+        _ => unlock_saved_passwords(config)?,
+    };
+    let key_passphrase = saved.as_mut().and_then(|saved| saved.key_passphrase.take());
+    // A profile with just a key passphrase has no saved passwords to fall back on.
+    let saved = saved.filter(|_| config.has_saved_passwords());
+    let jump_password = match (jump_file, &saved) {
+        (Some(password), _) => password,
+        (None, Some(saved)) => saved.jump_password.clone(),
+        (None, None) if config.proxy_command.is_some() => String::new(),
+        (None, None) => prompt_host_password(&config.jump_host_user, &config.jump_host)?,
+    };
+    let target_password = match (target_file, &saved) {
+        (Some(password), _) => password,
+        (None, Some(saved)) => saved.target_password.clone(),
+        (None, None) => prompt_host_password(&config.target_host_user, &config.target_host)?,
+    };
+    Ok(Some(Credentials {
+        jump_password,
+        target_password,
+        key_passphrase,
+    }))
+}
 
-    let mut command = Command::new("ssh");
-    command
-        .arg(jump_ssh_args)
-        .arg(format!("{}@{}", config.target_host_user, config.target_host))
-        .arg("-p")
-        .arg(&config.target_port.to_string());
-    if let Some(local_port) = &config.port_forward {
-        command
-            .arg("-L")
-            .arg(format!(
-                "{}:{}:{}",
-                local_port, config.target_host, config.target_port
-            ));
-    }
-
-    command
-        .status()
-        .context("Failed to establish SSH tunnel with port forwarding")?;
+fn prompt_host_password(user: &str, host: &str) -> Result<String> {
+    Password::new(&format!("{}@{}'s password:", user, bracket_host(host)))
+        .without_confirmation()
+        .prompt()
+        .context("Failed to get password")
+}
 
-    Ok(())
-}
\ No newline at end of file
+fn unlock_saved_passwords(config: &SshConfig) -> Result<Option<Credentials>> {
+    if !config.has_saved_secrets() {
+        return Ok(None);
+    }
+    // There is nothing to unlock, but nothing is used without the same override that saved them.
+    if plaintext::has_plaintext_passwords(config) {
+        plaintext::require_allowed("This profile")?;
+        return decrypt_saved_passwords(config, "").context("Failed to read the saved passwords");
+    }
+
+    loop {
+        let mp = Password::new("Enter master password:")
+            .without_confirmation()
+            .prompt()
+            .context("Failed to get master password")?;
+
+        match decrypt_saved_passwords(config, &mp) {
+            Ok(credentials) => return Ok(credentials),
+            Err(UnlockError::WrongMasterPassword) => println!("Wrong master password, please try again."),
+            Err(e) => return Err(anyhow::Error::new(e).context("Failed to decrypt the saved passwords")),
+        }
+    }
+}
+
+// Returns whether the profile was saved, which it isn't when the user declines to replace one.
+fn save_or_overwrite_profile(profile_name: &str, ssh_config: &SshConfig) -> Result<bool> {
+    let profiles_dir = get_profiles_dir()?;
+    let profile_path = Path::new(&profiles_dir).join(format!("{}.toml", profile_name));
+    let profiles = list_profiles()?.unwrap_or_default();
+
+    let variants = case_variants(profile_name, &profiles);
+    if !variants.is_empty() {
+        eprintln!(
+            "{}",
+            style::warning(&format!(
+                "Warning: '{}' differs from existing profile '{}' only by case; on case-insensitive filesystems they are the same file",
+                profile_name,
+                variants.join("', '")
+            ))
+        );
+        if !Confirm::new(&format!("Save as '{}' anyway?", profile_name))
+            .with_default(false)
+            .prompt()?
+        {
+            return Ok(false);
+        }
+    } else if profile_path.exists()
+        && !Confirm::new(&format!("Profile '{}' already exists. Overwrite?", profile_name))
+        .prompt()?
+    {
+        return Ok(false);
+    }
+
+    save_profile(profile_name, ssh_config)?;
+    Ok(true)
+}
+
+fn resolve_profile_name(profile: Option<String>, last: bool) -> Result<String> {
+    if let Some(profile) = profile {
+        return Ok(profile);
+    }
+    if last {
+        match state::read_state(state::LAST_PROFILE)? {
+            Some(last_profile) => return Ok(last_profile),
+            None => println!("No last-used profile recorded; please select one."),
+        }
+    }
+
+    let default_profile = state::read_state(state::DEFAULT_PROFILE)?;
+    match default_profile {
+        Some(default_profile) if !std::io::stdin().is_terminal() => Ok(default_profile),
+        Some(default_profile) => select_profile(Some(&default_profile)),
+        None => select_profile(state::read_state(state::LAST_PROFILE)?.as_deref()),
+    }
+}
+
+// Options, forwards and users given on the command line only apply to this run and are never saved.
+#[derive(Clone, Default)]
+struct ConnectOverrides {
+    options: SshOptions,
+    forwards: Vec<LocalForward>,
+    // Local ports of the stored forwards to open; empty means all of them.
+    only_forwards: Vec<u16>,
+    select_forwards: bool,
+    public_forward: bool,
+    jump_user: Option<String>,
+    target_user: Option<String>,
+}
+
+async fn connect_profile(
+    profile_name: &str,
+    overrides: ConnectOverrides,
+    replace: bool,
+    tunnel_options: &TunnelOptions,
+    report: Option<ReportFormat>,
+) -> Result<ExitCode> {
+    if tunnel_options.jump_only && !overrides.forwards.is_empty() {
+        anyhow::bail!("--local-forward can't be used with --jump-only, since forwards go through the target");
+    }
+    let ssh_config = load_connect_config(profile_name, overrides)?;
+    if tunnel_options.jump_only && ssh_config.proxy_command.is_some() {
+        anyhow::bail!("'{}' connects through a proxy command, so it has no jump host for --jump-only", profile_name);
+    }
+    let forwards = ssh_config.local_forwards().len();
+    if tunnel_options.print_urls && (forwards == 0 || tunnel_options.jump_only) {
+        anyhow::bail!("--print-url needs a forward, and '{}' opens none for this connection", profile_name);
+    }
+    if tunnel_options.forward_only && forwards == 0 {
+        anyhow::bail!("--local-forward-only needs a forward, and '{}' has none", profile_name);
+    }
+    if tunnel_options.jump_only && forwards > 0 && !tunnel_options.assume_yes {
+        let question = format!(
+            "'{}' has {} through the target, which won't be opened on the jump host. Continue?",
+            profile_name,
+            pluralize(forwards, "forward")
+        );
+        if !Confirm::new(&question).with_default(false).prompt()? {
+            println!("Connection cancelled");
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    if !confirm_local_command(profile_name, &ssh_config, tunnel_options)? {
+        println!("Connection cancelled");
+        return Ok(ExitCode::FAILURE);
+    }
+    let Some(mut tunnel_options) = confirm_and_unlock(&ssh_config, tunnel_options)? else {
+        println!("Connection cancelled");
+        return Ok(ExitCode::FAILURE);
+    };
+    check_running_tunnel(profile_name, replace, tunnel_options.quiet)?;
+    let started = SystemTime::now();
+    let result = establish_tunnel(&ssh_config, &mut tunnel_options).await;
+    let report_line = match report {
+        Some(format) => Some(session_report(profile_name, started, &result).render(format)?),
+        None => None,
+    };
+    let exit_code = match result {
+        Ok(outcome) => finish_tunnel(profile_name, &outcome, tunnel_options.quiet),
+        Err(err) => match err.downcast_ref::<SessionTimedOut>() {
+            Some(timed_out) => {
+                eprintln!("{}", style::warning(&timed_out.to_string()));
+                Ok(ExitCode::from(SESSION_TIMEOUT_EXIT_CODE))
+            }
+            None => Err(err),
+        },
+    };
+    // Printed last, and whatever happened to ssh, so callers can always take the final line.
+    if let Some(report_line) = report_line {
+        eprintln!("{}", report_line);
+    }
+    exit_code
+}
+
+fn session_report(profile_name: &str, started: SystemTime, result: &Result<TunnelOutcome>) -> SessionReport {
+    let mut report = SessionReport::new(profile_name, started, SystemTime::now());
+    match result {
+        Ok(outcome) => report.exit_code = outcome.status.code(),
+        Err(err) if err.is::<SessionTimedOut>() => report.timed_out = true,
+        Err(err) => report.error = Some(format!("{:#}", err)),
+    }
+    report
+}
+
+// Each tunnel runs forward-only in the background, and a failure in one never stops the others.
+// SIGINT or SIGTERM is passed on to every tunnel, and rush-tunnel waits for them all to close.
+async fn connect_profiles(
+    profile_names: &[String],
+    overrides: ConnectOverrides,
+    replace: bool,
+    tunnel_options: &TunnelOptions,
+) -> Result<ExitCode> {
+    let profiles_dir = PathBuf::from(get_profiles_dir()?);
+    // Caught from the start, so a signal while the tunnels start up still closes them and removes
+    // their pid files.
+    let mut signals = TerminationSignals::install()?;
+    let mut started = Vec::new();
+    let mut failed = Vec::new();
+    let mut used_ports = Vec::new();
+    let profile_names = dedup_profile_names(profile_names);
+    for &profile_name in &profile_names {
+        if !tunnel_options.quiet {
+            println!("Starting '{}'", profile_name);
+        }
+        match start_background_tunnel(profile_name, overrides.clone(), &used_ports, replace, tunnel_options) {
+            Ok(Some((tunnel, ports))) => {
+                used_ports.extend(ports);
+                if let Err(e) = pidfile::write_pid(&profiles_dir, profile_name, tunnel.child.id()) {
+                    eprintln!("{}", style::warning(&format!("Warning: {:#}", e)));
+                }
+                started.push((profile_name, tunnel));
+            }
+            Ok(None) => failed.push((profile_name, "cancelled".to_string())),
+            Err(e) => failed.push((profile_name, format!("{:#}", e))),
+        }
+    }
+
+    // ssh gives up within moments when it cannot connect, authenticate or bind a port.
+    if !started.is_empty() {
+        tokio::select! {
+            _ = tokio::time::sleep(TUNNEL_STARTUP_GRACE) => {}
+            signal = signals.recv() => {
+                for (_, tunnel) in &started {
+                    pidfile::pass_on(tunnel.child.id(), signal);
+                }
+                wait_for_tunnels(&profiles_dir, started, &mut signals, Some(signal)).await?;
+                return Ok(tunnels_closed(signal, tunnel_options.quiet));
+            }
+        }
+    }
+    let mut running = Vec::new();
+    for (profile_name, mut tunnel) in started {
+        match tunnel.child.try_wait() {
+            Ok(None) => running.push((profile_name, tunnel)),
+            Ok(Some(status)) => {
+                pidfile::remove_pid(&profiles_dir, profile_name, tunnel.child.id())?;
+                tunnel.finish();
+                failed.push((profile_name, format!("ssh {}", status)));
+            }
+            Err(e) => failed.push((profile_name, e.to_string())),
+        }
+    }
+
+    println!("{} of {} running", pluralize(running.len(), "tunnel"), profile_names.len());
+    for (profile_name, tunnel) in &running {
+        println!("  ok    {} (pid {})", profile_name, tunnel.child.id());
+    }
+    // Still running after the grace period counts as connected; the last one becomes the last profile.
+    // Failing to record it is no reason to leave the tunnels without anyone waiting on them.
+    for (profile_name, _) in &running {
+        if let Err(e) = record_connected(profile_name) {
+            eprintln!("{}", style::warning(&format!("Warning: {:#}", e)));
+        }
+    }
+    for (profile_name, reason) in &failed {
+        println!("  FAIL  {}: {}", profile_name, reason);
+    }
+    if running.is_empty() {
+        return Ok(ExitCode::FAILURE);
+    }
+
+    println!("Press Ctrl-C to close all tunnels");
+    if let Some(signal) = wait_for_tunnels(&profiles_dir, running, &mut signals, None).await? {
+        return Ok(tunnels_closed(signal, tunnel_options.quiet));
+    }
+    Ok(if failed.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+// Waits for every tunnel to exit, removing each one's pid file and running its on_disconnect as it
+// does. A signal is passed on to all of them, and a second one kills whatever is left. Returns the
+// first signal, if any.
+async fn wait_for_tunnels(
+    profiles_dir: &Path,
+    mut tunnels: Vec<(&str, BackgroundTunnel)>,
+    signals: &mut TerminationSignals,
+    mut interrupted_by: Option<i32>,
+) -> Result<Option<i32>> {
+    loop {
+        let mut still_running = Vec::new();
+        for (profile_name, mut tunnel) in tunnels {
+            let status =
+                tunnel.child.try_wait().context(format!("Failed to wait for the tunnel to '{}'", profile_name))?;
+            let Some(status) = status else {
+                still_running.push((profile_name, tunnel));
+                continue;
+            };
+            pidfile::remove_pid(profiles_dir, profile_name, tunnel.child.id())?;
+            tunnel.finish();
+            // A signalled ssh exits with 255, which is no failure when that is how it was closed.
+            if !status.success() && interrupted_by.is_none() {
+                eprintln!("Tunnel '{}' failed: ssh {}", profile_name, status);
+            }
+        }
+        tunnels = still_running;
+        if tunnels.is_empty() {
+            return Ok(interrupted_by);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(TUNNEL_POLL_INTERVAL) => {}
+            signal = signals.recv() => {
+                if interrupted_by.is_some() {
+                    for (_, tunnel) in &mut tunnels {
+                        let _ = tunnel.child.kill();
+                    }
+                } else {
+                    interrupted_by = Some(signal);
+                    for (_, tunnel) in &tunnels {
+                        pidfile::pass_on(tunnel.child.id(), signal);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Exits the way the shell reports a command stopped by that signal, as a single tunnel does.
+fn tunnels_closed(signal: i32, quiet: bool) -> ExitCode {
+    if !quiet {
+        println!("SSH tunnels closed");
+    }
+    ExitCode::from(128 + signal as u8)
+}
+
+fn start_background_tunnel(
+    profile_name: &str,
+    overrides: ConnectOverrides,
+    used_ports: &[u16],
+    replace: bool,
+    tunnel_options: &TunnelOptions,
+) -> Result<Option<(BackgroundTunnel, Vec<u16>)>> {
+    let ssh_config = load_connect_config(profile_name, overrides)?;
+    let ports: Vec<u16> = ssh_config.local_forwards().iter().map(|forward| forward.local_port).collect();
+    if let Some(port) = ports.iter().find(|port| used_ports.contains(port)) {
+        anyhow::bail!("local port {} is already used by another tunnel", port);
+    }
+    if !confirm_local_command(profile_name, &ssh_config, tunnel_options)? {
+        return Ok(None);
+    }
+    let Some(tunnel_options) = confirm_and_unlock(&ssh_config, tunnel_options)? else {
+        return Ok(None);
+    };
+    let mut tunnel_options = TunnelOptions {
+        forward_only: true,
+        ..tunnel_options
+    };
+    check_running_tunnel(profile_name, replace, tunnel_options.quiet)?;
+    Ok(Some((spawn_tunnel(&ssh_config, &mut tunnel_options)?, ports)))
+}
+
+// One background tunnel per profile: --replace closes the one already running, which is otherwise
+// only warned about, since its forwards will usually keep the new tunnel from binding them.
+fn check_running_tunnel(profile_name: &str, replace: bool, quiet: bool) -> Result<()> {
+    let profiles_dir = PathBuf::from(get_profiles_dir()?);
+    let Some(tunnel) = pidfile::running_pid(&profiles_dir, profile_name)? else {
+        return Ok(());
+    };
+    if !replace {
+        eprintln!(
+            "{}",
+            style::warning(&format!(
+                "Warning: '{}' already has a tunnel running in the background (pid {}); pass --replace to close it",
+                profile_name, tunnel.pid
+            ))
+        );
+        return Ok(());
+    }
+    if !quiet {
+        println!("Closing the running tunnel for '{}' (pid {})", profile_name, tunnel.pid);
+    }
+    pidfile::stop(&tunnel).context(format!("Failed to close the running tunnel for '{}'", profile_name))?;
+    pidfile::remove_pid(&profiles_dir, profile_name, tunnel.pid)
+}
+
+fn print_merged_config(config: &SshConfig, format: Option<Format>) -> Result<ExitCode> {
+    print!("{}", formats::serialize(&config.with_masked_secrets(), format.unwrap_or(Format::Toml))?);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn load_connect_config(profile_name: &str, overrides: ConnectOverrides) -> Result<SshConfig> {
+    let mut ssh_config =
+        load_profile(profile_name).context(format!("Failed to load profile '{}'", profile_name))?;
+    apply_global_defaults(&mut ssh_config, overrides.options)?;
+    narrow_forwards(profile_name, &mut ssh_config, &overrides.only_forwards, overrides.select_forwards)?;
+    // Forwards added for this run are always opened.
+    ssh_config.forwards.extend(overrides.forwards);
+    if overrides.public_forward {
+        if ssh_config.local_forwards().is_empty() {
+            anyhow::bail!("'{}' has no forwards to share with --public-forward", profile_name);
+        }
+        ssh_config.bind_forwards_publicly();
+    }
+    // Stored passwords belong to the profile's own users, so they are not offered to anyone else.
+    if overrides.jump_user.is_some() || overrides.target_user.is_some() {
+        ssh_config.set_encrypted_passwords(None);
+    }
+    if let Some(user) = overrides.jump_user {
+        if ssh_config.proxy_command.is_some() {
+            anyhow::bail!("'{}' connects through a proxy command, so it has no jump host user to override", profile_name);
+        }
+        ssh_config.jump_host_user = normalize_required(&user).context("--jump-user is empty")?;
+        ssh_config.jump_password_file = None;
+    }
+    if let Some(user) = overrides.target_user {
+        ssh_config.target_host_user = normalize_required(&user).context("--target-user is empty")?;
+        ssh_config.target_password_file = None;
+    }
+    if let Some(port) = duplicate_local_ports(&ssh_config.local_forwards()).first() {
+        anyhow::bail!("Local port {} is used by more than one forward", port);
+    }
+    Ok(ssh_config)
+}
+
+// A profile can still have its primary forward in `port_forward`, which ssh opens ahead of
+// `forwards`, so it is folded in first to be picked from like any other.
+fn narrow_forwards(profile_name: &str, ssh_config: &mut SshConfig, only: &[u16], select: bool) -> Result<()> {
+    migrate::fold_legacy_forward(ssh_config);
+    if !only.is_empty() {
+        restrict_forwards(profile_name, &mut ssh_config.forwards, only)?;
+    } else if select && ssh_config.forwards.len() > 1 {
+        ssh_config.forwards = select_forwards(&ssh_config.forwards, &ssh_config.target_host)?;
+    }
+    Ok(())
+}
+
+fn restrict_forwards(profile_name: &str, forwards: &mut Vec<LocalForward>, local_ports: &[u16]) -> Result<()> {
+    if let Some(port) = local_ports.iter().find(|port| !forwards.iter().any(|forward| forward.local_port == **port)) {
+        anyhow::bail!("'{}' has no forward on local port {} for --only-forward", profile_name, port);
+    }
+    forwards.retain(|forward| local_ports.contains(&forward.local_port));
+    Ok(())
+}
+
+fn select_forwards(forwards: &[LocalForward], target_host: &str) -> Result<Vec<LocalForward>> {
+    let labels: Vec<String> = forwards
+        .iter()
+        .map(|forward| format!("{} -> {}", forward.listen_spec(), forward.destination(target_host)))
+        .collect();
+    let selected = MultiSelect::new("Forwards to open:", labels)
+        .with_all_selected_by_default()
+        .raw_prompt()
+        .context("Failed to select forwards")?;
+    Ok(selected.into_iter().map(|option| forwards[option.index].clone()).collect())
+}
+
+// A local_command or hook runs whatever it says on this machine, so each profile's commands are
+// confirmed the first time, and again whenever one changes.
+fn confirm_local_command(profile_name: &str, ssh_config: &SshConfig, tunnel_options: &TunnelOptions) -> Result<bool> {
+    if tunnel_options.jump_only {
+        return Ok(true);
+    }
+    let commands: Vec<(&str, &str, &str)> = [
+        ("", "once connected", &ssh_config.local_command),
+        ("on_connect: ", "once the tunnel is up", &ssh_config.on_connect),
+        ("on_disconnect: ", "after the tunnel closes", &ssh_config.on_disconnect),
+    ]
+    .into_iter()
+    .filter_map(|(label, when, command)| Some((label, when, command.as_deref()?)))
+    .collect();
+    if commands.is_empty() {
+        return Ok(true);
+    }
+    // A lone local_command is recorded as-is, so approvals from before hooks existed still count.
+    let approval: Vec<String> = commands.iter().map(|(label, _, command)| format!("{}{}", label, command)).collect();
+    let approval = approval.join("\n");
+    if tunnel_options.assume_yes || state::is_local_command_approved(profile_name, &approval)? {
+        return Ok(true);
+    }
+    for (_, when, command) in &commands {
+        eprintln!(
+            "{}",
+            style::warning(&format!("'{}' runs this command on your machine {}: {}", profile_name, when, command))
+        );
+    }
+    let question = if commands.len() == 1 { "Allow it?" } else { "Allow them?" };
+    if !Confirm::new(question).with_default(false).prompt()? {
+        return Ok(false);
+    }
+    state::approve_local_command(profile_name, &approval)?;
+    Ok(true)
+}
+
+// Returns None when the user declines to connect with saved passwords.
+fn confirm_and_unlock(ssh_config: &SshConfig, tunnel_options: &TunnelOptions) -> Result<Option<TunnelOptions>> {
+    // Saved passwords make the connection run unattended, so say what is about to happen first.
+    // Under --batch they can't be used, which resolve_credentials reports.
+    let unattended = tunnel_options.quiet || tunnel_options.assume_yes || tunnel_options.batch;
+    if ssh_config.has_saved_passwords() && !unattended {
+        println!("{}", saved_credentials_preview(ssh_config, tunnel_options.jump_only));
+        if !Confirm::new("Continue?").with_default(true).prompt()? {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(TunnelOptions {
+        quiet: tunnel_options.quiet,
+        assume_yes: tunnel_options.assume_yes,
+        exec: tunnel_options.exec.clone(),
+        forward_only: tunnel_options.forward_only,
+        agent_forwarding: tunnel_options.agent_forwarding,
+        tty: tunnel_options.tty,
+        jump_only: tunnel_options.jump_only,
+        session_timeout: tunnel_options.session_timeout,
+        print_urls: tunnel_options.print_urls,
+        dns_retries: tunnel_options.dns_retries,
+        allow_privileged_ports: tunnel_options.allow_privileged_ports,
+        batch: tunnel_options.batch,
+        credentials: resolve_credentials(ssh_config, tunnel_options.batch)?,
+    }))
+}
+
+fn profiles_with_tag(tag: Option<&str>) -> Result<Vec<String>> {
+    let mut profiles = list_profiles()?.unwrap_or_default();
+    if let Some(tag) = tag {
+        profiles = index::load_profiles(&profiles)?
+            .into_iter()
+            .filter(|(_, config)| config.as_ref().is_ok_and(|ssh_config| ssh_config.has_tag(tag)))
+            .map(|(profile, _)| profile)
+            .collect();
+    }
+    if profiles.is_empty() {
+        anyhow::bail!("No profiles found");
+    }
+    Ok(profiles)
+}
+
+// Unlike a full purge, the global config and other state stay, and one confirmation covers every match.
+fn purge_profiles(names: Vec<String>) -> Result<ExitCode> {
+    let existing = list_profiles()?.unwrap_or_default();
+    let profiles = select::expand(&names, &existing)?;
+    if let Some(missing) = profiles.iter().find(|profile| !existing.contains(profile)) {
+        anyhow::bail!("No such profile '{}'", missing);
+    }
+
+    println!("This permanently deletes {}:", pluralize(profiles.len(), "profile"));
+    for profile in &profiles {
+        println!("  {}", profile);
+    }
+    if !Confirm::new("Delete them?").with_default(false).prompt()? {
+        println!("Purge cancelled");
+        return Ok(ExitCode::FAILURE);
+    }
+
+    purge::remove_profiles(Path::new(&get_profiles_dir()?), &profiles)?;
+    // A default or last profile that no longer exists would only fail the next connect.
+    for name in [state::DEFAULT_PROFILE, state::LAST_PROFILE] {
+        if state::read_state(name)?.is_some_and(|profile| profiles.contains(&profile)) {
+            state::clear_state(name)?;
+        }
+    }
+    println!("Deleted {}", pluralize(profiles.len(), "profile"));
+    Ok(ExitCode::SUCCESS)
+}
+
+// Only reads the profiles directory when one of the names is a pattern.
+fn expand_profile_names(names: Vec<String>) -> Result<Vec<String>> {
+    if !names.iter().any(|name| select::is_pattern(name)) {
+        return Ok(names);
+    }
+    select::expand(&names, &list_profiles()?.unwrap_or_default())
+}
+
+// Every profile is changed in memory first, and nothing is written unless all of them can be, so a
+// profile that fails to decrypt can't leave some profiles on the old master password and some on the new.
+fn change_master_password(profile_names: Vec<String>, dry_run: bool, force: bool) -> Result<ExitCode> {
+    let named = !profile_names.is_empty();
+    let profile_names = if named { profile_names } else { list_profiles()?.unwrap_or_default() };
+    let mut candidates = Vec::new();
+    let mut failed = Vec::new();
+    for profile_name in dedup_profile_names(&profile_names) {
+        match load_profile(profile_name) {
+            Ok(ssh_config) if ssh_config.has_saved_secrets() => candidates.push((profile_name, ssh_config)),
+            Ok(_) if named => println!("Skipping '{}', which has no saved passwords", profile_name),
+            Ok(_) => {}
+            Err(e) => failed.push((profile_name, format!("{:#}", e))),
+        }
+    }
+    if candidates.is_empty() && failed.is_empty() {
+        println!("No profiles with saved passwords");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let current = Password::new("Enter current master password:")
+        .without_confirmation()
+        .prompt()
+        .context("Failed to get master password")?;
+    println!("Choose the new master password.");
+    let new = prompt_confirmed(|message| {
+        Password::new(message)
+            .without_confirmation()
+            .prompt()
+            .context("Failed to get master password")
+    })?;
+    if !is_password_strong(&new) {
+        println!("Master password is not strong enough.");
+        return Ok(ExitCode::FAILURE);
+    }
+    let cipher = GlobalConfig::load()?.cipher()?;
+    let mut changed = Vec::new();
+    for (profile_name, mut ssh_config) in candidates {
+        let reencrypted = reencrypt_passwords(&ssh_config, &current, &new, cipher).and_then(|encrypted| {
+            Ok((encrypted, reencrypt_key_passphrase(&ssh_config, &current, &new, cipher)?))
+        });
+        match reencrypted {
+            Ok((encrypted, key_passphrase)) => {
+                ssh_config.set_encrypted_passwords(encrypted);
+                ssh_config.enc_key_passphrase = key_passphrase;
+                changed.push((profile_name, ssh_config));
+            }
+            Err(e) => failed.push((profile_name, format!("{:#}", e))),
+        }
+    }
+
+    for (profile_name, _) in &changed {
+        println!("  ok    {}", profile_name);
+    }
+    for (profile_name, reason) in &failed {
+        println!("  FAIL  {}: {}", profile_name, reason);
+    }
+    let exit_code = if failed.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE };
+    if dry_run {
+        println!(
+            "Dry run: {} can be changed, {} can't; nothing was written",
+            pluralize(changed.len(), "profile"),
+            failed.len()
+        );
+        return Ok(exit_code);
+    }
+    if !failed.is_empty() && !force {
+        println!(
+            "Nothing was changed, since {} can't be; pass --force to change the others anyway",
+            pluralize(failed.len(), "profile")
+        );
+        return Ok(ExitCode::FAILURE);
+    }
+    for (profile_name, ssh_config) in &changed {
+        save_profile(profile_name, ssh_config).context(format!("Failed to save profile '{}'", profile_name))?;
+    }
+    println!("Master password changed for {}", pluralize(changed.len(), "profile"));
+    Ok(exit_code)
+}
+
+fn dedup_profile_names(profile_names: &[String]) -> Vec<&str> {
+    let mut unique = Vec::new();
+    for profile_name in profile_names {
+        if !unique.contains(&profile_name.as_str()) {
+            unique.push(profile_name.as_str());
+        }
+    }
+    unique
+}
+
+fn select_profile(preselected: Option<&str>) -> Result<String> {
+    let profiles = list_profiles()?.unwrap_or_default();
+    if profiles.is_empty() {
+        anyhow::bail!("No profiles found");
+    }
+
+    let cursor = preselected
+        .and_then(|name| profiles.iter().position(|profile| profile == name))
+        .unwrap_or(0);
+    Select::<String>::new("Select profile:", profiles)
+        .with_starting_cursor(cursor)
+        .with_page_size(PROFILE_PAGE_SIZE)
+        .with_help_message("↑↓ to move, type to filter, enter to select")
+        .prompt()
+        .context("Failed to select profile")
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode> {
+    // ssh runs this binary as its askpass program, with the prompt as the only argument.
+    if askpass::is_helper_invocation() {
+        return Ok(askpass::run_helper());
+    }
+
+    let cli = Cli::from_args();
+    if cli.no_color {
+        style::disable_color();
+    }
+    init_logging(cli.log_level);
+    if let Some(profiles_dir) = cli.profiles_dir {
+        rush_tunnel::set_profiles_dir(profiles_dir);
+    }
+    let quiet = cli.quiet;
+    let tunnel_options = TunnelOptions {
+        quiet,
+        allow_privileged_ports: cli.allow_privileged,
+        batch: cli.batch,
+        ..Default::default()
+    };
+
+    let command = match cli.command {
+        Some(command) => command,
+        None if std::io::stdin().is_terminal() => match main_menu()? {
+            Some(command) => command,
+            None => return Ok(ExitCode::SUCCESS),
+        },
+        None => {
+            Cli::clap().write_help(&mut std::io::stderr())?;
+            eprintln!();
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    match command {
+        Subcommand::Interactive { save } => {
+            let mut config = from_interactive_input(&save)?;
+            let profile_name = save_new_profile(save, &config)?;
+            apply_global_defaults(&mut config, SshOptions::default())?;
+            let mut tunnel_options = TunnelOptions {
+                credentials: resolve_credentials(&config, tunnel_options.batch)?,
+                ..tunnel_options
+            };
+            let outcome = establish_tunnel(&config, &mut tunnel_options).await?;
+            return match profile_name {
+                Some(profile_name) => finish_tunnel(&profile_name, &outcome, quiet),
+                None => Ok(tunnel_exit_code(&outcome, quiet)),
+            };
+        }
+        Subcommand::Tunnel(args) if args.format.is_some() && !args.stdin_config && !args.print_config => {
+            anyhow::bail!("--format can only be used with --stdin-config or --print-config");
+        }
+        Subcommand::Tunnel(args) if args.stdin_config => {
+            let (print_config, format) = (args.print_config, args.format);
+            let config = config_from_stdin(args)?;
+            if print_config {
+                return print_merged_config(&config, format);
+            }
+            // The config was never saved, so there is no profile to remember as the last one or to
+            // record a local_command approval for; whoever pipes it in chose the command.
+            let mut tunnel_options = TunnelOptions {
+                credentials: resolve_credentials(&config, tunnel_options.batch)?,
+                ..tunnel_options
+            };
+            let outcome = establish_tunnel(&config, &mut tunnel_options).await?;
+            return Ok(tunnel_exit_code(&outcome, quiet));
+        }
+        Subcommand::Tunnel(args) if args.print_config => {
+            let format = args.format;
+            let mut config = from_non_interactive_input(args)?;
+            // In the shape it would be saved in.
+            migrate::migrate(&mut config)?;
+            apply_global_defaults(&mut config, SshOptions::default())?;
+            return print_merged_config(&config, format);
+        }
+        Subcommand::Tunnel(args) => {
+            let save = args.save.clone();
+            let mut config = from_non_interactive_input(args)?;
+            let profile_name = save_new_profile(save, &config)?;
+            apply_global_defaults(&mut config, SshOptions::default())?;
+            // With --no-save there is no profile to record an approval for; the command was given
+            // on this command line, or by the --clone-from profile that was asked about when it connected.
+            if let Some(profile_name) = &profile_name {
+                if !confirm_local_command(profile_name, &config, &tunnel_options)? {
+                    println!("Connection cancelled");
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+            let mut tunnel_options = TunnelOptions {
+                credentials: resolve_credentials(&config, tunnel_options.batch)?,
+                ..tunnel_options
+            };
+            let outcome = establish_tunnel(&config, &mut tunnel_options).await?;
+            return match profile_name {
+                Some(profile_name) => finish_tunnel(&profile_name, &outcome, quiet),
+                None => Ok(tunnel_exit_code(&outcome, quiet)),
+            };
+        }
+        Subcommand::Connect {
+            profile,
+            last,
+            all,
+            tag,
+            exec,
+            local_forwards,
+            only_forward,
+            select_forward,
+            yes,
+            agent_forward,
+            tty,
+            no_tty,
+            no_exit_on_forward_failure,
+            ipv4,
+            ipv6,
+            jump_only,
+            public_forward,
+            jump_user,
+            target_user,
+            session_timeout,
+            report,
+            print_url,
+            local_forward_only,
+            replace,
+            dns_retries,
+            print_config,
+            format,
+            mut options,
+        } => {
+            if no_exit_on_forward_failure {
+                options.exit_on_forward_failure = Some(false);
+            }
+            options.address_family = AddressFamily::from_flags(ipv4, ipv6);
+            if session_timeout == Some(0) {
+                anyhow::bail!("--session-timeout must be at least 1 second");
+            }
+            let tunnel_options = TunnelOptions {
+                exec,
+                assume_yes: yes,
+                agent_forwarding: agent_forward,
+                tty: if tty { Some(true) } else if no_tty { Some(false) } else { None },
+                jump_only,
+                session_timeout: session_timeout.map(Duration::from_secs),
+                print_urls: print_url,
+                forward_only: local_forward_only,
+                dns_retries,
+                ..tunnel_options
+            };
+            let mut profile = expand_profile_names(profile)?;
+            if all || profile.len() > 1 {
+                let profile_names = if all { profiles_with_tag(tag.as_deref())? } else { profile };
+                // Tunnels for several profiles run forwards-only (`-N`), with no remote session to attach.
+                if tunnel_options.exec.is_some()
+                    || !local_forwards.is_empty()
+                    || !only_forward.is_empty()
+                    || select_forward
+                    || tty
+                    || jump_only
+                    || session_timeout.is_some()
+                    || report.is_some()
+                    || print_url
+                    || dns_retries > 0
+                    || print_config
+                {
+                    anyhow::bail!(
+                        "--exec, --local-forward, --only-forward, --select-forward, --tty, --jump-only, \
+                         --session-timeout, --report, --print-url, --dns-retries and --print-config can only be used \
+                         with a single profile"
+                    );
+                }
+                let overrides = ConnectOverrides {
+                    options,
+                    public_forward,
+                    jump_user,
+                    target_user,
+                    ..Default::default()
+                };
+                return connect_profiles(&profile_names, overrides, replace, &tunnel_options).await;
+            }
+            let profile_name = resolve_profile_name(profile.pop(), last)?;
+            let overrides = ConnectOverrides {
+                options,
+                forwards: local_forwards,
+                only_forwards: only_forward,
+                select_forwards: select_forward,
+                public_forward,
+                jump_user,
+                target_user,
+            };
+            if print_config {
+                let mut config = load_connect_config(&profile_name, overrides)?;
+                config.agent_forwarding |= tunnel_options.agent_forwarding;
+                return print_merged_config(&config, format);
+            }
+            return connect_profile(&profile_name, overrides, replace, &tunnel_options, report).await;
+        }
+        Subcommand::Reconnect { yes } => {
+            let profile_name = resolve_profile_name(None, true)?;
+            let tunnel_options = TunnelOptions {
+                assume_yes: yes,
+                ..tunnel_options
+            };
+            return connect_profile(&profile_name, ConnectOverrides::default(), false, &tunnel_options, None).await;
+        }
+        Subcommand::SetDefault { profile } => {
+            load_profile(&profile).context(format!("Failed to load profile '{}'", &profile))?;
+            state::write_state(state::DEFAULT_PROFILE, &profile)?;
+            println!("Default profile set to '{}'", profile);
+        }
+        Subcommand::UnsetDefault => {
+            if state::clear_state(state::DEFAULT_PROFILE)? {
+                println!("Default profile cleared");
+            } else {
+                println!("No default profile set");
+            }
+        }
+        Subcommand::ClearPasswords { profile, yes } => {
+            let ssh_config = load_profile(&profile).context(format!("Failed to load profile '{}'", &profile))?;
+            if ssh_config.enc1.is_none()
+                && ssh_config.enc2.is_none()
+                && ssh_config.verifier.is_none()
+                && ssh_config.enc_key_passphrase.is_none()
+            {
+                println!("Profile '{}' has no saved passwords", profile);
+                return Ok(ExitCode::SUCCESS);
+            }
+            if !yes
+                && !Confirm::new(&format!("Remove the saved passwords from '{}'?", profile))
+                    .with_default(false)
+                    .prompt()?
+            {
+                println!("Passwords not cleared");
+                return Ok(ExitCode::FAILURE);
+            }
+            save_profile(&profile, &ssh_config.without_secrets())?;
+            println!("Saved passwords removed from '{}'; you will be asked for passwords when connecting", profile);
+        }
+        Subcommand::ChangeMasterPassword {
+            profiles,
+            dry_run,
+            force,
+        } => return change_master_password(expand_profile_names(profiles)?, dry_run, force),
+        Subcommand::CopyId { profile, key } => {
+            let mut ssh_config = load_profile(&profile).context(format!("Failed to load profile '{}'", &profile))?;
+            apply_global_defaults(&mut ssh_config, SshOptions::default())?;
+            let key = match key {
+                Some(key) => key,
+                None => copy_id::default_public_key()?,
+            };
+            if !key.is_file() {
+                anyhow::bail!("Key {} does not exist", key.display());
+            }
+            if !quiet {
+                println!("Installing {} on {}", key.display(), ssh_config.target_host);
+            }
+            // ssh-copy-id is how key authentication gets set up, so it still asks for the password.
+            let credentials = resolve_credentials(&ssh_config, false)?;
+            let status = copy_id::copy_id(&ssh_config, &key, credentials.as_ref())?;
+            if !status.success() {
+                eprintln!("{}", style::error(&format!("ssh-copy-id failed: {}", status)));
+                return Ok(match status.code() {
+                    Some(code) if (1..=255).contains(&code) => ExitCode::from(code as u8),
+                    _ => ExitCode::FAILURE,
+                });
+            }
+        }
+        Subcommand::Profiles {
+            tag,
+            verbose,
+            format,
+            columns,
+            sort,
+            reverse,
+        } => {
+            if !columns.is_empty() && format != ListFormat::Table {
+                anyhow::bail!("--columns only applies to --format table");
+            }
+            let columns = if columns.is_empty() { DEFAULT_COLUMNS.to_vec() } else { columns };
+            let scan = scan_profiles()?;
+            if let Some(mut scan) = scan {
+                let created = if sort == ProfileSort::Created { state::created()? } else { Default::default() };
+                let last_used = if sort == ProfileSort::LastUsed { state::last_used()? } else { Default::default() };
+                let profiles_dir = get_profiles_dir()?;
+                table::sort_profiles(&mut scan.profiles, sort, Path::new(&profiles_dir), &created, &last_used);
+                if reverse {
+                    scan.profiles.reverse();
+                }
+                let mut listed = 0;
+                let mut skipped = scan.skipped;
+                let mut rows = Vec::new();
+                let all_profiles = scan.profiles.clone();
+                let loaded: Vec<_> = if tag.is_some() || verbose || format == ListFormat::Table {
+                    let loaded = index::load_profiles(&scan.profiles)?;
+                    loaded.into_iter().map(|(name, config)| (name, Some(config))).collect()
+                } else {
+                    scan.profiles.into_iter().map(|name| (name, None)).collect()
+                };
+                for (profile, config) in loaded {
+                    if let Some(config) = config {
+                        match config {
+                            Ok(ssh_config) if tag.as_ref().is_none_or(|tag| ssh_config.has_tag(tag)) => {
+                                rows.push(columns.iter().map(|column| column.value(&profile, &ssh_config)).collect());
+                            }
+                            Ok(_) => continue,
+                            Err(e) => {
+                                let reason = format!("{:#}", e);
+                                let reason = reason.lines().next().unwrap_or_default().to_string();
+                                skipped.push((format!("{}.toml", profile), reason));
+                                continue;
+                            }
+                        }
+                    }
+                    listed += 1;
+                    if format == ListFormat::Table {
+                        continue;
+                    }
+                    let variants = case_variants(&profile, &all_profiles);
+                    if variants.is_empty() {
+                        println!("- {}", style::highlight(&profile));
+                    } else {
+                        println!(
+                            "- {} {}",
+                            style::highlight(&profile),
+                            style::dim(&format!("(differs only by case from '{}')", variants.join("', '")))
+                        );
+                    }
+                }
+                if format == ListFormat::Table {
+                    let headers: Vec<&str> = columns.iter().map(Column::header).collect();
+                    print!("{}", table::render(&headers, &rows));
+                }
+                if verbose {
+                    for (file_name, reason) in &skipped {
+                        println!("{}", style::dim(&format!("  skipped {}: {}", file_name, reason)));
+                    }
+                }
+                println!("{}", style::dim(&pluralize(listed, "profile")));
+            } else {
+                println!("No profiles found");
+            }
+        }
+        Subcommand::Search { query } => {
+            let mut found = false;
+            for (profile, ssh_config) in index::load_profiles(&list_profiles()?.unwrap_or_default())? {
+                if let Ok(ssh_config) = ssh_config {
+                    if ssh_config.matches_query(&query) {
+                        found = true;
+                        println!(
+                            "- {} — {}@{}",
+                            profile,
+                            ssh_config.target_host_user,
+                            bracket_host(&ssh_config.target_host)
+                        );
+                    }
+                }
+            }
+            if !found {
+                println!("No profiles match '{}'", query);
+            }
+        }
+        Subcommand::Show { profiles, format } => {
+            let profiles = expand_profile_names(profiles)?;
+            if format.is_some() && profiles.len() > 1 {
+                anyhow::bail!("--format can only print one profile, and {} were named", profiles.len());
+            }
+            for (i, profile) in profiles.iter().enumerate() {
+                let ssh_config = load_profile(profile).context(format!("Failed to load profile '{}'", profile))?;
+                match format {
+                    Some(format) => print!("{}", formats::serialize(&ssh_config.without_secrets(), format)?),
+                    None => {
+                        if i > 0 {
+                            println!();
+                        }
+                        show_profile(profile, &ssh_config);
+                    }
+                }
+            }
+        }
+        Subcommand::Path { create, json } => {
+            let profiles_dir = PathBuf::from(get_profiles_dir()?);
+            if create && !profiles_dir.exists() {
+                fsutil::create_private_dir(&profiles_dir)
+                    .context(format!("Failed to create {}", profiles_dir.display()))?;
+                if !json {
+                    println!("Created {}", profiles_dir.display());
+                }
+            }
+            let status = fsutil::DirStatus::of(&profiles_dir);
+            if json {
+                println!("{}", serde_json::to_string(&status).context("Failed to serialize the directory status")?);
+                return Ok(ExitCode::SUCCESS);
+            }
+            let note = match (status.exists, status.writable) {
+                (true, true) => "exists, writable".to_string(),
+                (true, false) if !profiles_dir.is_dir() => "not a directory".to_string(),
+                (true, false) => "exists, not writable".to_string(),
+                (false, _) => "doesn't exist yet; `rush-tunnel path --create` creates it".to_string(),
+            };
+            println!("Profiles directory: {} ({})", status.path, note);
+        }
+        Subcommand::Schema => {
+            println!("{}", formats::profile_schema()?);
+        }
+        Subcommand::Purge { profiles } if !profiles.is_empty() => return purge_profiles(profiles),
+        Subcommand::Purge { .. } => {
+            let profiles_dir = PathBuf::from(get_profiles_dir()?);
+            if !profiles_dir.exists() {
+                println!("Nothing to purge: {} does not exist", profiles_dir.display());
+                return Ok(ExitCode::SUCCESS);
+            }
+            purge::check_purge_target(&profiles_dir)?;
+            let files = purge::purgeable_files(&profiles_dir)?;
+            if files.is_empty() {
+                println!("Nothing to purge in {}", profiles_dir.display());
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            println!("This permanently deletes from {}:", profiles_dir.display());
+            for file in &files {
+                println!("  {}", file.file_name().unwrap_or_default().to_string_lossy());
+            }
+            // A stray "y" is too easy to type for something that can't be undone.
+            let answer = Text::new("Type DELETE to confirm:").prompt()?;
+            if answer.trim() != "DELETE" {
+                println!("Purge cancelled");
+                return Ok(ExitCode::FAILURE);
+            }
+
+            let removed_dir = purge::purge(&profiles_dir, &files)?;
+            println!("Deleted {}", pluralize(files.len(), "file"));
+            if !removed_dir {
+                println!("Kept {}, which still contains other files", profiles_dir.display());
+            }
+        }
+        Subcommand::CleanSockets => {
+            let profiles_dir = PathBuf::from(get_profiles_dir()?);
+            let cleanup = multiplex::clean_stale_sockets(&profiles_dir)?;
+            for path in &cleanup.removed {
+                println!("Removed {}", path.display());
+            }
+            println!(
+                "Removed {}, {} still in use",
+                pluralize(cleanup.removed.len(), "stale socket"),
+                cleanup.live.len()
+            );
+        }
+        Subcommand::Completions { shell, install } => {
+            let shell = match shell {
+                Some(shell) => shell,
+                None => std::env::var("SHELL")
+                    .ok()
+                    .and_then(|shell| detect_shell(&shell))
+                    .context("Couldn't tell the shell from $SHELL; name it, e.g. `completions bash`")?,
+            };
+            if !install {
+                print!("{}", completion_script(shell)?);
+                return Ok(ExitCode::SUCCESS);
+            }
+            let home = dirs::home_dir().context("Failed to get home directory")?;
+            let xdg_dir = |name| std::env::var_os(name).map(PathBuf::from).filter(|dir| dir.is_absolute());
+            let (path, setup) =
+                completion_install_target(shell, &home, xdg_dir("XDG_DATA_HOME"), xdg_dir("XDG_CONFIG_HOME"));
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+            }
+            fs::write(&path, completion_script(shell)?).context(format!("Failed to write {}", path.display()))?;
+            println!("Installed {} completions to {}", shell.to_string().to_lowercase(), path.display());
+            match setup {
+                Some((startup_file, line)) => {
+                    println!("Unless it's there already, add this to {} and start a new shell:", startup_file);
+                    println!("  {}", line);
+                }
+                None => println!("Start a new shell to use them"),
+            }
+        }
+        Subcommand::ListProfileNames => {
+            for profile in list_profiles()?.unwrap_or_default() {
+                println!("{}", profile);
+            }
+        }
+        Subcommand::Doctor => {
+            let checks = doctor::run_checks();
+            for check in &checks {
+                println!("{:<5} {}: {}", check.status.label(), check.name, check.detail);
+            }
+            if checks.iter().any(|check| check.status == CheckStatus::Fail) {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Subcommand::Validate => {
+            let profiles = list_profiles()?.unwrap_or_default();
+            let mut invalid = 0;
+            for profile in &profiles {
+                let problems = match load_profile(profile) {
+                    Ok(ssh_config) => join_problems(&ssh_config.validate(), "; "),
+                    Err(e) => format!("{:#}", e),
+                };
+                if problems.is_empty() {
+                    println!("ok       {}", profile);
+                } else {
+                    invalid += 1;
+                    println!("invalid  {}: {}", profile, problems);
+                }
+            }
+            println!("{} of {} profile(s) invalid", invalid, profiles.len());
+            if invalid > 0 {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Subcommand::Export { profile, append, format } => {
+            let ssh_config = load_profile(&profile).context(format!("Failed to load profile '{}'", &profile))?;
+            if let Some(format) = format {
+                print!("{}", formats::serialize(&ssh_config.without_secrets(), format)?);
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            let block = openssh::to_host_block(&profile, &ssh_config);
+            print!("{}", block);
+
+            if append {
+                let config_path = openssh::get_user_config_path()?;
+                if Confirm::new(&format!("Append to {}?", config_path.display()))
+                    .with_default(false)
+                    .prompt()?
+                {
+                    openssh::append_host_block(&block)?;
+                    println!("Appended profile '{}' to {}", profile, config_path.display());
+                }
+            }
+        }
+        Subcommand::Import {
+            file,
+            ssh_config: true,
+            merge,
+            ..
+        } => {
+            let user_config = openssh::get_user_config_path()?;
+            let path = file.unwrap_or_else(|| user_config.clone());
+            return import_ssh_config(&path, user_config.parent().unwrap_or(Path::new(".")), merge);
+        }
+        Subcommand::Import { file, name, format, .. } => {
+            let file = file.context("A file to import is required")?;
+            let input = fs::read_to_string(&file).context("Failed to read profile file")?;
+            let format = format.or_else(|| Format::from_path(&file)).unwrap_or(Format::Toml);
+            let ssh_config = parse_imported_profile(&input, format)?;
+            let profile_name = match name {
+                Some(name) => name,
+                None => file
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .context("Failed to derive a profile name from the file name")?,
+            };
+            if save_or_overwrite_profile(&profile_name, &ssh_config)? {
+                println!("Imported profile '{}'", profile_name);
+            }
+        }
+        Subcommand::Backup { out } => {
+            let profiles_dir = get_profiles_dir()?;
+            let mut archive = backup::create_archive(Path::new(&profiles_dir))?;
+
+            if Confirm::new("Encrypt backup with a passphrase?").with_default(false).prompt()? {
+                let passphrase = Password::new("Enter backup passphrase:").prompt()?;
+                if !is_password_strong(&passphrase) {
+                    anyhow::bail!("Backup passphrase is not strong enough.");
+                }
+                archive = backup::encrypt_archive(&passphrase, &archive, GlobalConfig::load()?.cipher()?)?;
+            }
+
+            fsutil::write_private(&out, &archive).context("Failed to write backup file")?;
+            println!("Profiles backed up to {}", out.display());
+        }
+        Subcommand::Restore { file } => {
+            let mut archive = fs::read(&file).context("Failed to read backup file")?;
+            if backup::is_encrypted_archive(&archive) {
+                let passphrase = Password::new("Enter backup passphrase:")
+                    .without_confirmation()
+                    .prompt()?;
+                archive = backup::decrypt_archive(&passphrase, &archive)?;
+            }
+
+            let profiles_dir = get_profiles_dir()?;
+            let restored = backup::restore_archive(&archive, Path::new(&profiles_dir), |name| {
+                Confirm::new(&format!("'{}' already exists. Overwrite?", name))
+                    .with_default(false)
+                    .prompt()
+                    .context("Failed to confirm overwrite")
+            })?;
+            println!("Restored {} file(s) into {}", restored.len(), profiles_dir);
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+// Each menu entry maps onto the subcommand a user would have typed, so both go through the same flow.
+fn main_menu() -> Result<Option<Subcommand>> {
+    const CONNECT: &str = "Connect";
+    const CREATE: &str = "Create tunnel";
+    const LIST: &str = "List profiles";
+    const MANAGE: &str = "Manage profiles";
+    const QUIT: &str = "Quit";
+
+    loop {
+        let choice = Select::new("What would you like to do?", vec![CONNECT, CREATE, LIST, MANAGE, QUIT])
+            .prompt()
+            .context("Failed to select an action")?;
+        let args = match choice {
+            CONNECT => vec!["connect".to_string()],
+            CREATE => vec!["interactive".to_string()],
+            LIST => vec!["profiles".to_string()],
+            MANAGE => match manage_profiles_menu()? {
+                Some(args) => args,
+                None => continue,
+            },
+            _ => return Ok(None),
+        };
+        return Ok(Cli::from_iter(std::iter::once("rush-tunnel".to_string()).chain(args)).command);
+    }
+}
+
+// Returns None when the user goes back to the main menu.
+fn manage_profiles_menu() -> Result<Option<Vec<String>>> {
+    const ACTIONS: [(&str, &str); 4] = [
+        ("Show details", "show"),
+        ("Set as default", "set-default"),
+        ("Export as ssh_config host block", "export"),
+        ("Remove saved passwords", "clear-passwords"),
+    ];
+    const BACK: &str = "Back";
+
+    let profile = select_profile(state::read_state(state::DEFAULT_PROFILE)?.as_deref())?;
+    let labels = ACTIONS.iter().map(|(label, _)| *label).chain([BACK]).collect();
+    let choice = Select::new(&format!("What should be done with '{}'?", profile), labels)
+        .prompt()
+        .context("Failed to select an action")?;
+    Ok(ACTIONS
+        .iter()
+        .find(|(label, _)| *label == choice)
+        .map(|(_, subcommand)| vec![subcommand.to_string(), profile]))
+}
+
+// Logs go to stderr so they never mix with output meant for pipes, such as `show --format`.
+fn init_logging(level: Option<LevelFilter>) {
+    let filter = match level {
+        Some(level) => EnvFilter::default().add_directive(level.into()),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .with_ansi(style::stderr_color())
+        .init();
+}
+
+fn show_profile(profile_name: &str, config: &SshConfig) {
+    println!("{} {}", style::heading("Profile:"), style::highlight(profile_name));
+    match &config.proxy_command {
+        Some(command) => println!("{}", style::field("Proxy Command:", command)),
+        None => {
+            let mut jump =
+                format!("{}@{}:{}", config.jump_host_user, bracket_host(&config.jump_host), config.jump_port);
+            if let Some(name) = &config.jump_ref {
+                jump.push_str(&format!(" (from {} [{}])", hostbook::HOST_BOOK_FILE, name));
+            }
+            println!("{}", style::field("Jump Host:", &jump));
+        }
+    }
+    if let Some(pre_proxy) = &config.pre_proxy {
+        println!("{}", style::field("Via Proxy:", pre_proxy));
+    }
+    let target = format!(
+        "{}@{}:{}",
+        config.target_host_user,
+        bracket_host(&config.target_host),
+        config.target_port
+    );
+    println!("{}", style::field("Target Host:", &target));
+    for forward in config.local_forwards() {
+        let forward = format!("{} -> {}", forward.listen_spec(), forward.destination(&config.target_host));
+        println!("{}", style::field("Forward:", &forward));
+    }
+    if !config.tags.is_empty() {
+        println!("{}", style::field("Tags:", &config.tags.join(", ")));
+    }
+    if let Some(description) = &config.description {
+        println!("{}", style::field("Description:", description));
+    }
+    println!("{}", style::field("Agent forwarding:", if config.agent_forwarding { "yes" } else { "no" }));
+    if config.multiplex {
+        let persist = config.control_persist.unwrap_or(multiplex::DEFAULT_CONTROL_PERSIST);
+        println!("{}", style::field("Multiplex:", &format!("yes, kept open {}s", persist)));
+    }
+    if let Some(command) = &config.local_command {
+        println!("{}", style::field("Local command:", command));
+    }
+    if let Some(command) = &config.on_connect {
+        println!("{}", style::field("On connect:", command));
+    }
+    if let Some(command) = &config.on_disconnect {
+        println!("{}", style::field("On disconnect:", command));
+    }
+    let saved = match (config.enc1.is_some() || config.enc2.is_some(), plaintext::has_plaintext_passwords(config)) {
+        (true, true) => "yes (insecure plaintext)",
+        (true, false) => "yes",
+        (false, _) => "no",
+    };
+    println!("{}", style::field("Saved passwords:", saved));
+    if let Some(key_file) = &config.options.identity_file {
+        println!("{}", style::field("Identity file:", key_file));
+    }
+    if let Some(rate) = config.options.limit_rate {
+        let limit = format!("{} Kbit/s, for scp -l {} / sftp -l {} (ssh can't cap forwards)", rate, rate, rate);
+        println!("{}", style::field("Limit rate:", &limit));
+    }
+    if config.enc_key_passphrase.is_some() {
+        println!("{}", style::field("Key passphrase:", "saved"));
+    }
+    if let Some(path) = &config.jump_password_file {
+        println!("{}", style::field("Jump password:", &format!("from {}", path)));
+    }
+    if let Some(path) = &config.target_password_file {
+        println!("{}", style::field("Target password:", &format!("from {}", path)));
+    }
+}
+
+// A tunnel closed with Ctrl-C is how forward-only sessions usually end, so it still counts as used.
+fn finish_tunnel(profile_name: &str, outcome: &TunnelOutcome, quiet: bool) -> Result<ExitCode> {
+    if outcome.status.success() || outcome.interrupted_by.is_some() {
+        record_connected(profile_name)?;
+    }
+    Ok(tunnel_exit_code(outcome, quiet))
+}
+
+fn record_connected(profile_name: &str) -> Result<()> {
+    state::write_state(state::LAST_PROFILE, profile_name)?;
+    state::record_last_used(profile_name, SystemTime::now())
+}
+
+fn tunnel_exit_code(outcome: &TunnelOutcome, quiet: bool) -> ExitCode {
+    let status = outcome.status;
+    if status.success() {
+        if !quiet {
+            println!("SSH tunnel closed gracefully!");
+        }
+        return ExitCode::SUCCESS;
+    }
+    // Exits the way the shell reports a command stopped by that signal.
+    if let Some(signal) = outcome.interrupted_by {
+        if !quiet {
+            println!("SSH tunnel closed");
+        }
+        return ExitCode::from(128 + signal as u8);
+    }
+
+    eprintln!("{}", style::error(&format!("SSH tunnel failed: ssh {}", status)));
+    match status.code() {
+        Some(code) if (1..=255).contains(&code) => ExitCode::from(code as u8),
+        _ => ExitCode::FAILURE,
+    }
+}
+
+// Subcommands whose first positional argument is a profile name.
+const PROFILE_ARG_SUBCOMMANDS: &[&str] = &[
+    "show",
+    "export",
+    "set-default",
+    "clear-passwords",
+    "copy-id",
+    "change-master-password",
+    "purge",
+];
+
+// How clap's zsh script spells a positional `profile`, or `profiles` when it takes several or none.
+fn is_zsh_profile_arg(line: &str) -> bool {
+    ["':profile -- ", "':profiles -- ", "'::profiles -- "]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+}
+
+const BASH_PROFILE_COMPLETION: &str = r#"_rush-tunnel_with_profiles() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    case "${prev}" in
+        --profile|@SUBCOMMANDS@)
+            if [[ ${cur} != -* ]]; then
+                COMPREPLY=( $(compgen -W "$(rush-tunnel list-profile-names 2>/dev/null)" -- "${cur}") )
+                return 0
+            fi
+            ;;
+    esac
+    _rush-tunnel "$@"
+}
+
+complete -F _rush-tunnel_with_profiles -o bashdefault -o default rush-tunnel
+"#;
+
+const ZSH_PROFILE_COMPLETION: &str = r#"_rush-tunnel_profiles() {
+    local -a profiles
+    profiles=(${(f)"$(rush-tunnel list-profile-names 2>/dev/null)"})
+    compadd -a profiles
+}
+
+"#;
+
+// clap only generates static scripts, so the bash and zsh ones are patched to ask the hidden
+// `list-profile-names` command for profile names at completion time.
+fn completion_script(shell: Shell) -> Result<String> {
+    let mut script = Vec::new();
+    Cli::clap().gen_completions_to("rush-tunnel", shell, &mut script);
+    let script = String::from_utf8(script)?;
+
+    Ok(match shell {
+        Shell::Bash => {
+            let registration = "complete -F _rush-tunnel -o bashdefault -o default rush-tunnel\n";
+            let wrapper = BASH_PROFILE_COMPLETION.replace("@SUBCOMMANDS@", &PROFILE_ARG_SUBCOMMANDS.join("|"));
+            script.replace(registration, &wrapper)
+        }
+        Shell::Zsh => {
+            let script: Vec<String> = script
+                .lines()
+                .map(|line| {
+                    if is_zsh_profile_arg(line) {
+                        line.replace(":_files'", ":_rush-tunnel_profiles'")
+                    } else if line.starts_with("'*--profile=[") {
+                        line.replace("]' \\", "]:profile:_rush-tunnel_profiles' \\")
+                    } else {
+                        line.to_string()
+                    }
+                })
+                .collect();
+            let script = script.join("\n") + "\n";
+            let entry_point = "_rush-tunnel \"$@\"";
+            script.replace(entry_point, &format!("{}{}", ZSH_PROFILE_COMPLETION, entry_point))
+        }
+        _ => script,
+    })
+}
+
+fn detect_shell(shell_path: &str) -> Option<Shell> {
+    let name = shell_path.rsplit(['/', '\\']).next()?;
+    match name.strip_suffix(".exe").unwrap_or(name) {
+        "bash" => Some(Shell::Bash),
+        "zsh" => Some(Shell::Zsh),
+        "fish" => Some(Shell::Fish),
+        "pwsh" | "powershell" => Some(Shell::PowerShell),
+        "elvish" => Some(Shell::Elvish),
+        _ => None,
+    }
+}
+
+// Where each shell finds completions for a single user. bash (with bash-completion) and fish load
+// them from there on their own; the others need a line in their startup file, given as
+// `(startup file, line)`.
+fn completion_install_target(
+    shell: Shell,
+    home: &Path,
+    xdg_data_home: Option<PathBuf>,
+    xdg_config_home: Option<PathBuf>,
+) -> (PathBuf, Option<(&'static str, String)>) {
+    let data_dir = xdg_data_home.unwrap_or_else(|| home.join(".local/share"));
+    let config_dir = xdg_config_home.unwrap_or_else(|| home.join(".config"));
+    match shell {
+        Shell::Bash => (data_dir.join("bash-completion/completions/rush-tunnel"), None),
+        Shell::Fish => (config_dir.join("fish/completions/rush-tunnel.fish"), None),
+        Shell::Zsh => {
+            let dir = home.join(".zfunc");
+            let line = format!("fpath=({} $fpath); autoload -Uz compinit && compinit", dir.display());
+            (dir.join("_rush-tunnel"), Some(("~/.zshrc", line)))
+        }
+        Shell::PowerShell => {
+            let path = config_dir.join("powershell/rush-tunnel.ps1");
+            let line = format!(". '{}'", path.display());
+            (path, Some(("your $PROFILE", line)))
+        }
+        Shell::Elvish => {
+            let path = config_dir.join("elvish/lib/rush-tunnel.elv");
+            (path, Some(("rc.elv", "use rush-tunnel".to_string())))
+        }
+    }
+}
+
+// Hosts that can't become a profile, such as those reached without a jump host, are listed and skipped.
+fn import_ssh_config(path: &Path, include_dir: &Path, merge: bool) -> Result<ExitCode> {
+    let parsed = import::read_ssh_config(path, include_dir)?;
+    for file in &parsed.files {
+        println!("{}: {}", file.path.display(), pluralize(file.hosts, "host"));
+    }
+    for warning in &parsed.warnings {
+        eprintln!("{}", style::warning(&format!("Warning: {}", warning)));
+    }
+
+    let existing = list_profiles()?.unwrap_or_default();
+    let mut imported = 0;
+    let mut skipped = 0;
+    for host in parsed.host_names() {
+        let ssh_config = match parsed.to_profile(&host) {
+            Ok(ssh_config) => ssh_config,
+            Err(e) => {
+                println!("  {:<9}  {}: {:#}", "skipped", host, e);
+                skipped += 1;
+                continue;
+            }
+        };
+        if !merge || !existing.contains(&host) {
+            if save_or_overwrite_profile(&host, &ssh_config)? {
+                let action = if existing.contains(&host) { "replaced" } else { "created" };
+                println!("  {:<9}  {}", action, host);
+                imported += 1;
+            }
+            continue;
+        }
+        let current = load_profile(&host).context(format!("Failed to load profile '{}'", host))?;
+        let merged = import::merge_profile(&current, ssh_config);
+        if merged == current {
+            println!("  {:<9}  {}", "unchanged", host);
+        } else {
+            save_profile(&host, &merged)?;
+            println!("  {:<9}  {}", "updated", host);
+            imported += 1;
+        }
+    }
+    println!("Imported {}, skipped {}", pluralize(imported, "profile"), skipped);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn pluralize(count: usize, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_config(jump_host: &str, target_host: &str) -> SshConfig {
+        SshConfig {
+            jump_host_user: "jumper".to_string(),
+            jump_host: jump_host.to_string(),
+            target_host_user: "deploy".to_string(),
+            target_host: target_host.to_string(),
+            jump_port: 22,
+            target_port: 2222,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_required_arg() {
+        assert_eq!(required_arg(Some(" user ".to_string()), "target_host_user").unwrap(), "user");
+        assert!(required_arg(Some("  ".to_string()), "target_host_user").is_err());
+        assert_eq!(
+            required_arg(None, "target_host_user").unwrap_err().to_string(),
+            "Missing target host username"
+        );
+    }
+
+    #[test]
+    fn test_parse_profile_name() {
+        assert_eq!(parse_profile_name(" staging-db ").unwrap(), "staging-db");
+        assert!(parse_profile_name(" ").is_err());
+    }
+
+    #[test]
+    fn test_checked_port() {
+        assert_eq!(checked_port(Some(22), "port").unwrap(), Some(22));
+        assert_eq!(checked_port(Some(65535), "port").unwrap(), Some(65535));
+        assert_eq!(checked_port(None, "port").unwrap(), None);
+        assert_eq!(checked_port(Some(0), "--jump-port").unwrap_err().to_string(), "--jump-port 0 is out of range");
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        assert_eq!(parse_tags(" prod, Team-A ,,"), vec!["prod", "Team-A"]);
+        assert!(parse_tags("").is_empty());
+    }
+
+    #[test]
+    fn test_pluralize() {
+        assert_eq!(pluralize(0, "profile"), "0 profiles");
+        assert_eq!(pluralize(1, "profile"), "1 profile");
+        assert_eq!(pluralize(3, "profile"), "3 profiles");
+    }
+
+    #[test]
+    fn test_dedup_profile_names() {
+        let names: Vec<String> = ["db", "web", "db"].iter().map(|s| s.to_string()).collect();
+
+        assert_eq!(dedup_profile_names(&names), vec!["db", "web"]);
+    }
+
+    #[test]
+    fn test_prompt_confirmed_reprompts_on_mismatch() {
+        let mut answers = ["Str0ng!Passw0rd", "Str0ng!Passw0rd?", "An0ther!Passw0rd", "An0ther!Passw0rd"].into_iter();
+        let mut prompts = Vec::new();
+
+        let password = prompt_confirmed(|message| {
+            prompts.push(message.to_string());
+            Ok(answers.next().unwrap().to_string())
+        })
+        .unwrap();
+
+        assert_eq!(password, "An0ther!Passw0rd");
+        assert_eq!(prompts.len(), 4);
+        assert_eq!(prompts[1], "Confirm master password:");
+    }
+
+    #[test]
+    fn test_restrict_forwards() {
+        let mut forwards: Vec<LocalForward> =
+            ["15432:db:5432", "8080:web:80", "6379:cache:6379"].iter().map(|f| f.parse().unwrap()).collect();
+
+        restrict_forwards("app", &mut forwards, &[6379, 15432]).unwrap();
+
+        assert_eq!(forwards.iter().map(|f| f.local_port).collect::<Vec<_>>(), vec![15432, 6379]);
+        assert_eq!(
+            restrict_forwards("app", &mut forwards, &[8080]).unwrap_err().to_string(),
+            "'app' has no forward on local port 8080 for --only-forward"
+        );
+    }
+
+    #[test]
+    fn test_narrow_forwards_includes_the_primary_forward() {
+        let mut config = sample_config("bastion", "10.0.0.5");
+        config.schema_version = migrate::CURRENT_SCHEMA_VERSION;
+        config.port_forward = Some(5432);
+        config.forwards = vec!["8080:web:80".parse().unwrap()];
+
+        narrow_forwards("app", &mut config, &[5432], false).unwrap();
+
+        assert_eq!(config.port_forward, None);
+        let forwards = config.local_forwards();
+        assert_eq!(forwards.iter().map(|f| f.local_port).collect::<Vec<_>>(), vec![5432]);
+
+        config.port_forward = Some(5432);
+        config.forwards = vec!["8080:web:80".parse().unwrap()];
+        narrow_forwards("app", &mut config, &[8080], false).unwrap();
+        assert_eq!(config.local_forwards().iter().map(|f| f.local_port).collect::<Vec<_>>(), vec![8080]);
+    }
+
+    #[test]
+    fn test_save_password_flags_skip_the_prompt() {
+        let save = |args: &[&str]| SaveArgs::from_iter_safe(std::iter::once("save").chain(args.iter().copied()));
+
+        assert!(save(&["--save-password"]).unwrap().wants_saved_passwords().unwrap());
+        assert!(!save(&["--no-save-password"]).unwrap().wants_saved_passwords().unwrap());
+        assert!(!save(&["--no-save"]).unwrap().wants_saved_passwords().unwrap());
+        assert!(save(&["--save-password", "--no-save-password"]).is_err());
+        assert!(save(&["--save-password", "--no-save"]).is_err());
+    }
+
+    #[test]
+    fn test_saved_credentials_preview() {
+        let mut config = sample_config("bastion", "::1");
+        assert!(!config.has_saved_passwords());
+        config.enc1 = Some("secret1".to_string());
+        config.enc2 = Some("secret2".to_string());
+
+        assert!(config.has_saved_passwords());
+        assert_eq!(
+            saved_credentials_preview(&config, false),
+            "Connecting to deploy@[::1]:2222 via jumper@bastion:22 using saved credentials"
+        );
+        assert_eq!(
+            saved_credentials_preview(&config, true),
+            "Connecting to jumper@bastion:22 using saved credentials"
+        );
+    }
+
+    #[test]
+    fn test_imported_profiles_are_validated() {
+        let profile = r#"{"jump_host_user": "jumper", "jump_host": "bastion", "jump_port": 22,
+                          "target_host_user": "deploy", "target_host": "10.0.0.5", "target_port": 22}"#;
+        assert!(parse_imported_profile(profile, Format::Json).is_ok());
+
+        let err = parse_imported_profile(&profile.replace("\"target_port\": 22", "\"target_port\": 0"), Format::Json)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid profile: target_port 0 is out of range");
+        let err = parse_imported_profile(&profile.replace("\"bastion\"", "\"\""), Format::Json).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid profile: Missing jump host address");
+    }
+
+    #[test]
+    fn test_completion_scripts_list_profiles() {
+        let bash = completion_script(Shell::Bash).unwrap();
+        assert!(bash.contains("rush-tunnel list-profile-names"));
+        assert!(bash.ends_with("complete -F _rush-tunnel_with_profiles -o bashdefault -o default rush-tunnel\n"));
+
+        let zsh = completion_script(Shell::Zsh).unwrap();
+        assert!(zsh.contains("rush-tunnel list-profile-names"));
+        assert!(zsh
+            .lines()
+            .any(|line| line.starts_with("'*--profile=[") && line.ends_with("]:profile:_rush-tunnel_profiles' \\")));
+        let profile_args = zsh.lines().filter(|line| is_zsh_profile_arg(line)).collect::<Vec<_>>();
+        assert_eq!(profile_args.len(), PROFILE_ARG_SUBCOMMANDS.len());
+        assert!(profile_args.iter().all(|line| line.ends_with(":_rush-tunnel_profiles' \\")));
+    }
+
+    #[test]
+    fn test_completion_install_target() {
+        assert!(matches!(detect_shell("/usr/local/bin/zsh"), Some(Shell::Zsh)));
+        assert!(matches!(detect_shell("C:\\Program Files\\PowerShell\\7\\pwsh.exe"), Some(Shell::PowerShell)));
+        assert!(detect_shell("/bin/tcsh").is_none());
+
+        let home = Path::new("/home/me");
+        let (path, setup) = completion_install_target(Shell::Bash, home, None, None);
+        assert_eq!(path, Path::new("/home/me/.local/share/bash-completion/completions/rush-tunnel"));
+        assert!(setup.is_none());
+
+        let (path, _) = completion_install_target(Shell::Fish, home, None, Some(PathBuf::from("/xdg/config")));
+        assert_eq!(path, Path::new("/xdg/config/fish/completions/rush-tunnel.fish"));
+
+        let (path, setup) = completion_install_target(Shell::Zsh, home, None, None);
+        assert_eq!(path, Path::new("/home/me/.zfunc/_rush-tunnel"));
+        assert_eq!(setup.unwrap().1, "fpath=(/home/me/.zfunc $fpath); autoload -Uz compinit && compinit");
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::{bail, Result};
+use tracing::info;
+
+use crate::forward::LocalForward;
+use crate::SshConfig;
+
+// Profiles written before `schema_version` existed deserialize as version 0.
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+// Each step upgrades a profile by exactly one version, so a profile of any age reaches the current
+// schema by running the steps in order, and an up-to-date profile is left alone. Returns whether
+// anything changed, so the caller knows to rewrite the file.
+pub fn migrate(config: &mut SshConfig) -> Result<bool> {
+    let from = config.schema_version;
+    if from > CURRENT_SCHEMA_VERSION {
+        bail!(
+            "schema_version {} is newer than this version of rush-tunnel supports ({}); please upgrade rush-tunnel",
+            from,
+            CURRENT_SCHEMA_VERSION
+        );
+    }
+
+    while config.schema_version < CURRENT_SCHEMA_VERSION {
+        match config.schema_version {
+            0 => fold_legacy_forward(config),
+            version => unreachable!("no migration from schema_version {}", version),
+        }
+        config.schema_version += 1;
+    }
+
+    if from == CURRENT_SCHEMA_VERSION {
+        return Ok(false);
+    }
+    info!(from, to = CURRENT_SCHEMA_VERSION, "migrated profile");
+    Ok(true)
+}
+
+// v1: the single forward entered at creation time, stored as flat `port_forward`, `bind_address`
+// and `forward_remote_*` fields, becomes the first entry of `forwards`.
+pub fn fold_legacy_forward(config: &mut SshConfig) {
+    let bind_address = config.bind_address.take();
+    let remote_host = config.forward_remote_host.take();
+    let remote_port = config.forward_remote_port.take();
+    if let Some(local_port) = config.port_forward.take() {
+        config.forwards.insert(
+            0,
+            LocalForward {
+                bind_address,
+                local_port,
+                remote_host,
+                remote_port: remote_port.unwrap_or(local_port),
+                scheme: None,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V0_PROFILE: &str = include_str!("../tests/fixtures/profile_v0.toml");
+
+    #[test]
+    fn test_migrate_v0_profile() {
+        let mut config: SshConfig = toml::from_str(V0_PROFILE).unwrap();
+        let forwards_before = config.local_forwards();
+        assert_eq!(config.schema_version, 0);
+
+        assert!(migrate(&mut config).unwrap());
+
+        assert_eq!(config.schema_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(config.port_forward, None);
+        assert_eq!(config.bind_address, None);
+        assert_eq!(config.local_forwards(), forwards_before);
+        assert_eq!(config.forwards[0], "127.0.0.1:15432:internal-db:5432".parse::<LocalForward>().unwrap());
+        assert_eq!(config.options.server_alive_interval, Some(30));
+    }
+
+    #[test]
+    fn test_migrate_is_idempotent() {
+        let mut config: SshConfig = toml::from_str(V0_PROFILE).unwrap();
+        migrate(&mut config).unwrap();
+        let migrated = toml::to_string(&config).unwrap();
+
+        assert!(!migrate(&mut config).unwrap());
+        assert_eq!(toml::to_string(&config).unwrap(), migrated);
+
+        let reloaded: SshConfig = toml::from_str(&migrated).unwrap();
+        assert_eq!(reloaded.schema_version, CURRENT_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_migrate_rejects_newer_schema() {
+        let mut config = SshConfig {
+            schema_version: CURRENT_SCHEMA_VERSION + 1,
+            ..Default::default()
+        };
+
+        assert!(migrate(&mut config).is_err());
+    }
+}
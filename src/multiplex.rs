@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use crate::{bracket_host, SshConfig};
+
+// Control sockets live in the profiles directory, named after the connection they multiplex.
+pub const CONTROL_SOCKET_PREFIX: &str = "cm-";
+// How long a master stays open after its last session closes, unless the profile says otherwise.
+pub const DEFAULT_CONTROL_PERSIST: u32 = 600;
+
+pub fn control_path(control_dir: &Path) -> PathBuf {
+    control_dir.join(format!("{}%r@%h:%p", CONTROL_SOCKET_PREFIX))
+}
+
+// The first connection becomes the master and later ones reuse it without authenticating again.
+pub fn ssh_args(config: &SshConfig, control_dir: &Path) -> Vec<String> {
+    vec![
+        "-o".to_string(),
+        "ControlMaster=auto".to_string(),
+        "-o".to_string(),
+        format!("ControlPath={}", control_path(control_dir).display()),
+        "-o".to_string(),
+        format!("ControlPersist={}", config.control_persist.unwrap_or(DEFAULT_CONTROL_PERSIST)),
+    ]
+}
+
+// `ssh -O check` on the target's control socket, which ssh only starts listening on once it has
+// logged in. It never connects by itself, so it fails until then.
+pub fn check_args(config: &SshConfig, control_dir: &Path) -> Vec<String> {
+    vec![
+        "-o".to_string(),
+        format!("ControlPath={}", control_path(control_dir).display()),
+        "-O".to_string(),
+        "check".to_string(),
+        format!("{}@{}", config.target_host_user, bracket_host(&config.target_host)),
+        "-p".to_string(),
+        config.target_port.to_string(),
+    ]
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct SocketCleanup {
+    pub removed: Vec<PathBuf>,
+    pub live: Vec<PathBuf>,
+}
+
+// A socket is stale once no master is listening on it, which happens when ssh was killed instead of
+// exiting. Live ones are left for their masters to remove.
+pub fn clean_stale_sockets(control_dir: &Path) -> Result<SocketCleanup> {
+    let mut cleanup = SocketCleanup::default();
+    if !control_dir.exists() {
+        return Ok(cleanup);
+    }
+    for entry in fs::read_dir(control_dir).context("Failed to read profiles directory")? {
+        let entry = entry.context("Failed to read profiles directory")?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(CONTROL_SOCKET_PREFIX) || !is_socket(&entry.path()) {
+            continue;
+        }
+        let path = entry.path();
+        if is_listening(&path) {
+            cleanup.live.push(path);
+        } else {
+            debug!(path = %path.display(), "removing stale control socket");
+            fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+            cleanup.removed.push(path);
+        }
+    }
+    cleanup.removed.sort();
+    cleanup.live.sort();
+    Ok(cleanup)
+}
+
+#[cfg(unix)]
+fn is_socket(path: &Path) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+}
+
+#[cfg(not(unix))]
+fn is_socket(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn is_listening(path: &Path) -> bool {
+    std::os::unix::net::UnixStream::connect(path).is_ok()
+}
+
+#[cfg(not(unix))]
+fn is_listening(_path: &Path) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_args() {
+        let mut config = SshConfig {
+            multiplex: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            ssh_args(&config, Path::new("/home/me/.rush-tunnel")),
+            vec![
+                "-o",
+                "ControlMaster=auto",
+                "-o",
+                "ControlPath=/home/me/.rush-tunnel/cm-%r@%h:%p",
+                "-o",
+                "ControlPersist=600"
+            ]
+        );
+
+        config.control_persist = Some(60);
+        assert_eq!(ssh_args(&config, Path::new("/tmp")).last().unwrap(), "ControlPersist=60");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_clean_stale_sockets() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = tempfile::tempdir().unwrap();
+        let live = dir.path().join("cm-deploy@db:22");
+        let _listener = UnixListener::bind(&live).unwrap();
+        let stale = dir.path().join("cm-deploy@web:22");
+        drop(UnixListener::bind(&stale).unwrap());
+        fs::write(dir.path().join("cm-notes.toml"), "").unwrap();
+
+        let cleanup = clean_stale_sockets(dir.path()).unwrap();
+
+        assert_eq!(cleanup.removed, vec![stale.clone()]);
+        assert_eq!(cleanup.live, vec![live.clone()]);
+        assert!(!stale.exists());
+        assert!(live.exists());
+        assert!(dir.path().join("cm-notes.toml").exists());
+    }
+}
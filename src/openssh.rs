@@ -0,0 +1,206 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::{bracket_host, get_profiles_dir, multiplex, proxy, SshConfig};
+
+pub fn to_host_block(profile_name: &str, config: &SshConfig) -> String {
+    let mut block = String::new();
+    if let Some(description) = &config.description {
+        for line in description.lines() {
+            block.push_str(format!("# {}", line).trim_end());
+            block.push('\n');
+        }
+    }
+    block.push_str(&format!("Host {}\n", profile_name));
+    block.push_str(&format!("    HostName {}\n", config.target_host));
+    block.push_str(&format!("    User {}\n", config.target_host_user));
+    block.push_str(&format!("    Port {}\n", config.target_port));
+    match (&config.proxy_command, proxy::resolve(config)) {
+        (Some(command), _) => block.push_str(&format!("    ProxyCommand {}\n", command)),
+        (None, Some((pre_proxy, tool))) => block.push_str(&format!(
+            "    ProxyCommand {}\n",
+            proxy::jump_proxy_command(config, Some((&pre_proxy, tool)), &[])
+        )),
+        (None, None) => block.push_str(&format!(
+            "    ProxyJump {}@{}:{}\n",
+            config.jump_host_user,
+            bracket_host(&config.jump_host),
+            config.jump_port
+        )),
+    }
+    for forward in config.local_forwards() {
+        block.push_str(&format!(
+            "    LocalForward {} {}\n",
+            forward.listen_spec(),
+            forward.destination(&config.target_host)
+        ));
+    }
+    if config.agent_forwarding {
+        block.push_str("    ForwardAgent yes\n");
+    }
+    if let Some(command) = &config.local_command {
+        block.push_str("    PermitLocalCommand yes\n");
+        block.push_str(&format!("    LocalCommand {}\n", command));
+    }
+    if let Some(profiles_dir) = get_profiles_dir().ok().filter(|_| config.multiplex) {
+        // Same socket as rush-tunnel's own connections, so either can reuse the other's master.
+        block.push_str("    ControlMaster auto\n");
+        block.push_str(&format!("    ControlPath {}\n", multiplex::control_path(Path::new(&profiles_dir)).display()));
+        block.push_str(&format!(
+            "    ControlPersist {}\n",
+            config.control_persist.unwrap_or(multiplex::DEFAULT_CONTROL_PERSIST)
+        ));
+    }
+    block
+}
+
+pub fn get_user_config_path() -> Result<PathBuf> {
+    let home_dir = dirs::home_dir().context("Failed to get home directory")?;
+    Ok(home_dir.join(".ssh").join("config"))
+}
+
+pub fn append_host_block(block: &str) -> Result<PathBuf> {
+    let config_path = get_user_config_path()?;
+    if let Some(ssh_dir) = config_path.parent() {
+        fs::create_dir_all(ssh_dir).context("Failed to create ~/.ssh directory")?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config_path)
+        .context("Failed to open ssh config file")?;
+    write!(file, "\n{}", block).context("Failed to append to ssh config file")?;
+
+    Ok(config_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_config() -> SshConfig {
+        SshConfig {
+            jump_host_user: "jumper".to_string(),
+            jump_host: "bastion.example.com".to_string(),
+            target_host_user: "deploy".to_string(),
+            target_host: "10.0.0.5".to_string(),
+            jump_port: 2222,
+            target_port: 22,
+            enc1: Some("secret1".to_string()),
+            enc2: Some("secret2".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_host_block_without_forward() {
+        let block = to_host_block("prod", &sample_config());
+
+        assert_eq!(
+            block,
+            "Host prod\n    HostName 10.0.0.5\n    User deploy\n    Port 22\n    ProxyJump jumper@bastion.example.com:2222\n"
+        );
+    }
+
+    #[test]
+    fn test_host_block_with_forward() {
+        let mut config = sample_config();
+        config.port_forward = Some(15432);
+        config.forward_remote_port = Some(5432);
+
+        let block = to_host_block("prod", &config);
+
+        assert!(block.ends_with("    LocalForward 15432 10.0.0.5:5432\n"));
+    }
+
+    #[test]
+    fn test_host_block_with_proxy_command() {
+        let mut config = sample_config();
+        config.jump_host_user = String::new();
+        config.jump_host = String::new();
+        config.proxy_command = Some("cloudflared access ssh --hostname %h".to_string());
+
+        let block = to_host_block("prod", &config);
+
+        assert!(block.contains("    ProxyCommand cloudflared access ssh --hostname %h\n"));
+        assert!(!block.contains("ProxyJump"));
+    }
+
+    #[test]
+    fn test_host_block_with_pre_proxy() {
+        let mut config = sample_config();
+        config.pre_proxy = Some("socks5://127.0.0.1:1080".to_string());
+
+        let block = to_host_block("prod", &config);
+
+        assert!(block.contains(
+            "    ProxyCommand ssh -o 'ProxyCommand=nc -X 5 -x 127.0.0.1:1080 %%h %%p' -p 2222 -W '[%h]:%p' \
+             'jumper@bastion.example.com'\n"
+        ));
+        assert!(!block.contains("ProxyJump"));
+    }
+
+    #[test]
+    fn test_host_block_with_description() {
+        let mut config = sample_config();
+        config.description = Some("prod DB\n\nask Sam before touching".to_string());
+
+        assert!(to_host_block("prod", &config).starts_with("# prod DB\n#\n# ask Sam before touching\nHost prod\n"));
+    }
+
+    #[test]
+    fn test_host_block_with_agent_forwarding() {
+        let mut config = sample_config();
+        config.agent_forwarding = true;
+
+        assert!(to_host_block("prod", &config).ends_with("    ForwardAgent yes\n"));
+    }
+
+    #[test]
+    fn test_host_block_with_local_command() {
+        let mut config = sample_config();
+        config.local_command = Some("open http://localhost:8080".to_string());
+
+        assert!(to_host_block("prod", &config)
+            .ends_with("    PermitLocalCommand yes\n    LocalCommand open http://localhost:8080\n"));
+    }
+
+    #[test]
+    fn test_host_block_with_multiplex() {
+        let mut config = sample_config();
+        config.multiplex = true;
+        config.control_persist = Some(120);
+
+        let block = to_host_block("prod", &config);
+
+        assert!(block.contains("    ControlMaster auto\n"));
+        assert!(block.contains("cm-%r@%h:%p\n"));
+        assert!(block.ends_with("    ControlPersist 120\n"));
+    }
+
+    #[test]
+    fn test_host_block_brackets_ipv6() {
+        let mut config = sample_config();
+        config.jump_host = "2001:db8::1".to_string();
+        config.target_host = "::1".to_string();
+        config.port_forward = Some(8080);
+
+        let block = to_host_block("prod", &config);
+
+        assert!(block.contains("    HostName ::1\n"));
+        assert!(block.contains("    ProxyJump jumper@[2001:db8::1]:2222\n"));
+        assert!(block.contains("    LocalForward 8080 [::1]:8080\n"));
+    }
+
+    #[test]
+    fn test_host_block_never_contains_passwords() {
+        let block = to_host_block("prod", &sample_config());
+
+        assert!(!block.contains("secret1"));
+        assert!(!block.contains("secret2"));
+    }
+}
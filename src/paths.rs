@@ -0,0 +1,101 @@
+use std::env;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+
+// Paths from flags and config files are not seen by a shell, so `~` and `$VAR` are expanded here.
+// Only the current user's home is supported; `~user` is rejected rather than passed through.
+pub fn expand_path(input: &str) -> Result<PathBuf> {
+    expand_path_with(input, |name| env::var(name).ok(), dirs::home_dir)
+}
+
+// For values that aren't paths, such as commands, where a leading `~` means nothing.
+pub fn expand_env(input: &str) -> Result<String> {
+    expand_vars(input, &|name| env::var(name).ok())
+}
+
+fn expand_path_with(
+    input: &str,
+    lookup_var: impl Fn(&str) -> Option<String>,
+    home_dir: impl Fn() -> Option<PathBuf>,
+) -> Result<PathBuf> {
+    let expanded = expand_vars(input, &lookup_var)?;
+
+    if expanded == "~" || expanded.starts_with("~/") {
+        let home = home_dir().context("Failed to get home directory")?;
+        let rest = expanded[1..].trim_start_matches('/');
+        return Ok(if rest.is_empty() { home } else { home.join(rest) });
+    }
+    if expanded.starts_with('~') {
+        bail!("'{}': ~user paths are not supported, use an absolute path", input);
+    }
+    Ok(PathBuf::from(expanded))
+}
+
+fn expand_vars(input: &str, lookup_var: &impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut output = String::new();
+    let mut rest = input;
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, remainder) = match after.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced.find('}').context(format!("'{}': unterminated ${{", input))?;
+                (&braced[..end], &braced[end + 1..])
+            }
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], &after[end..])
+            }
+        };
+        if name.is_empty() {
+            output.push('$');
+        } else {
+            let value = lookup_var(name).context(format!("'{}': environment variable {} is not set", input, name))?;
+            output.push_str(&value);
+        }
+        rest = remainder;
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(input: &str) -> Result<PathBuf> {
+        expand_path_with(
+            input,
+            |name| match name {
+                "HOME" => Some("/home/dev".to_string()),
+                "PROJECT" => Some("infra".to_string()),
+                _ => None,
+            },
+            || Some(PathBuf::from("/home/dev")),
+        )
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        assert_eq!(expand("~").unwrap(), PathBuf::from("/home/dev"));
+        assert_eq!(expand("~/.ssh/id_ed25519").unwrap(), PathBuf::from("/home/dev/.ssh/id_ed25519"));
+        assert_eq!(expand("/etc/~notes").unwrap(), PathBuf::from("/etc/~notes"));
+    }
+
+    #[test]
+    fn test_tilde_user_is_unsupported() {
+        assert!(expand("~root/.ssh").is_err());
+    }
+
+    #[test]
+    fn test_expand_vars() {
+        assert_eq!(expand("$HOME/.rush-tunnel").unwrap(), PathBuf::from("/home/dev/.rush-tunnel"));
+        assert_eq!(expand("/srv/${PROJECT}_keys").unwrap(), PathBuf::from("/srv/infra_keys"));
+        assert_eq!(expand("price$").unwrap(), PathBuf::from("price$"));
+        assert!(expand("$UNSET/keys").is_err());
+        assert!(expand("${HOME").is_err());
+    }
+}
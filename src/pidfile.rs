@@ -0,0 +1,205 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use crate::fsutil;
+
+// Background tunnels leave `<profile>.pid` next to the profile, holding the pid of their ssh and
+// when it started.
+pub const PID_FILE_SUFFIX: &str = ".pid";
+// How long a replaced tunnel gets to exit after SIGTERM before it is killed.
+#[cfg(unix)]
+const STOP_GRACE: std::time::Duration = std::time::Duration::from_secs(5);
+
+pub fn pid_path(profiles_dir: &Path, profile_name: &str) -> PathBuf {
+    profiles_dir.join(format!("{}{}", profile_name, PID_FILE_SUFFIX))
+}
+
+// A tunnel's pid, with when that process started so a later process given the same pid isn't
+// taken for it.
+#[derive(Debug, PartialEq)]
+pub struct TunnelPid {
+    pub pid: u32,
+    started: Option<String>,
+}
+
+impl TunnelPid {
+    // Whether the pid still belongs to the ssh it was recorded for.
+    fn is_ours(&self) -> bool {
+        is_running(self.pid) && start_time(self.pid) == self.started
+    }
+}
+
+pub fn write_pid(profiles_dir: &Path, profile_name: &str, pid: u32) -> Result<()> {
+    debug!(profile = profile_name, pid, "writing pid file");
+    fsutil::create_private_dir(profiles_dir).context("Failed to create profiles directory")?;
+    let path = pid_path(profiles_dir, profile_name);
+    let contents = format!("{}\n{}\n", pid, start_time(pid).unwrap_or_default());
+    fsutil::write_private(&path, contents.as_bytes()).context(format!("Failed to write {}", path.display()))
+}
+
+// A pid file whose process is gone, or whose pid has since been given to another process, was left
+// by a rush-tunnel that was killed, and is removed.
+pub fn running_pid(profiles_dir: &Path, profile_name: &str) -> Result<Option<TunnelPid>> {
+    let path = pid_path(profiles_dir, profile_name);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+    let mut lines = contents.lines();
+    let pid = lines.next().and_then(|pid| pid.trim().parse::<u32>().ok());
+    let started = lines.next().map(str::trim).filter(|started| !started.is_empty()).map(str::to_string);
+    match pid.map(|pid| TunnelPid { pid, started }) {
+        Some(tunnel) if tunnel.is_ours() => Ok(Some(tunnel)),
+        _ => {
+            debug!(path = %path.display(), "removing stale pid file");
+            fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+            Ok(None)
+        }
+    }
+}
+
+// Only removes the file while it still names `pid`, so a tunnel that was replaced doesn't remove the
+// file of the one that replaced it.
+pub fn remove_pid(profiles_dir: &Path, profile_name: &str, pid: u32) -> Result<()> {
+    let path = pid_path(profiles_dir, profile_name);
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Ok(());
+    };
+    if contents.lines().next().map(str::trim) == Some(pid.to_string().as_str()) {
+        fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+// Asks the tunnel to close with SIGTERM, and kills it if it is still there after a grace period.
+// Nothing is signalled once the pid no longer belongs to the tunnel's ssh.
+#[cfg(unix)]
+pub fn stop(tunnel: &TunnelPid) -> Result<()> {
+    debug!(pid = tunnel.pid, "stopping tunnel");
+    if !tunnel.is_ours() || !signal(tunnel.pid, libc::SIGTERM) {
+        return Ok(());
+    }
+    let deadline = std::time::Instant::now() + STOP_GRACE;
+    while std::time::Instant::now() < deadline {
+        if !tunnel.is_ours() {
+            return Ok(());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    if tunnel.is_ours() && signal(tunnel.pid, libc::SIGKILL) {
+        debug!(pid = tunnel.pid, "tunnel ignored SIGTERM; killed it");
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn stop(tunnel: &TunnelPid) -> Result<()> {
+    if !tunnel.is_ours() {
+        return Ok(());
+    }
+    let status = std::process::Command::new("taskkill")
+        .args(["/PID", &tunnel.pid.to_string(), "/F"])
+        .status()
+        .context("Failed to run taskkill")?;
+    if !status.success() && is_running(tunnel.pid) {
+        anyhow::bail!("taskkill could not stop process {}", tunnel.pid);
+    }
+    Ok(())
+}
+
+// Passes a signal rush-tunnel received on to a background tunnel's ssh.
+#[cfg(unix)]
+pub fn pass_on(pid: u32, signal_number: i32) -> bool {
+    signal(pid, signal_number)
+}
+
+// Ctrl-C already reaches every process on the console, and there is nothing else to pass on.
+#[cfg(not(unix))]
+pub fn pass_on(_pid: u32, _signal_number: i32) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn signal(pid: u32, signal: i32) -> bool {
+    // SAFETY: kill has no memory effects; a pid outside pid_t is rejected rather than wrapped.
+    libc::pid_t::try_from(pid).is_ok_and(|pid| pid > 0 && unsafe { libc::kill(pid, signal) } == 0)
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    signal(pid, 0)
+}
+
+// The start time in clock ticks since boot, field 22 of /proc/<pid>/stat. The command name before
+// it is in parentheses and may hold spaces, so fields are counted from the closing one.
+#[cfg(target_os = "linux")]
+fn start_time(pid: u32) -> Option<String> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19).map(str::to_string)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn start_time(pid: u32) -> Option<String> {
+    let output = std::process::Command::new("ps").args(["-o", "lstart=", "-p", &pid.to_string()]).output().ok()?;
+    let started = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !started.is_empty()).then_some(started)
+}
+
+// tasklist has no start time to compare, so only the pid is checked there.
+#[cfg(not(unix))]
+fn start_time(_pid: u32) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+fn is_running(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_running_pid() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(running_pid(dir.path(), "db").unwrap(), None);
+
+        write_pid(dir.path(), "db", std::process::id()).unwrap();
+        assert_eq!(running_pid(dir.path(), "db").unwrap().map(|tunnel| tunnel.pid), Some(std::process::id()));
+
+        // Someone else's pid is left alone.
+        remove_pid(dir.path(), "db", std::process::id() + 1).unwrap();
+        assert!(pid_path(dir.path(), "db").exists());
+        remove_pid(dir.path(), "db", std::process::id()).unwrap();
+        assert!(!pid_path(dir.path(), "db").exists());
+
+        fs::write(pid_path(dir.path(), "web"), "not a pid\n").unwrap();
+        assert_eq!(running_pid(dir.path(), "web").unwrap(), None);
+        assert!(!pid_path(dir.path(), "web").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reused_pid_is_not_ours() {
+        let dir = tempfile::tempdir().unwrap();
+        // Our pid, as if it had been given to us after a tunnel that started at another time.
+        fs::write(pid_path(dir.path(), "db"), format!("{}\n1\n", std::process::id())).unwrap();
+
+        assert_eq!(running_pid(dir.path(), "db").unwrap(), None);
+        assert!(!pid_path(dir.path(), "db").exists());
+        let tunnel = TunnelPid {
+            pid: std::process::id(),
+            started: Some("1".to_string()),
+        };
+        // Would have sent us SIGTERM if it didn't check.
+        stop(&tunnel).unwrap();
+    }
+}
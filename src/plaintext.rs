@@ -0,0 +1,78 @@
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::{style, SshConfig};
+
+// For CI fixtures and local testing only: passwords saved with `--insecure-plaintext` are base64,
+// not encrypted, so anyone who can read the profile has them. Saving or using them takes this
+// variable set to exactly `I_UNDERSTAND`.
+pub const OVERRIDE_ENV: &str = "RUSH_TUNNEL_INSECURE_PLAINTEXT";
+const OVERRIDE_VALUE: &str = "I_UNDERSTAND";
+// Encrypted passwords are hex, which never contains a colon, so the two can't be confused.
+const PREFIX: &str = "insecure-plaintext:";
+
+pub fn is_allowed() -> bool {
+    is_override(std::env::var(OVERRIDE_ENV).ok().as_deref())
+}
+
+fn is_override(value: Option<&str>) -> bool {
+    value == Some(OVERRIDE_VALUE)
+}
+
+pub fn require_allowed(what: &str) -> Result<()> {
+    if !is_allowed() {
+        bail!("{} stores passwords unencrypted; set {}={} to allow it", what, OVERRIDE_ENV, OVERRIDE_VALUE);
+    }
+    eprintln!(
+        "{}",
+        style::warning(&format!(
+            "WARNING: {} stores passwords unencrypted (base64), so anyone who can read the profile can read \
+             them; use it for testing only",
+            what
+        ))
+    );
+    Ok(())
+}
+
+pub fn encode(password: &str) -> String {
+    format!("{}{}", PREFIX, STANDARD.encode(password))
+}
+
+pub fn decode(stored: &str) -> Result<String> {
+    let encoded = stored.strip_prefix(PREFIX).context("not an insecure plaintext password")?;
+    let bytes = STANDARD.decode(encoded).context("insecure plaintext password is not valid base64")?;
+    String::from_utf8(bytes).context("insecure plaintext password is not valid UTF-8")
+}
+
+pub fn is_plaintext(stored: &str) -> bool {
+    stored.starts_with(PREFIX)
+}
+
+pub fn has_plaintext_passwords(config: &SshConfig) -> bool {
+    [&config.enc1, &config.enc2].into_iter().flatten().any(|stored| is_plaintext(stored))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let stored = encode("s3cret: with spaces");
+
+        assert_eq!(stored, "insecure-plaintext:czNjcmV0OiB3aXRoIHNwYWNlcw==");
+        assert!(is_plaintext(&stored));
+        assert_eq!(decode(&stored).unwrap(), "s3cret: with spaces");
+        assert!(decode("insecure-plaintext:not base64!").is_err());
+        assert!(!is_plaintext("76313b636861636861"));
+    }
+
+    #[test]
+    fn test_override_must_be_exact() {
+        assert!(is_override(Some("I_UNDERSTAND")));
+        for value in [None, Some(""), Some("1"), Some("i_understand")] {
+            assert!(!is_override(value), "{:?}", value);
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{bracket_host, find_executable, is_valid_port, SshConfig};
+
+pub const NETCAT_BINARY: &str = "nc";
+pub const CORKSCREW_BINARY: &str = "corkscrew";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProxyKind {
+    Http,
+    Socks4,
+    Socks5,
+}
+
+// The proxy in front of the jump host, written as `http://host:port`, `socks4://host:port` or
+// `socks5://host:port`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreProxy {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProxyTool {
+    // OpenBSD netcat, whose -X and -x options speak to HTTP and SOCKS proxies.
+    Netcat,
+    // Only speaks HTTP CONNECT.
+    Corkscrew,
+}
+
+impl FromStr for PreProxy {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<PreProxy> {
+        let (scheme, address) = spec
+            .split_once("://")
+            .context(format!("invalid pre_proxy '{}' (expected http://, socks4:// or socks5://host:port)", spec))?;
+        let kind = match scheme.to_lowercase().as_str() {
+            "http" => ProxyKind::Http,
+            "socks4" => ProxyKind::Socks4,
+            "socks5" | "socks" => ProxyKind::Socks5,
+            _ => bail!("invalid pre_proxy '{}': unknown proxy type '{}' (expected http, socks4 or socks5)", spec, scheme),
+        };
+        let address = address.trim_end_matches('/');
+        let (host, port) = match address.strip_prefix('[') {
+            Some(bracketed) => bracketed
+                .split_once("]:")
+                .context(format!("invalid pre_proxy '{}': missing port", spec))?,
+            None => address
+                .rsplit_once(':')
+                .context(format!("invalid pre_proxy '{}': missing port", spec))?,
+        };
+        if host.is_empty() {
+            bail!("invalid pre_proxy '{}': host is empty", spec);
+        }
+        let port = port
+            .parse::<u16>()
+            .ok()
+            .filter(|port| is_valid_port(*port))
+            .context(format!("invalid pre_proxy '{}': bad port '{}'", spec, port))?;
+        Ok(PreProxy {
+            kind,
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for PreProxy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scheme = match self.kind {
+            ProxyKind::Http => "http",
+            ProxyKind::Socks4 => "socks4",
+            ProxyKind::Socks5 => "socks5",
+        };
+        write!(f, "{}://{}:{}", scheme, bracket_host(&self.host), self.port)
+    }
+}
+
+impl PreProxy {
+    // netcat is preferred, as it handles every proxy type; corkscrew is the fallback for HTTP proxies.
+    pub fn find_tool(&self) -> Result<ProxyTool> {
+        if find_executable(NETCAT_BINARY).is_some() {
+            return Ok(ProxyTool::Netcat);
+        }
+        if self.kind == ProxyKind::Http && find_executable(CORKSCREW_BINARY).is_some() {
+            return Ok(ProxyTool::Corkscrew);
+        }
+        match self.kind {
+            ProxyKind::Http => bail!(
+                "pre_proxy needs {} (OpenBSD netcat, netcat-openbsd on Debian and Ubuntu) or {} on PATH",
+                NETCAT_BINARY,
+                CORKSCREW_BINARY
+            ),
+            _ => bail!(
+                "pre_proxy needs {} (OpenBSD netcat, netcat-openbsd on Debian and Ubuntu) on PATH for SOCKS proxies",
+                NETCAT_BINARY
+            ),
+        }
+    }
+
+    // A ProxyCommand for ssh that connects to `%h:%p` through the proxy.
+    pub fn command(&self, tool: ProxyTool) -> String {
+        let proxy = format!("{}:{}", bracket_host(&self.host), self.port);
+        match (tool, self.kind) {
+            (ProxyTool::Corkscrew, _) => format!("{} {} {} %h %p", CORKSCREW_BINARY, self.host, self.port),
+            (ProxyTool::Netcat, ProxyKind::Http) => format!("{} -X connect -x {} %h %p", NETCAT_BINARY, proxy),
+            (ProxyTool::Netcat, ProxyKind::Socks4) => format!("{} -X 4 -x {} %h %p", NETCAT_BINARY, proxy),
+            (ProxyTool::Netcat, ProxyKind::Socks5) => format!("{} -X 5 -x {} %h %p", NETCAT_BINARY, proxy),
+        }
+    }
+}
+
+// `-J` starts its own ssh for the jump host, which no `-o` option reaches, so with a pre_proxy, or
+// `hop_options` the jump host needs as well, the hop is spelled out as a ProxyCommand instead. The
+// outer ssh expands `%h:%p` to the target and `%%` to `%`, leaving `%h %p` for the inner ssh to
+// expand to the jump host.
+pub fn jump_proxy_command(config: &SshConfig, proxy: Option<(&PreProxy, ProxyTool)>, hop_options: &[String]) -> String {
+    let mut command = "ssh".to_string();
+    if let Some((proxy, tool)) = proxy {
+        let option = format!("ProxyCommand={}", proxy.command(tool).replace('%', "%%"));
+        command.push_str(&format!(" -o {}", shell_quote(&option)));
+    }
+    for option in hop_options {
+        command.push_str(&format!(" -o {}", shell_quote(option)));
+    }
+    format!(
+        "{} -p {} -W '[%h]:%p' {}",
+        command,
+        config.jump_port,
+        shell_quote(&format!("{}@{}", config.jump_host_user, config.jump_host))
+    )
+}
+
+// The profile's pre_proxy, with the tool to speak to it. netcat is assumed when neither tool is
+// installed, so the command can still be shown; connecting reports the missing tool.
+pub fn resolve(config: &SshConfig) -> Option<(PreProxy, ProxyTool)> {
+    let proxy = config.pre_proxy.as_deref()?.parse::<PreProxy>().ok()?;
+    let tool = proxy.find_tool().unwrap_or(ProxyTool::Netcat);
+    Some((proxy, tool))
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pre_proxy() {
+        let proxy: PreProxy = "http://proxy.corp:3128".parse().unwrap();
+        assert_eq!(proxy.kind, ProxyKind::Http);
+        assert_eq!(proxy.host, "proxy.corp");
+        assert_eq!(proxy.port, 3128);
+
+        let proxy: PreProxy = "SOCKS5://[::1]:1080/".parse().unwrap();
+        assert_eq!((proxy.kind, proxy.host.as_str(), proxy.port), (ProxyKind::Socks5, "::1", 1080));
+        assert_eq!(proxy.to_string(), "socks5://[::1]:1080");
+
+        for spec in ["proxy.corp:3128", "ftp://proxy:21", "http://proxy", "http://:3128", "socks4://proxy:0"] {
+            assert!(spec.parse::<PreProxy>().is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_proxy_commands() {
+        let http: PreProxy = "http://proxy.corp:3128".parse().unwrap();
+        assert_eq!(http.command(ProxyTool::Netcat), "nc -X connect -x proxy.corp:3128 %h %p");
+        assert_eq!(http.command(ProxyTool::Corkscrew), "corkscrew proxy.corp 3128 %h %p");
+
+        let socks: PreProxy = "socks4://10.0.0.1:1080".parse().unwrap();
+        assert_eq!(socks.command(ProxyTool::Netcat), "nc -X 4 -x 10.0.0.1:1080 %h %p");
+    }
+
+    #[test]
+    fn test_jump_proxy_command() {
+        let config = SshConfig::builder()
+            .jump_host_user("jumper")
+            .jump_host("bastion")
+            .jump_port(2200)
+            .target_host_user("deploy")
+            .target_host("10.0.0.5")
+            .build()
+            .unwrap();
+        let proxy: PreProxy = "socks5://127.0.0.1:1080".parse().unwrap();
+
+        assert_eq!(
+            jump_proxy_command(&config, Some((&proxy, ProxyTool::Netcat)), &[]),
+            "ssh -o 'ProxyCommand=nc -X 5 -x 127.0.0.1:1080 %%h %%p' -p 2200 -W '[%h]:%p' 'jumper@bastion'"
+        );
+        assert_eq!(
+            jump_proxy_command(&config, None, &["NumberOfPasswordPrompts=1".to_string()]),
+            "ssh -o 'NumberOfPasswordPrompts=1' -p 2200 -W '[%h]:%p' 'jumper@bastion'"
+        );
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
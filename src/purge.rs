@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{classify_profiles_dir_entry, ProfilesDirEntry};
+
+// A mistyped --profiles-dir could point at a home or project directory, so only a dot-directory
+// like the default ~/.rush-tunnel may be purged.
+pub fn check_purge_target(profiles_dir: &Path) -> Result<()> {
+    let name = profiles_dir.file_name().map(|name| name.to_string_lossy().to_string());
+    match name {
+        Some(name) if name.len() > 1 && name.starts_with('.') => Ok(()),
+        _ => bail!(
+            "Refusing to purge {}: the profiles directory is expected to be a dot-directory such as ~/.rush-tunnel",
+            profiles_dir.display()
+        ),
+    }
+}
+
+// Only files rush-tunnel writes itself (profiles, the global config and state files) are listed.
+// Directories, symlinks and anything else in the directory are left alone.
+pub fn purgeable_files(profiles_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(profiles_dir).context("Failed to read profiles directory")? {
+        let entry = entry.context("Failed to read profiles directory")?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        match classify_profiles_dir_entry(&file_name, false) {
+            ProfilesDirEntry::Profile(_) | ProfilesDirEntry::Internal => files.push(entry.path()),
+            ProfilesDirEntry::Skipped(_) => {}
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Returns whether the directory itself was removed, which only happens once it is empty.
+pub fn purge(profiles_dir: &Path, files: &[PathBuf]) -> Result<bool> {
+    for file in files {
+        fs::remove_file(file).context(format!("Failed to remove {}", file.display()))?;
+    }
+    Ok(fs::remove_dir(profiles_dir).is_ok())
+}
+
+pub fn remove_profiles(profiles_dir: &Path, profiles: &[String]) -> Result<()> {
+    for profile in profiles {
+        let path = profiles_dir.join(format!("{}.toml", profile));
+        fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_purge_target() {
+        assert!(check_purge_target(Path::new("/home/me/.rush-tunnel")).is_ok());
+        assert!(check_purge_target(Path::new("/home/me/.rush-tunnel/")).is_ok());
+        assert!(check_purge_target(Path::new("/home/me")).is_err());
+        assert!(check_purge_target(Path::new("/home/me/.rush-tunnel/..")).is_err());
+        assert!(check_purge_target(Path::new("/")).is_err());
+    }
+
+    #[test]
+    fn test_purge_leaves_unknown_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles_dir = dir.path().join(".rush-tunnel");
+        fs::create_dir(&profiles_dir).unwrap();
+        for name in ["prod.toml", "config.toml", "last", "notes.txt"] {
+            fs::write(profiles_dir.join(name), "x").unwrap();
+        }
+        fs::create_dir(profiles_dir.join("keys.toml")).unwrap();
+
+        let files = purgeable_files(&profiles_dir).unwrap();
+        let names: Vec<_> = files.iter().map(|file| file.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, vec!["config.toml", "last", "prod.toml"]);
+
+        assert!(!purge(&profiles_dir, &files).unwrap());
+        assert!(profiles_dir.join("notes.txt").exists());
+        assert!(profiles_dir.join("keys.toml").is_dir());
+        assert!(!profiles_dir.join("prod.toml").exists());
+    }
+
+    #[test]
+    fn test_remove_profiles_keeps_the_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["staging-db.toml", "staging-web.toml", "prod.toml", "config.toml"] {
+            fs::write(dir.path().join(name), "x").unwrap();
+        }
+
+        remove_profiles(dir.path(), &["staging-db".to_string(), "staging-web".to_string()]).unwrap();
+
+        let mut left: Vec<_> = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        left.sort();
+        assert_eq!(left, vec!["config.toml", "prod.toml"]);
+    }
+
+    #[test]
+    fn test_purge_removes_empty_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let profiles_dir = dir.path().join(".rush-tunnel");
+        fs::create_dir(&profiles_dir).unwrap();
+        fs::write(profiles_dir.join("prod.toml"), "x").unwrap();
+
+        let files = purgeable_files(&profiles_dir).unwrap();
+
+        assert!(purge(&profiles_dir, &files).unwrap());
+        assert!(!profiles_dir.exists());
+        assert!(dir.path().exists());
+    }
+}
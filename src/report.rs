@@ -0,0 +1,88 @@
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<ReportFormat> {
+        match value.to_lowercase().as_str() {
+            "json" => Ok(ReportFormat::Json),
+            _ => bail!("unknown report format '{}' (expected json)", value),
+        }
+    }
+}
+
+// How one connection ended, for scripts that wrap `connect`. Timestamps are Unix seconds.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SessionReport {
+    pub profile: String,
+    // `None` when ssh never ran, or was stopped by a signal (including the session timeout).
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub started_at: u64,
+    pub ended_at: u64,
+    pub duration_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SessionReport {
+    pub fn new(profile: &str, started: SystemTime, ended: SystemTime) -> SessionReport {
+        SessionReport {
+            profile: profile.to_string(),
+            exit_code: None,
+            timed_out: false,
+            started_at: unix_secs(started),
+            ended_at: unix_secs(ended),
+            duration_secs: ended.duration_since(started).unwrap_or_default().as_secs_f64(),
+            error: None,
+        }
+    }
+
+    // A single line, so callers can pick the report out as the last line of stderr.
+    pub fn render(&self, format: ReportFormat) -> Result<String> {
+        match format {
+            ReportFormat::Json => serde_json::to_string(self).context("Failed to serialize session report"),
+        }
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO).as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_json() {
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut report = SessionReport::new("prod", started, started + Duration::from_millis(2500));
+        report.exit_code = Some(255);
+
+        let json: serde_json::Value = serde_json::from_str(&report.render(ReportFormat::Json).unwrap()).unwrap();
+
+        assert_eq!(json["profile"], "prod");
+        assert_eq!(json["exit_code"], 255);
+        assert_eq!(json["timed_out"], false);
+        assert_eq!(json["started_at"], 1_700_000_000);
+        assert_eq!(json["ended_at"], 1_700_000_002);
+        assert_eq!(json["duration_secs"], 2.5);
+        assert!(json.get("error").is_none());
+    }
+
+    #[test]
+    fn test_report_format_from_str() {
+        assert_eq!("JSON".parse::<ReportFormat>().unwrap(), ReportFormat::Json);
+        assert!("yaml".parse::<ReportFormat>().is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::{bail, Context, Result};
+use glob::{MatchOptions, Pattern};
+
+// `*`, `?` and `[...]` make a name a pattern; anything else names exactly one profile.
+pub fn is_pattern(name: &str) -> bool {
+    name.contains(['*', '?', '['])
+}
+
+// Profile names are file names, so `*` matches any character, dots and slashes included.
+pub fn match_profiles(pattern: &str, profiles: &[String]) -> Result<Vec<String>> {
+    let compiled = Pattern::new(pattern).context(format!("Invalid profile pattern '{}'", pattern))?;
+    let options = MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: false,
+        require_literal_leading_dot: false,
+    };
+    Ok(profiles.iter().filter(|profile| compiled.matches_with(profile, options)).cloned().collect())
+}
+
+// Plain names are passed through, so a missing profile is still reported by whatever loads it. A
+// pattern must match at least one profile. Each profile is listed once, in the order first named.
+pub fn expand(names: &[String], profiles: &[String]) -> Result<Vec<String>> {
+    let mut expanded: Vec<String> = Vec::new();
+    for name in names {
+        let matches = if is_pattern(name) {
+            let matches = match_profiles(name, profiles)?;
+            if matches.is_empty() {
+                bail!("No profiles match '{}'", name);
+            }
+            matches
+        } else {
+            vec![name.clone()]
+        };
+        for profile in matches {
+            if !expanded.contains(&profile) {
+                expanded.push(profile);
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiles() -> Vec<String> {
+        ["prod-1", "prod-2", "prod-eu", "staging-db", "staging-web"].iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_match_profiles() {
+        assert_eq!(match_profiles("*", &profiles()).unwrap(), profiles());
+        assert_eq!(match_profiles("prod-?", &profiles()).unwrap(), vec!["prod-1", "prod-2"]);
+        assert_eq!(match_profiles("staging-*", &profiles()).unwrap(), vec!["staging-db", "staging-web"]);
+        assert!(match_profiles("dev-*", &profiles()).unwrap().is_empty());
+        assert!(match_profiles("prod-[", &profiles()).is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            expand(&names(&["staging-web", "staging-*", "missing"]), &profiles()).unwrap(),
+            vec!["staging-web", "staging-db", "missing"]
+        );
+        assert_eq!(
+            expand(&names(&["dev-*"]), &profiles()).unwrap_err().to_string(),
+            "No profiles match 'dev-*'"
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use crate::fsutil;
+use crate::get_profiles_dir;
+
+pub const DEFAULT_PROFILE: &str = "default";
+pub const LAST_PROFILE: &str = "last";
+// The local_command each profile was last allowed to run, so an edited command is asked about again.
+pub const APPROVED_LOCAL_COMMANDS: &str = "approved_local_commands";
+// When each profile last connected successfully, in Unix seconds, for `profiles --sort last-used`.
+pub const LAST_USED: &str = "last_used";
+// When each profile was first saved, in Unix seconds, for `profiles --sort created`. The profile
+// file's own creation time changes whenever it is saved again.
+pub const CREATED: &str = "created";
+
+fn state_path(name: &str) -> Result<PathBuf> {
+    let profiles_dir = get_profiles_dir()?;
+    Ok(Path::new(&profiles_dir).join(name))
+}
+
+pub fn read_state(name: &str) -> Result<Option<String>> {
+    let path = state_path(name)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let value = fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+    let value = value.trim();
+    Ok(if value.is_empty() { None } else { Some(value.to_string()) })
+}
+
+pub fn write_state(name: &str, value: &str) -> Result<()> {
+    debug!(state = name, value, "writing state");
+    let profiles_dir = get_profiles_dir()?;
+    fsutil::create_private_dir(Path::new(&profiles_dir)).context("Failed to create profiles directory")?;
+    let path = state_path(name)?;
+    fsutil::write_private(&path, format!("{}\n", value).as_bytes())
+        .context(format!("Failed to write {}", path.display()))
+}
+
+pub fn is_local_command_approved(profile_name: &str, command: &str) -> Result<bool> {
+    Ok(approved_local_commands()?.get(profile_name).is_some_and(|approved| approved == command))
+}
+
+pub fn approve_local_command(profile_name: &str, command: &str) -> Result<()> {
+    let mut approved = approved_local_commands()?;
+    approved.insert(profile_name.to_string(), command.to_string());
+    let json = serde_json::to_string(&approved).context("Failed to serialize approved local commands")?;
+    write_state(APPROVED_LOCAL_COMMANDS, &json)
+}
+
+fn approved_local_commands() -> Result<BTreeMap<String, String>> {
+    match read_state(APPROVED_LOCAL_COMMANDS)? {
+        Some(json) => serde_json::from_str(&json).context(format!("Failed to parse {}", APPROVED_LOCAL_COMMANDS)),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+pub fn record_last_used(profile_name: &str, at: SystemTime) -> Result<()> {
+    record_time(LAST_USED, profile_name, at)
+}
+
+pub fn last_used() -> Result<BTreeMap<String, u64>> {
+    times(LAST_USED)
+}
+
+pub fn record_created(profile_name: &str, at: SystemTime) -> Result<()> {
+    record_time(CREATED, profile_name, at)
+}
+
+pub fn created() -> Result<BTreeMap<String, u64>> {
+    times(CREATED)
+}
+
+fn record_time(name: &str, profile_name: &str, at: SystemTime) -> Result<()> {
+    let mut times = times(name)?;
+    let secs = at.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or_default();
+    times.insert(profile_name.to_string(), secs);
+    let json = serde_json::to_string(&times).context(format!("Failed to serialize {}", name))?;
+    write_state(name, &json)
+}
+
+fn times(name: &str) -> Result<BTreeMap<String, u64>> {
+    match read_state(name)? {
+        Some(json) => serde_json::from_str(&json).context(format!("Failed to parse {}", name)),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+pub fn clear_state(name: &str) -> Result<bool> {
+    debug!(state = name, "clearing state");
+    let path = state_path(name)?;
+    if !path.exists() {
+        return Ok(false);
+    }
+
+    fs::remove_file(&path).context(format!("Failed to remove {}", path.display()))?;
+    Ok(true)
+}
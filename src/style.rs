@@ -0,0 +1,89 @@
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use owo_colors::OwoColorize;
+
+static COLOR_DISABLED: AtomicBool = AtomicBool::new(false);
+
+// Colors only ever wrap the text, never change it, so output read by scripts is the same with
+// `--no-color` as without.
+pub fn disable_color() {
+    COLOR_DISABLED.store(true, Ordering::Relaxed);
+}
+
+// Honours https://no-color.org, and leaves output that is piped or redirected uncolored.
+pub fn stdout_color() -> bool {
+    color_allowed() && io::stdout().is_terminal()
+}
+
+pub fn stderr_color() -> bool {
+    color_allowed() && io::stderr().is_terminal()
+}
+
+fn color_allowed() -> bool {
+    !COLOR_DISABLED.load(Ordering::Relaxed) && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+// One `  Label:   value` line of the banner and `show`, with labels padded to a common width.
+pub fn field(label: &str, value: &str) -> String {
+    let label = format!("  {:<18}", label);
+    if stdout_color() {
+        format!("{}{}", label.dimmed(), value.bold())
+    } else {
+        format!("{}{}", label, value)
+    }
+}
+
+pub fn heading(text: &str) -> String {
+    if stdout_color() {
+        text.bold().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn highlight(text: &str) -> String {
+    if stdout_color() {
+        text.cyan().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn dim(text: &str) -> String {
+    if stdout_color() {
+        text.dimmed().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn warning(text: &str) -> String {
+    if stderr_color() {
+        text.yellow().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn error(text: &str) -> String {
+    if stderr_color() {
+        text.red().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_keeps_plain_text_layout() {
+        disable_color();
+
+        assert_eq!(field("Jump Host:", "j@b:22"), "  Jump Host:        j@b:22");
+        assert_eq!(field("Agent forwarding:", "no"), "  Agent forwarding: no");
+        assert_eq!(warning("Warning: x"), "Warning: x");
+    }
+}
@@ -0,0 +1,251 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Result};
+
+use crate::{bracket_host, SshConfig};
+
+// Longer cells are cut short so one long host or forward list can't push the other columns away.
+pub const MAX_CELL_WIDTH: usize = 40;
+const COLUMN_GAP: &str = "  ";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListFormat {
+    Plain,
+    Table,
+}
+
+impl FromStr for ListFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<ListFormat> {
+        match value.to_lowercase().as_str() {
+            "plain" => Ok(ListFormat::Plain),
+            "table" => Ok(ListFormat::Table),
+            _ => bail!("unknown list format '{}' (expected plain or table)", value),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    Name,
+    Jump,
+    Target,
+    Forward,
+    Tags,
+}
+
+pub const DEFAULT_COLUMNS: &[Column] = &[Column::Name, Column::Jump, Column::Target, Column::Forward];
+
+impl FromStr for Column {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Column> {
+        match value.trim().to_lowercase().as_str() {
+            "name" => Ok(Column::Name),
+            "jump" => Ok(Column::Jump),
+            "target" => Ok(Column::Target),
+            "forward" | "forwards" => Ok(Column::Forward),
+            "tags" => Ok(Column::Tags),
+            _ => bail!("unknown column '{}' (expected name, jump, target, forward or tags)", value),
+        }
+    }
+}
+
+impl Column {
+    pub fn header(&self) -> &'static str {
+        match self {
+            Column::Name => "NAME",
+            Column::Jump => "JUMP HOST",
+            Column::Target => "TARGET HOST",
+            Column::Forward => "FORWARD",
+            Column::Tags => "TAGS",
+        }
+    }
+
+    pub fn value(&self, profile_name: &str, config: &SshConfig) -> String {
+        match self {
+            Column::Name => profile_name.to_string(),
+            Column::Jump if config.proxy_command.is_some() => "(proxy command)".to_string(),
+            Column::Jump => {
+                format!("{}@{}:{}", config.jump_host_user, bracket_host(&config.jump_host), config.jump_port)
+            }
+            Column::Target => format!(
+                "{}@{}:{}",
+                config.target_host_user,
+                bracket_host(&config.target_host),
+                config.target_port
+            ),
+            Column::Forward => config
+                .local_forwards()
+                .iter()
+                .map(|forward| format!("{} -> {}", forward.listen_spec(), forward.destination(&config.target_host)))
+                .collect::<Vec<_>>()
+                .join(", "),
+            Column::Tags => config.tags.join(", "),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProfileSort {
+    Name,
+    Created,
+    Updated,
+    LastUsed,
+}
+
+impl FromStr for ProfileSort {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<ProfileSort> {
+        match value.to_lowercase().as_str() {
+            "name" => Ok(ProfileSort::Name),
+            "created" => Ok(ProfileSort::Created),
+            "updated" => Ok(ProfileSort::Updated),
+            "last-used" => Ok(ProfileSort::LastUsed),
+            _ => bail!("unknown sort '{}' (expected name, created, updated or last-used)", value),
+        }
+    }
+}
+
+// `profiles` comes in sorted by name, which is kept for any ties. Times sort newest first, and a
+// profile without one (never connected to, or on a filesystem that doesn't record creation times)
+// comes after the rest. Created is when the profile was first saved, falling back to the file's
+// creation time for one saved before that was recorded; updated is the file's modification time.
+pub fn sort_profiles(
+    profiles: &mut [String],
+    sort: ProfileSort,
+    profiles_dir: &Path,
+    created: &BTreeMap<String, u64>,
+    last_used: &BTreeMap<String, u64>,
+) {
+    let file_time = |profile: &str, created: bool| {
+        let metadata = fs::metadata(profiles_dir.join(format!("{}.toml", profile))).ok()?;
+        let time = if created { metadata.created() } else { metadata.modified() }.ok()?;
+        time.duration_since(UNIX_EPOCH).ok().map(|since| since.as_secs())
+    };
+    match sort {
+        ProfileSort::Name => {}
+        ProfileSort::Created => profiles.sort_by_cached_key(|profile| {
+            Reverse(created.get(profile).copied().or_else(|| file_time(profile, true)))
+        }),
+        ProfileSort::Updated => profiles.sort_by_cached_key(|profile| Reverse(file_time(profile, false))),
+        ProfileSort::LastUsed => profiles.sort_by_key(|profile| Reverse(last_used.get(profile).copied())),
+    }
+}
+
+// Left-aligned columns sized to their widest cell, with no trailing spaces on any line.
+pub fn render(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let rows: Vec<Vec<String>> = rows.iter().map(|row| row.iter().map(|cell| truncate(cell)).collect()).collect();
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            rows.iter()
+                .filter_map(|row| row.get(i))
+                .map(|cell| cell.chars().count())
+                .fold(header.chars().count(), usize::max)
+        })
+        .collect();
+
+    let headers: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+    let mut output = String::new();
+    for row in std::iter::once(&headers).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        output.push_str(line.join(COLUMN_GAP).trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+fn truncate(cell: &str) -> String {
+    if cell.chars().count() <= MAX_CELL_WIDTH {
+        return cell.to_string();
+    }
+    let kept: String = cell.chars().take(MAX_CELL_WIDTH - 1).collect();
+    format!("{}…", kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsutil;
+
+    #[test]
+    fn test_render_aligns_columns() {
+        let rows = vec![
+            vec!["db".to_string(), "j@bastion:22".to_string(), String::new()],
+            vec!["web-frontend".to_string(), "j@b:22".to_string(), "8080 -> web:80".to_string()],
+        ];
+
+        assert_eq!(
+            render(&["NAME", "JUMP HOST", "FORWARD"], &rows),
+            "NAME          JUMP HOST     FORWARD\n\
+             db            j@bastion:22\n\
+             web-frontend  j@b:22        8080 -> web:80\n"
+        );
+    }
+
+    #[test]
+    fn test_long_cells_are_truncated() {
+        let long = "x".repeat(MAX_CELL_WIDTH + 10);
+
+        let table = render(&["NAME"], &[vec![long]]);
+
+        let cell = table.lines().nth(1).unwrap();
+        assert_eq!(cell.chars().count(), MAX_CELL_WIDTH);
+        assert!(cell.ends_with('…'));
+    }
+
+    #[test]
+    fn test_parse_columns() {
+        assert_eq!("Forwards".parse::<Column>().unwrap(), Column::Forward);
+        assert!("password".parse::<Column>().is_err());
+        assert_eq!("table".parse::<ListFormat>().unwrap(), ListFormat::Table);
+        assert!("json".parse::<ListFormat>().is_err());
+        assert_eq!("last-used".parse::<ProfileSort>().unwrap(), ProfileSort::LastUsed);
+        assert!("size".parse::<ProfileSort>().is_err());
+    }
+
+    #[test]
+    fn test_sort_profiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let names = ["alpha", "beta", "gamma"];
+        for (age, name) in names.iter().enumerate() {
+            let path = dir.path().join(format!("{}.toml", name));
+            fs::write(&path, "").unwrap();
+            let modified = std::time::SystemTime::now() - std::time::Duration::from_secs(60 * (3 - age as u64));
+            fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        }
+        let sorted = |sort, times: &BTreeMap<String, u64>| {
+            let mut profiles: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+            sort_profiles(&mut profiles, sort, dir.path(), times, times);
+            profiles
+        };
+
+        assert_eq!(sorted(ProfileSort::Name, &BTreeMap::new()), ["alpha", "beta", "gamma"]);
+        assert_eq!(sorted(ProfileSort::Updated, &BTreeMap::new()), ["gamma", "beta", "alpha"]);
+        let last_used = BTreeMap::from([("alpha".to_string(), 200), ("gamma".to_string(), 100)]);
+        assert_eq!(sorted(ProfileSort::LastUsed, &last_used), ["alpha", "gamma", "beta"]);
+
+        // Saving a profile again replaces its file, which doesn't change when it was created.
+        let created = BTreeMap::from([
+            ("alpha".to_string(), 100),
+            ("beta".to_string(), 200),
+            ("gamma".to_string(), 300),
+        ]);
+        assert_eq!(sorted(ProfileSort::Created, &created), ["gamma", "beta", "alpha"]);
+        fsutil::write_private(&dir.path().join("alpha.toml"), b"").unwrap();
+        assert_eq!(sorted(ProfileSort::Created, &created), ["gamma", "beta", "alpha"]);
+    }
+}
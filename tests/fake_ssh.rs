@@ -0,0 +1,360 @@
+#![cfg(unix)]
+
+// End-to-end runs of `establish_tunnel` against a fake ssh that records its arguments and exits
+// with a chosen status, so argument building and exit codes are covered without a network.
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use rush_tunnel::askpass::Credentials;
+use rush_tunnel::config::{SshOptions, SSH_BINARY_ENV};
+use rush_tunnel::{establish_tunnel, set_profiles_dir, spawn_tunnel, SshConfig, TunnelOptions};
+use zeroize::Zeroizing;
+
+// The fake is found through an environment variable, which every test in this binary shares.
+static SSH_BINARY_LOCK: Mutex<()> = Mutex::new(());
+
+struct FakeSsh {
+    dir: tempfile::TempDir,
+    _guard: MutexGuard<'static, ()>,
+}
+
+impl FakeSsh {
+    fn install(exit_code: i32) -> FakeSsh {
+        FakeSsh::install_script(&format!("exit {}", exit_code))
+    }
+
+    // `body` runs after the arguments are recorded, with `$DIR` set to a directory it can keep state in.
+    fn install_script(body: &str) -> FakeSsh {
+        let guard = SSH_BINARY_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Holds no global config, so none from the developer's machine leaks into the args.
+        set_profiles_dir(env::temp_dir().join("rush-tunnel-fake-ssh-no-profiles"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("ssh");
+        fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\nDIR='{}'\nprintf '%s\\n' \"$@\" > \"$DIR/args\"\necho run >> \"$DIR/runs\"\n{}\n",
+                dir.path().display(),
+                body
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        env::set_var(SSH_BINARY_ENV, &script);
+
+        FakeSsh { dir, _guard: guard }
+    }
+
+    fn runs(&self) -> usize {
+        fs::read_to_string(self.dir.path().join("runs")).map(|runs| runs.lines().count()).unwrap_or(0)
+    }
+
+    fn args_path(&self) -> PathBuf {
+        self.dir.path().join("args")
+    }
+
+    fn received_args(&self) -> Vec<String> {
+        fs::read_to_string(self.args_path()).unwrap().lines().map(str::to_string).collect()
+    }
+}
+
+fn quiet() -> TunnelOptions {
+    TunnelOptions {
+        quiet: true,
+        ..Default::default()
+    }
+}
+
+fn builder() -> rush_tunnel::SshConfigBuilder {
+    SshConfig::builder()
+        .jump_host_user("jumper")
+        .jump_host("bastion.example.com")
+        .target_host_user("deploy")
+        .target_host("10.0.0.5")
+}
+
+#[tokio::test]
+async fn test_tunnel_without_forward() {
+    let ssh = FakeSsh::install(0);
+    let config = builder().jump_port(2200).build().unwrap();
+
+    let outcome = establish_tunnel(&config, &mut quiet()).await.unwrap();
+
+    assert!(outcome.status.success());
+    assert_eq!(
+        ssh.received_args(),
+        vec!["-J", "jumper@bastion.example.com:2200", "deploy@10.0.0.5", "-p", "22"]
+    );
+}
+
+#[tokio::test]
+async fn test_tunnel_with_port_forward() {
+    let ssh = FakeSsh::install(0);
+    let config = builder().port_forward(15432).forward_remote_port(5432).build().unwrap();
+
+    let outcome = establish_tunnel(&config, &mut quiet()).await.unwrap();
+
+    assert!(outcome.status.success());
+    assert_eq!(
+        ssh.received_args(),
+        vec![
+            "-J",
+            "jumper@bastion.example.com:22",
+            "deploy@10.0.0.5",
+            "-p",
+            "22",
+            "-L",
+            "15432:10.0.0.5:5432",
+            "-o",
+            "ExitOnForwardFailure=yes"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_exec_is_passed_as_one_argument() {
+    let ssh = FakeSsh::install(0);
+    let config = builder().build().unwrap();
+    let mut options = TunnelOptions {
+        exec: Some("uptime && df -h".to_string()),
+        ..quiet()
+    };
+
+    establish_tunnel(&config, &mut options).await.unwrap();
+
+    assert_eq!(ssh.received_args().last().unwrap(), "uptime && df -h");
+}
+
+#[tokio::test]
+async fn test_non_zero_exit_is_propagated() {
+    for code in [1, 255] {
+        let _ssh = FakeSsh::install(code);
+        let config = builder().build().unwrap();
+
+        let outcome = establish_tunnel(&config, &mut quiet()).await.unwrap();
+
+        assert_eq!(outcome.status.code(), Some(code));
+    }
+}
+
+#[tokio::test]
+async fn test_resolve_failures_are_retried() {
+    // Fails to resolve the first time only.
+    let ssh = FakeSsh::install_script(
+        "if [ \"$(wc -l < \"$DIR/runs\")\" -eq 1 ]; then\n\
+         echo 'ssh: Could not resolve hostname bastion.example.com: Temporary failure in name resolution' >&2\n\
+         exit 255\nfi\nexit 0",
+    );
+    let config = builder().build().unwrap();
+    let mut options = TunnelOptions { dns_retries: 2, ..quiet() };
+
+    let outcome = establish_tunnel(&config, &mut options).await.unwrap();
+
+    assert!(outcome.status.success());
+    assert_eq!(ssh.runs(), 2);
+}
+
+#[tokio::test]
+async fn test_resolve_retries_run_out() {
+    let ssh = FakeSsh::install_script(
+        "echo 'ssh: Could not resolve hostname bastion.example.com: Name or service not known' >&2\nexit 255",
+    );
+    let config = builder().build().unwrap();
+    let mut options = TunnelOptions { dns_retries: 1, ..quiet() };
+
+    let outcome = establish_tunnel(&config, &mut options).await.unwrap();
+
+    assert_eq!(outcome.status.code(), Some(255));
+    assert_eq!(ssh.runs(), 2);
+}
+
+#[tokio::test]
+async fn test_auth_failures_are_not_retried() {
+    let ssh =
+        FakeSsh::install_script("echo 'jumper@bastion.example.com: Permission denied (publickey).' >&2\nexit 255");
+    let config = builder().build().unwrap();
+    let mut options = TunnelOptions { dns_retries: 3, ..quiet() };
+
+    let outcome = establish_tunnel(&config, &mut options).await.unwrap();
+
+    assert_eq!(outcome.status.code(), Some(255));
+    assert_eq!(ssh.runs(), 1);
+}
+
+#[tokio::test]
+async fn test_key_passphrase_is_dropped_once_ssh_has_started() {
+    // Records the passphrase askpass would answer with, and fails to resolve the first time only.
+    let ssh = FakeSsh::install_script(
+        "echo \"$RUSH_TUNNEL_ASKPASS_KEY_PASSPHRASE\" >> \"$DIR/passphrases\"\n\
+         if [ \"$(wc -l < \"$DIR/runs\")\" -eq 1 ]; then\n\
+         echo 'ssh: Could not resolve hostname bastion.example.com: Name or service not known' >&2\n\
+         exit 255\nfi\nexit 0",
+    );
+    let identity = SshOptions {
+        identity_file: Some("/home/deploy/.ssh/id_ed25519".to_string()),
+        ..Default::default()
+    };
+    let config = builder().options(identity).build().unwrap();
+    let credentials = || Credentials {
+        jump_password: String::new(),
+        target_password: String::new(),
+        key_passphrase: Some(Zeroizing::new("key-pw".to_string())),
+    };
+
+    let mut options = TunnelOptions {
+        dns_retries: 1,
+        credentials: Some(credentials()),
+        ..quiet()
+    };
+    let outcome = establish_tunnel(&config, &mut options).await.unwrap();
+
+    // The retry was still answered.
+    assert!(outcome.status.success());
+    assert_eq!(fs::read_to_string(ssh.dir.path().join("passphrases")).unwrap(), "key-pw\nkey-pw\n");
+    assert!(options.credentials.is_none());
+
+    let mut options = TunnelOptions {
+        forward_only: true,
+        credentials: Some(credentials()),
+        ..quiet()
+    };
+    let mut tunnel = spawn_tunnel(&config, &mut options).unwrap();
+    assert!(options.credentials.is_none());
+    assert!(tunnel.child.wait().unwrap().success());
+}
+
+#[tokio::test]
+async fn test_missing_ssh_binary_is_an_error() {
+    let ssh = FakeSsh::install(0);
+    env::set_var(SSH_BINARY_ENV, ssh.dir.path().join("no-such-ssh"));
+    let config = builder().build().unwrap();
+
+    let err = establish_tunnel(&config, &mut quiet()).await.unwrap_err();
+
+    assert!(err.to_string().contains("not found"));
+    assert!(!ssh.args_path().exists());
+}
+
+// Stands in for a multiplexed ssh: `-O check` only succeeds once the session has logged in.
+fn install_multiplexed(login: &str) -> FakeSsh {
+    FakeSsh::install_script(&format!(
+        "if [ \"$1\" = -o ] && [ \"$3\" = -O ]; then\n[ -e \"$DIR/master\" ]; exit $?\nfi\n{}",
+        login
+    ))
+}
+
+fn hooks(builder: rush_tunnel::SshConfigBuilder, dir: &str) -> SshConfig {
+    builder
+        .on_connect(format!("echo \"up $RUSH_TARGET_HOST\" >> '{}/hooks'; exit 3", dir))
+        .on_disconnect(format!("echo \"down $RUSH_JUMP_HOST\" >> '{}/hooks'", dir))
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_hooks_run_around_the_tunnel() {
+    let fake = install_multiplexed("sleep 1\ntouch \"$DIR/master\"\nsleep 1");
+    let config = hooks(builder().multiplex(true), &fake.dir.path().display().to_string());
+
+    let outcome = establish_tunnel(&config, &mut quiet()).await.unwrap();
+
+    // The failing on_connect is only reported.
+    assert!(outcome.status.success());
+    assert_eq!(
+        fs::read_to_string(fake.dir.path().join("hooks")).unwrap(),
+        "up 10.0.0.5\ndown bastion.example.com\n"
+    );
+}
+
+#[test]
+fn test_hooks_run_around_a_background_tunnel() {
+    let fake = install_multiplexed("sleep 1\ntouch \"$DIR/master\"\nsleep 1");
+    let config = hooks(builder().multiplex(true), &fake.dir.path().display().to_string());
+    let mut options = TunnelOptions {
+        forward_only: true,
+        ..quiet()
+    };
+
+    let mut tunnel = spawn_tunnel(&config, &mut options).unwrap();
+    assert!(tunnel.child.wait().unwrap().success());
+    tunnel.finish();
+
+    assert_eq!(
+        fs::read_to_string(fake.dir.path().join("hooks")).unwrap(),
+        "up 10.0.0.5\ndown bastion.example.com\n"
+    );
+}
+
+#[tokio::test]
+async fn test_hooks_dont_run_when_login_fails() {
+    let fake = install_multiplexed("sleep 1\necho 'deploy@10.0.0.5: Permission denied (password).' >&2\nexit 255");
+    let config = hooks(builder().multiplex(true), &fake.dir.path().display().to_string());
+
+    let outcome = establish_tunnel(&config, &mut quiet()).await.unwrap();
+
+    assert_eq!(outcome.status.code(), Some(255));
+    assert!(!fake.dir.path().join("hooks").exists());
+}
+
+#[tokio::test]
+async fn test_hooks_need_a_forward_or_multiplex() {
+    let fake = FakeSsh::install(0);
+    let dir = fake.dir.path().display().to_string();
+    assert!(builder().on_connect("true").build().is_err());
+    let mut config = hooks(builder().port_forward(15432), &dir);
+    config.port_forward = None;
+
+    let err = establish_tunnel(&config, &mut quiet()).await.unwrap_err();
+
+    assert!(err.to_string().contains("need a forward, or multiplex"));
+    assert!(!fake.args_path().exists());
+}
+
+#[tokio::test]
+async fn test_sigterm_is_passed_on_to_ssh() {
+    let fake = FakeSsh::install_script("trap 'echo TERM > \"$DIR/signal\"; exit 255' TERM\nsleep 10 &\nwait");
+    let config = builder().build().unwrap();
+    let runs = fake.dir.path().join("runs");
+    let signaller = std::thread::spawn(move || {
+        while !runs.exists() {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        Command::new("kill").args(["-TERM", &std::process::id().to_string()]).status().unwrap();
+    });
+
+    let outcome = establish_tunnel(&config, &mut quiet()).await.unwrap();
+    signaller.join().unwrap();
+
+    assert_eq!(outcome.interrupted_by, Some(15));
+    assert_eq!(outcome.status.code(), Some(255));
+    assert_eq!(fs::read_to_string(fake.dir.path().join("signal")).unwrap(), "TERM\n");
+}
+
+// Resizes only reach a remote pty if ssh has the terminal itself and gets the terminal's SIGWINCH,
+// so it must share our stdin and stdout, and our process group.
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_ssh_shares_our_terminal() {
+    let fake = FakeSsh::install_script(
+        "stdin=$(readlink /proc/$$/fd/0)\nstdout=$(readlink /proc/$$/fd/1)\n\
+         echo \"$stdin\" > \"$DIR/stdin\"\necho \"$stdout\" > \"$DIR/stdout\"\n\
+         cut -d ' ' -f 5 /proc/$$/stat > \"$DIR/pgid\"",
+    );
+    let config = builder().build().unwrap();
+
+    establish_tunnel(&config, &mut quiet()).await.unwrap();
+
+    let recorded = |name: &str| fs::read_to_string(fake.dir.path().join(name)).unwrap().trim().to_string();
+    let ours = |fd: &str| fs::read_link(format!("/proc/self/fd/{}", fd)).unwrap().display().to_string();
+    assert_eq!(recorded("stdin"), ours("0"));
+    assert_eq!(recorded("stdout"), ours("1"));
+    // SAFETY: getpgrp only reads the calling process's group.
+    assert_eq!(recorded("pgid"), unsafe { libc::getpgrp() }.to_string());
+}